@@ -0,0 +1,2 @@
+-- Add source-level debug info (address ranges to source lines) to assembly_jobs
+ALTER TABLE assembly_jobs ADD COLUMN IF NOT EXISTS debug_info JSONB;
@@ -0,0 +1,200 @@
+use serde::Serialize;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::debug_info::DebugInfo;
+use crate::diagnostics::Diagnostic;
+use crate::encoding::Encoding;
+use crate::image::MemoryImage;
+use crate::lint;
+use crate::pass1::Pass1;
+use crate::pass_trace::PassTrace;
+use crate::pass2::{BlockInfo, Pass2, MAX_TEXT_RECORD_LEN};
+use crate::source::SourceFormat;
+use crate::tables::{TableEntry, TableOrder};
+
+/// Settings shared by every way of running the assembler (CLI, server).
+#[derive(Debug, Clone)]
+pub struct AssemblyOptions {
+    pub source_format: SourceFormat,
+    pub strict: bool,
+    pub lint: bool,
+    pub table_order: TableOrder,
+    pub include_paths: Vec<String>,
+    pub max_record_length: usize,
+    /// Explain how each instruction was encoded
+    pub explain: bool,
+    /// Record what each pass did with each statement
+    pub trace: bool,
+}
+
+impl Default for AssemblyOptions {
+    fn default() -> Self {
+        Self {
+            source_format: SourceFormat::default(),
+            strict: false,
+            lint: false,
+            table_order: TableOrder::default(),
+            include_paths: Vec::new(),
+            max_record_length: MAX_TEXT_RECORD_LEN,
+            explain: false,
+            trace: false,
+        }
+    }
+}
+
+/// Everything one run of both passes produces.
+#[derive(Debug, Clone, Serialize)]
+pub struct Assembly {
+    pub intermediate: String,
+    pub listing: String,
+    pub symb_table: String,
+    pub lit_table: String,
+    pub object_program: String,
+    pub symbols: Vec<TableEntry>,
+    pub literals: Vec<TableEntry>,
+    pub blocks: Vec<BlockInfo>,
+    pub diagnostics: Vec<Diagnostic>,
+    /// The object code as bytes, for the binary, Intel HEX and S-record
+    /// exports
+    #[serde(skip)]
+    pub image: MemoryImage,
+    /// Which source statement each address came from
+    pub debug_info: DebugInfo,
+    /// How each instruction's object code was built; empty unless
+    /// `AssemblyOptions::explain` is set
+    pub encodings: Vec<Encoding>,
+    /// Set when `AssemblyOptions::trace` is
+    pub trace: Option<PassTrace>,
+}
+
+#[derive(Debug)]
+pub enum AssembleError {
+    /// The source or one of its includes could not be read
+    Source(String),
+    /// Pass 1 rejected the program; `diagnostics` holds the errors and any
+    /// warnings found before it stopped
+    Pass1 { message: String, diagnostics: Vec<Diagnostic> },
+    /// Pass 2 failed; `diagnostics` holds the errors it found, such as
+    /// unknown instructions or operands that do not fit, with any warnings
+    /// from both passes. Empty when the intermediate files could not be
+    /// processed at all.
+    Pass2 { message: String, diagnostics: Vec<Diagnostic> },
+    /// The output files could not be written or read back
+    Output(String),
+}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AssembleError::Source(msg) => write!(f, "Failed to process source file: {}", msg),
+            AssembleError::Pass1 { message, .. } => write!(f, "Assembly Error (Pass 1): {}", message),
+            AssembleError::Pass2 { message, .. } => write!(f, "Assembly Error (Pass 2): {}", message),
+            AssembleError::Output(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for AssembleError {}
+
+/// Runs Pass 1 and Pass 2 over `input_path`, writing the intermediate files
+/// into `output_dir` (which must exist) and returning their contents.
+pub fn assemble_file(input_path: &str, output_dir: &str, options: &AssemblyOptions) -> Result<Assembly, AssembleError> {
+    assemble(read_source(input_path, options)?, output_dir, options)
+}
+
+/// Reads `input_path` and its includes into a `Pass1` set up from `options`,
+/// for callers that look at the statements before assembling them.
+pub fn read_source(input_path: &str, options: &AssemblyOptions) -> Result<Pass1, AssembleError> {
+    let mut pass1 = Pass1::new();
+    pass1.source_format = options.source_format;
+    pass1.strict = options.strict;
+    pass1.table_order = options.table_order;
+    pass1.include_paths = options.include_paths.clone();
+    pass1.trace = options.trace;
+
+    pass1.process_file(input_path)
+        .map_err(|e| AssembleError::Source(e.to_string()))?;
+    Ok(pass1)
+}
+
+/// Assembles source already read by [`read_source`], as `assemble_file`
+/// does.
+pub fn assemble(mut pass1: Pass1, output_dir: &str, options: &AssemblyOptions) -> Result<Assembly, AssembleError> {
+    if let Err(message) = pass1.pass1_generator(output_dir) {
+        return Err(AssembleError::Pass1 { message, diagnostics: pass1.diagnostics });
+    }
+    if options.lint {
+        let warnings = lint::lint(&pass1);
+        pass1.diagnostics.extend(warnings);
+    }
+
+    let intermediate_path = format!("{}/intermediate.txt", output_dir);
+    let symbol_path = format!("{}/symbTable.txt", output_dir);
+    let literal_path = format!("{}/litTable.txt", output_dir);
+    let object_path = format!("{}/objectProgram.txt", output_dir);
+
+    let mut pass2 = Pass2::new();
+    pass2.max_text_record_len = options.max_record_length;
+    pass2.sources = pass1.statement_sources.clone();
+    pass2.columns = pass1.statement_columns.clone();
+    pass2.explain = options.explain;
+    pass2.trace = options.trace;
+    pass2.pass2_generator(&intermediate_path, &symbol_path, &literal_path, &object_path)
+        .map_err(|e| AssembleError::Pass2 { message: e.to_string(), diagnostics: Vec::new() })?;
+
+    let read = |path: &str, what: &str| {
+        fs::read_to_string(path).map_err(|e| AssembleError::Output(format!("Failed to read {}: {}", what, e)))
+    };
+
+    let debug_info = pass2.debug_info();
+    let mut diagnostics = pass1.diagnostics;
+    diagnostics.append(&mut pass2.diagnostics);
+    let errors: Vec<String> = diagnostics.iter().filter(|d| d.is_error()).map(ToString::to_string).collect();
+    if !errors.is_empty() {
+        return Err(AssembleError::Pass2 { message: errors.join("\n"), diagnostics });
+    }
+    Ok(Assembly {
+        intermediate: read(&intermediate_path, "intermediate file")?,
+        listing: pass2.generate_listing(),
+        symb_table: read(&symbol_path, "symbol table file")?,
+        lit_table: read(&literal_path, "literal table file")?,
+        object_program: read(&object_path, "object program file")?,
+        symbols: pass1.symbol_entries,
+        literals: pass1.literal_entries,
+        blocks: pass2.block_table,
+        diagnostics,
+        image: pass2.image,
+        debug_info,
+        encodings: pass2.encodings,
+        trace: options.trace.then_some(PassTrace { pass1: pass1.trace_steps, pass2: pass2.trace_steps }),
+    })
+}
+
+/// Files `input_path` reads: itself first, then everything reached through
+/// `INCLUDE`/`COPY`. Stops at the first include that cannot be read, so the
+/// list is still useful for a program that does not assemble.
+pub fn source_files(input_path: &str, options: &AssemblyOptions) -> Vec<PathBuf> {
+    let mut pass1 = Pass1::new();
+    pass1.source_format = options.source_format;
+    pass1.include_paths = options.include_paths.clone();
+    let _ = pass1.process_file(input_path);
+    pass1.files
+}
+
+/// Assembles `source` in a scratch directory, for tests.
+#[cfg(test)]
+pub(crate) fn assemble_text(source: &str, options: &AssemblyOptions) -> Result<Assembly, AssembleError> {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+    let dir = std::env::temp_dir().join(format!("sicxe-test-{}-{}", std::process::id(), NEXT.fetch_add(1, Ordering::Relaxed)));
+    fs::create_dir_all(&dir).map_err(|e| AssembleError::Source(e.to_string()))?;
+    let path = dir.join("test.asm");
+    let result = fs::write(&path, source)
+        .map_err(|e| AssembleError::Source(e.to_string()))
+        .and_then(|_| assemble_file(&path.to_string_lossy(), &dir.to_string_lossy(), options));
+    let _ = fs::remove_dir_all(&dir);
+    result
+}
@@ -40,9 +40,7 @@ impl FromRequest for AuthenticatedUser {
                 return Err(ErrorUnauthorized("Missing or invalid authorization header"));
             };
 
-            if auth_header.starts_with("Bearer ") {
-                let token = &auth_header[7..]; // remove "Bearer " prefix
-
+            if let Some(token) = auth_header.strip_prefix("Bearer ") {
                 let jwt_secret = std::env::var("JWT_SECRET").expect("JWT_SECRET must be set");
 
                 match decode_token(token, &jwt_secret) {
@@ -0,0 +1,14 @@
+use std::io;
+use std::process::ExitCode;
+
+// a Debug Adapter Protocol server on stdin/stdout, for editors such as VS Code
+fn main() -> ExitCode {
+    let stdin = io::stdin();
+    match systems_project::dap::serve(stdin.lock(), io::stdout().lock()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,14 @@
+use std::io;
+use std::process::ExitCode;
+
+// a Language Server Protocol server on stdin/stdout, for any LSP-capable editor
+fn main() -> ExitCode {
+    let stdin = io::stdin();
+    match systems_project::lsp::serve(stdin.lock(), io::stdout().lock()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -4,10 +4,9 @@ use actix_governor::{Governor, GovernorConfigBuilder, KeyExtractor};
 use actix_web::dev::ServiceRequest;
 use sqlx::postgres::PgPoolOptions;
 use tracing::info;
-use tracing_subscriber;
 use utoipa::OpenApi;
 use utoipa_swagger_ui::SwaggerUi;
-use systems_project::handlers::{register, login, me, delete_account, logout, google_auth, google_callback, github_auth, github_callback, assemble, get_history, get_job, delete_job, delete_all_jobs, ApiDoc};
+use systems_project::handlers::{register, login, me, delete_account, logout, google_auth, google_callback, github_auth, github_callback, assemble, format, validate, get_history, get_job, get_job_image, delete_job, delete_all_jobs, ApiDoc};
 use systems_project::error::AppError;
 
 // simple IP-based key extractor for rate limiting
@@ -117,9 +116,12 @@ async fn main() -> std::io::Result<()> {
             .route("/auth/github", web::get().to(github_auth))
             .route("/auth/github/callback", web::get().to(github_callback))
             .route("/assemble", web::post().to(assemble))
+            .route("/format", web::post().to(format))
+            .route("/validate", web::post().to(validate))
             .route("/history", web::get().to(get_history))
             .route("/history", web::delete().to(delete_all_jobs))
             .route("/history/{id}", web::get().to(get_job))
+            .route("/history/{id}/image", web::get().to(get_job_image))
             .route("/history/{id}", web::delete().to(delete_job));
 
         // apply rate limiting to auth endpoints
@@ -13,6 +13,21 @@ pub fn get_register_value(reg: &str) -> u8 {
     }
 }
 
+pub fn get_register_name(value: u8) -> Option<&'static str> {
+    match value {
+        0 => Some("A"),
+        1 => Some("X"),
+        2 => Some("L"),
+        3 => Some("B"),
+        4 => Some("S"),
+        5 => Some("T"),
+        6 => Some("F"),
+        8 => Some("PC"),
+        9 => Some("SW"),
+        _ => None,
+    }
+}
+
 pub fn string_to_hex(s: &str) -> String {
     let mut result = String::new();
     for c in s.chars() {
@@ -0,0 +1,567 @@
+use base64::Engine;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+use crate::assembler::{self, AssembleError, Assembly, AssemblyOptions};
+use crate::conversions::get_register_name;
+use crate::debug_info::SourceRange;
+use crate::debugger::{Debugger, Stop};
+use crate::devices::Device;
+use crate::framing::{self, read_message};
+use crate::emulator::{Halt, Machine, MEMORY_SIZE, RETURN_ADDRESS};
+use crate::object_program::ObjectProgram;
+
+/// The only thread a SIC/XE machine has.
+const THREAD_ID: i64 = 1;
+const REGISTERS_REFERENCE: i64 = 1;
+const LABELS_REFERENCE: i64 = 2;
+/// Most instructions one `disassemble` request returns or skips over.
+const MAX_DISASSEMBLY: usize = 4096;
+
+/// Serves Debug Adapter Protocol requests read from `input` until the client
+/// disconnects or `input` ends. Programs are launched from SIC/XE source,
+/// which is assembled first so addresses can be mapped to source lines.
+pub fn serve(mut input: impl BufRead, output: impl Write) -> io::Result<()> {
+    let mut session = Session::new(output);
+    while let Some(message) = read_message(&mut input)? {
+        if message["type"] == "request" && !session.handle(&message)? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+// a launched program with what is needed to map it back to source
+struct Program {
+    debugger: Debugger,
+    /// Directory of the main source file; source locations are relative to it
+    root: PathBuf,
+    /// First address of each source line's code, by file and line
+    lines: BTreeMap<(PathBuf, usize), usize>,
+    stop_on_entry: bool,
+    /// Breakpoints set by `setBreakpoints`, per source file
+    source_breakpoints: HashMap<PathBuf, Vec<usize>>,
+    instruction_breakpoints: Vec<usize>,
+    /// Device output already sent as `output` events
+    shown: HashMap<u8, usize>,
+}
+
+struct Session<W: Write> {
+    output: W,
+    seq: i64,
+    program: Option<Program>,
+    events: Vec<(&'static str, Value)>,
+}
+
+impl<W: Write> Session<W> {
+    fn new(output: W) -> Self {
+        Self { output, seq: 1, program: None, events: Vec::new() }
+    }
+
+    fn send(&mut self, mut message: Value) -> io::Result<()> {
+        message["seq"] = json!(self.seq);
+        self.seq += 1;
+        framing::write_message(&mut self.output, &message)
+    }
+
+    // answers one request, then sends the events it raised; `false` once
+    // the client disconnects
+    fn handle(&mut self, request: &Value) -> io::Result<bool> {
+        let command = request["command"].as_str().unwrap_or_default().to_string();
+        let arguments = &request["arguments"];
+        let result = match command.as_str() {
+            "initialize" => Ok(capabilities()),
+            "launch" => self.launch(arguments),
+            "setBreakpoints" => self.set_breakpoints(arguments),
+            "setInstructionBreakpoints" => self.set_instruction_breakpoints(arguments),
+            "setExceptionBreakpoints" => Ok(json!({ "breakpoints": [] })),
+            "configurationDone" => self.configuration_done(),
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "SIC/XE" }] })),
+            "stackTrace" => self.stack_trace(),
+            "scopes" => Ok(json!({ "scopes": [
+                { "name": "Registers", "variablesReference": REGISTERS_REFERENCE, "expensive": false },
+                { "name": "Labels", "variablesReference": LABELS_REFERENCE, "expensive": false },
+            ] })),
+            "variables" => self.variables(arguments),
+            "evaluate" => self.evaluate(arguments),
+            "continue" => self.execute(|debugger| debugger.resume()).map(|_| json!({ "allThreadsContinued": true })),
+            "next" => self.execute(|debugger| debugger.step_over()),
+            "stepIn" => self.execute(|debugger| debugger.step()),
+            "stepOut" => self.execute(|debugger| {
+                // the return address is in L until the routine saves it
+                let l = debugger.machine.registers.l;
+                if l == RETURN_ADDRESS { debugger.resume() } else { debugger.run_to(l as usize) }
+            }),
+            "pause" => {
+                // requests are handled one at a time, so the program is never running here
+                self.events.push(("stopped", json!({ "reason": "pause", "threadId": THREAD_ID })));
+                Ok(Value::Null)
+            }
+            "disassemble" => self.disassemble(arguments),
+            "readMemory" => self.read_memory(arguments),
+            "disconnect" | "terminate" => Ok(Value::Null),
+            _ => Err(format!("Unsupported request '{}'", command)),
+        };
+
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": command,
+            "success": result.is_ok(),
+        });
+        match result {
+            Ok(Value::Null) => {}
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = json!(message),
+        }
+        self.send(response)?;
+
+        for (event, body) in std::mem::take(&mut self.events) {
+            self.send(json!({ "type": "event", "event": event, "body": body }))?;
+        }
+        Ok(!matches!(command.as_str(), "disconnect" | "terminate"))
+    }
+
+    fn program(&mut self) -> Result<&mut Program, String> {
+        self.program.as_mut().ok_or_else(|| "No program has been launched".to_string())
+    }
+
+    // arguments: `program` (source path), `stopOnEntry`, and `inputs` /
+    // `outputs` mapping hex device numbers to files
+    fn launch(&mut self, arguments: &Value) -> Result<Value, String> {
+        let path = arguments["program"].as_str().ok_or("launch needs a 'program' source file")?;
+        let (program, assembly) = assemble(path)?;
+
+        let mut machine = Machine::new();
+        for (key, input) in [("inputs", true), ("outputs", false)] {
+            for (number, file) in arguments[key].as_object().into_iter().flatten() {
+                let number = u8::from_str_radix(number, 16).map_err(|_| format!("Invalid device number '{}'", number))?;
+                let file = file.as_str().ok_or_else(|| format!("Device {:02X} needs a file name", number))?;
+                let device = if input { Device::input_file(file) } else { Device::output_file(file) };
+                machine.devices.attach(number, device.map_err(|e| format!("{}: {}", file, e))?);
+            }
+        }
+        machine.load(&program)?;
+
+        let root = Path::new(path).parent().map(Path::to_path_buf).unwrap_or_default();
+        let mut lines = BTreeMap::new();
+        for range in &assembly.debug_info.ranges {
+            lines.entry((canonical(&root.join(&range.file)), range.line)).or_insert(range.start);
+        }
+
+        let mut debugger = Debugger::new(machine);
+        debugger.symbols = assembly.symbols.into_iter().map(|entry| (entry.name, entry.address)).collect();
+        debugger.debug_info = assembly.debug_info;
+        self.program = Some(Program {
+            debugger,
+            root,
+            lines,
+            stop_on_entry: arguments["stopOnEntry"].as_bool().unwrap_or(false),
+            source_breakpoints: HashMap::new(),
+            instruction_breakpoints: Vec::new(),
+            shown: HashMap::new(),
+        });
+        self.events.push(("initialized", json!({})));
+        Ok(Value::Null)
+    }
+
+    // a line without code gets the next line in the file that has some
+    fn set_breakpoints(&mut self, arguments: &Value) -> Result<Value, String> {
+        let program = self.program()?;
+        let path = canonical(Path::new(arguments["source"]["path"].as_str().unwrap_or_default()));
+        let mut addresses = Vec::new();
+        let mut breakpoints = Vec::new();
+        for requested in arguments["breakpoints"].as_array().into_iter().flatten() {
+            let line = requested["line"].as_u64().unwrap_or(0) as usize;
+            let found = program.lines.range((path.clone(), line)..(path.clone(), usize::MAX)).next();
+            match found {
+                Some((&(_, line), &address)) => {
+                    addresses.push(address);
+                    breakpoints.push(json!({ "verified": true, "line": line, "instructionReference": reference(address) }));
+                }
+                None => breakpoints.push(json!({ "verified": false, "line": line, "message": "No code on or after this line" })),
+            }
+        }
+        program.source_breakpoints.insert(path, addresses);
+        program.sync_breakpoints();
+        Ok(json!({ "breakpoints": breakpoints }))
+    }
+
+    fn set_instruction_breakpoints(&mut self, arguments: &Value) -> Result<Value, String> {
+        let program = self.program()?;
+        let mut breakpoints = Vec::new();
+        program.instruction_breakpoints.clear();
+        for requested in arguments["breakpoints"].as_array().into_iter().flatten() {
+            let address = parse_reference(&requested["instructionReference"])
+                .map(|address| address.wrapping_add_signed(requested["offset"].as_i64().unwrap_or(0) as isize));
+            match address {
+                Some(address) if address < MEMORY_SIZE => {
+                    program.instruction_breakpoints.push(address);
+                    breakpoints.push(json!({ "verified": true, "instructionReference": reference(address) }));
+                }
+                _ => breakpoints.push(json!({ "verified": false, "message": "Not an address in memory" })),
+            }
+        }
+        program.sync_breakpoints();
+        Ok(json!({ "breakpoints": breakpoints }))
+    }
+
+    fn configuration_done(&mut self) -> Result<Value, String> {
+        if self.program()?.stop_on_entry {
+            self.events.push(("stopped", json!({ "reason": "entry", "threadId": THREAD_ID })));
+            Ok(Value::Null)
+        } else {
+            self.execute(|debugger| debugger.resume())
+        }
+    }
+
+    // runs the machine, then reports its output and why it stopped
+    fn execute(&mut self, run: impl FnOnce(&mut Debugger) -> Stop) -> Result<Value, String> {
+        let program = self.program()?;
+        let stop = run(&mut program.debugger);
+
+        let mut events = Vec::new();
+        for output in program.debugger.machine.devices.output() {
+            let shown = program.shown.entry(output.device).or_insert(0);
+            if output.bytes.len() > *shown {
+                let text = String::from_utf8_lossy(&output.bytes[*shown..]).into_owned();
+                events.push(("output", json!({ "category": "stdout", "output": text })));
+                *shown = output.bytes.len();
+            }
+        }
+
+        let stopped = |reason: &str, description: String| {
+            ("stopped", json!({ "reason": reason, "description": description, "threadId": THREAD_ID }))
+        };
+        match stop {
+            Stop::Step | Stop::Cursor { .. } => events.push(stopped("step", stop.to_string())),
+            Stop::Breakpoint { .. } => events.push(stopped("breakpoint", stop.to_string())),
+            Stop::Watchpoint { .. } => events.push(stopped("data breakpoint", stop.to_string())),
+            Stop::Halted { halt: Halt::StepLimit } => events.push(stopped("pause", stop.to_string())),
+            Stop::Halted { halt: Halt::Fault { address, message } } => {
+                let mut event = stopped("exception", format!("Fault at {:06X}", address));
+                event.1["text"] = json!(message);
+                events.push(event);
+            }
+            Stop::Halted { halt } => {
+                let code = match halt {
+                    Halt::SupervisorCall { code } => code as i64,
+                    _ => 0,
+                };
+                events.push(("output", json!({ "category": "console", "output": format!("Program {}\n", halt) })));
+                events.push(("exited", json!({ "exitCode": code })));
+                events.push(("terminated", json!({})));
+            }
+        }
+        self.events.extend(events);
+        Ok(Value::Null)
+    }
+
+    fn stack_trace(&mut self) -> Result<Value, String> {
+        let program = self.program()?;
+        let pc = program.debugger.machine.registers.pc as usize;
+        // the routine is the closest label at or before PC
+        let labels = program.debugger.labels();
+        let name = labels.range(..=pc).next_back().map_or_else(|| reference(pc), |(_, label)| label.clone());
+
+        let mut frame = json!({
+            "id": 0,
+            "name": name,
+            "line": 0,
+            "column": 0,
+            "instructionPointerReference": reference(pc),
+        });
+        if let Some(range) = program.debugger.debug_info.lookup(pc) {
+            frame["source"] = program.source(range);
+            frame["line"] = json!(range.line);
+            frame["column"] = json!(range.column);
+        }
+        Ok(json!({ "stackFrames": [frame], "totalFrames": 1 }))
+    }
+
+    fn variables(&mut self, arguments: &Value) -> Result<Value, String> {
+        let program = self.program()?;
+        let machine = &program.debugger.machine;
+        let variable = |name: &str, value: String| json!({ "name": name, "value": value, "variablesReference": 0 });
+        let variables: Vec<Value> = match arguments["variablesReference"].as_i64() {
+            Some(REGISTERS_REFERENCE) => {
+                let r = &machine.registers;
+                let mut variables: Vec<Value> = [0, 1, 2, 3, 4, 5, 8, 9].iter()
+                    .filter_map(|&number| Some(variable(get_register_name(number)?, format!("{:06X}", r.get(number).ok()?))))
+                    .collect();
+                variables.insert(6, variable("F", r.f.to_string()));
+                variables.push(variable("CC", format!("{:?}", r.condition_code())));
+                variables
+            }
+            Some(LABELS_REFERENCE) => program.debugger.labels().into_iter()
+                .map(|(address, label)| {
+                    let mut value = variable(&label, word_at(machine, address));
+                    value["memoryReference"] = json!(reference(address));
+                    value["evaluateName"] = json!(label);
+                    value
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+        Ok(json!({ "variables": variables }))
+    }
+
+    // a register name, or a label or hex address whose word is shown
+    fn evaluate(&mut self, arguments: &Value) -> Result<Value, String> {
+        let program = self.program()?;
+        let expression = arguments["expression"].as_str().unwrap_or_default().trim();
+        let registers = &program.debugger.machine.registers;
+        if expression.eq_ignore_ascii_case("F") {
+            return Ok(json!({ "result": registers.f.to_string(), "variablesReference": 0 }));
+        }
+        let register = (0..10).find(|&number| get_register_name(number).is_some_and(|name| name.eq_ignore_ascii_case(expression)));
+        if let Some(value) = register.and_then(|number| registers.get(number).ok()) {
+            return Ok(json!({ "result": format!("{:06X}", value), "variablesReference": 0 }));
+        }
+        let address = program.debugger.resolve(expression)?;
+        Ok(json!({
+            "result": word_at(&program.debugger.machine, address),
+            "variablesReference": 0,
+            "memoryReference": reference(address),
+        }))
+    }
+
+    // decodes from the statement starts the assembler recorded, so walking
+    // backwards (negative `instructionOffset`) stays aligned
+    fn disassemble(&mut self, arguments: &Value) -> Result<Value, String> {
+        let program = self.program()?;
+        let address = parse_reference(&arguments["memoryReference"])
+            .ok_or("disassemble needs a memoryReference")?
+            .wrapping_add_signed(arguments["offset"].as_i64().unwrap_or(0) as isize)
+            .min(MEMORY_SIZE);
+        let offset = arguments["instructionOffset"].as_i64().unwrap_or(0)
+            .clamp(-(MAX_DISASSEMBLY as i64), MAX_DISASSEMBLY as i64);
+        let count = (arguments["instructionCount"].as_u64().unwrap_or(0) as usize).min(MAX_DISASSEMBLY);
+
+        let starts = program.statement_starts();
+        let mut instructions = Vec::new();
+        let mut address = address;
+        if offset < 0 {
+            let back = offset.unsigned_abs() as usize;
+            let index = starts.partition_point(|&start| start < address);
+            if index >= back {
+                address = starts[index - back];
+            } else {
+                // before the program: placeholders keep the count right
+                let missing = back - index;
+                address = starts.first().copied().unwrap_or(address);
+                for k in (1..=missing).rev().take(count) {
+                    instructions.push(json!({
+                        "address": reference(address.saturating_sub(k)),
+                        "instruction": "??",
+                        "presentationHint": "invalid",
+                    }));
+                }
+            }
+        } else {
+            for _ in 0..offset {
+                if address >= MEMORY_SIZE {
+                    break;
+                }
+                address += program.instruction_at(address).0.len().max(1);
+            }
+        }
+
+        let labels = program.debugger.labels();
+        while instructions.len() < count {
+            // past the end of memory: placeholders keep the count right
+            if address >= MEMORY_SIZE {
+                instructions.push(json!({
+                    "address": reference(address),
+                    "instruction": "??",
+                    "presentationHint": "invalid",
+                }));
+                address += 1;
+                continue;
+            }
+            let (bytes, text) = program.instruction_at(address);
+            let mut instruction = json!({
+                "address": reference(address),
+                "instructionBytes": hex(&bytes),
+                "instruction": text,
+            });
+            if let Some(label) = labels.get(&address) {
+                instruction["symbol"] = json!(label);
+            }
+            if let Some(range) = program.debugger.debug_info.lookup(address) {
+                instruction["location"] = program.source(range);
+                instruction["line"] = json!(range.line);
+                instruction["column"] = json!(range.column);
+            }
+            instructions.push(instruction);
+            address += bytes.len().max(1);
+        }
+        Ok(json!({ "instructions": instructions }))
+    }
+
+    fn read_memory(&mut self, arguments: &Value) -> Result<Value, String> {
+        let program = self.program()?;
+        let address = parse_reference(&arguments["memoryReference"])
+            .ok_or("readMemory needs a memoryReference")?
+            .wrapping_add_signed(arguments["offset"].as_i64().unwrap_or(0) as isize)
+            .min(MEMORY_SIZE);
+        let count = (arguments["count"].as_u64().unwrap_or(0) as usize).min(MEMORY_SIZE - address);
+        let bytes = program.debugger.machine.read(address, count)?;
+        Ok(json!({
+            "address": reference(address),
+            "data": base64::engine::general_purpose::STANDARD.encode(bytes),
+        }))
+    }
+}
+
+impl Program {
+    // the debugger's breakpoints are the union of the source and instruction ones
+    fn sync_breakpoints(&mut self) {
+        let current: Vec<usize> = self.debugger.breakpoints().collect();
+        for address in current {
+            self.debugger.clear_breakpoint(address);
+        }
+        for &address in self.source_breakpoints.values().flatten().chain(&self.instruction_breakpoints) {
+            self.debugger.set_breakpoint(address);
+        }
+    }
+
+    // a DAP `Source` for a debug info range
+    fn source(&self, range: &SourceRange) -> Value {
+        json!({ "name": range.file, "path": self.root.join(&range.file).to_string_lossy() })
+    }
+
+    // addresses where one statement's code begins
+    fn statement_starts(&self) -> Vec<usize> {
+        self.debugger.debug_info.ranges.iter().map(|range| range.start).collect()
+    }
+
+    // the bytes and text of the instruction at `address`; code that does
+    // not decode, or that would run into the next statement, is shown as data
+    fn instruction_at(&self, address: usize) -> (Vec<u8>, String) {
+        let statement = self.debugger.debug_info.lookup(address).map(|range| range.end - address);
+        match self.debugger.disassemble(address, 1).into_iter().next() {
+            Some((instruction, text)) if statement.is_none_or(|len| instruction.size() <= len) => (instruction.bytes, text),
+            _ => {
+                let bytes = self.debugger.machine.read(address, statement.unwrap_or(1)).unwrap_or_default();
+                let text = format!("BYTE X'{}'", hex(&bytes));
+                (bytes, text)
+            }
+        }
+    }
+}
+
+fn capabilities() -> Value {
+    json!({
+        "supportsConfigurationDoneRequest": true,
+        "supportsDisassembleRequest": true,
+        "supportsInstructionBreakpoints": true,
+        "supportsReadMemoryRequest": true,
+        "supportsEvaluateForHovers": true,
+        "supportsSteppingGranularity": false,
+    })
+}
+
+// assembles `path` in a scratch directory that is removed afterwards
+fn assemble(path: &str) -> Result<(ObjectProgram, Assembly), String> {
+    let work = std::env::temp_dir().join(format!("systems_project-dap-{}", std::process::id()));
+    fs::create_dir_all(&work).map_err(|e| format!("Failed to create work directory: {}", e))?;
+    let assembly = assembler::assemble_file(path, &work.to_string_lossy(), &AssemblyOptions::default());
+    let _ = fs::remove_dir_all(&work);
+
+    let assembly = match assembly {
+        Ok(assembly) => assembly,
+        Err(AssembleError::Pass1 { message, diagnostics } | AssembleError::Pass2 { message, diagnostics }) if !diagnostics.is_empty() => {
+            let errors: Vec<String> = diagnostics.iter().filter(|d| d.is_error()).map(ToString::to_string).collect();
+            return Err(if errors.is_empty() { message } else { errors.join("\n") });
+        }
+        Err(e) => return Err(e.to_string()),
+    };
+    let program = ObjectProgram::parse(&assembly.object_program)
+        .map_err(|errors| errors.iter().map(ToString::to_string).collect::<Vec<_>>().join("\n"))?;
+    Ok((program, assembly))
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+fn reference(address: usize) -> String {
+    format!("0x{:06X}", address)
+}
+
+fn parse_reference(value: &Value) -> Option<usize> {
+    let text = value.as_str()?;
+    let digits = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X"))?;
+    usize::from_str_radix(digits, 16).ok()
+}
+
+fn word_at(machine: &Machine, address: usize) -> String {
+    machine.read_word(address).map_or_else(|_| "??????".to_string(), |word| format!("{:06X}", word))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02X}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::framing::exchange;
+    use std::fs;
+
+    fn request(seq: u64, command: &str, arguments: Value) -> Value {
+        json!({ "seq": seq, "type": "request", "command": command, "arguments": arguments })
+    }
+
+    // the responses to `requests` after launching a small program
+    fn session(name: &str, requests: &[Value]) -> Vec<Value> {
+        let dir = std::env::temp_dir().join(format!("sicxe-dap-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("main.asm");
+        fs::write(&source, "PROG    START  0\nFIRST   LDA    #1\n        ADD    #2\n        RSUB\n        END    FIRST\n").unwrap();
+
+        let mut messages = vec![
+            request(1, "initialize", json!({})),
+            request(2, "launch", json!({ "program": source.to_string_lossy(), "stopOnEntry": true })),
+        ];
+        messages.extend_from_slice(requests);
+        let responses = exchange(&messages, |input, output| serve(input, output));
+        fs::remove_dir_all(&dir).unwrap();
+        responses.into_iter().filter(|message| message["type"] == "response").collect()
+    }
+
+    fn body(responses: &[Value], seq: u64) -> &Value {
+        let response = responses.iter().find(|response| response["request_seq"] == seq).unwrap();
+        assert_eq!(response["success"], true, "{}", response);
+        &response["body"]
+    }
+
+    #[test]
+    fn disassembles_around_an_address() {
+        let responses = session("around", &[
+            request(3, "disassemble", json!({ "memoryReference": "0x000000", "instructionOffset": -1, "instructionCount": 4 })),
+        ]);
+        let instructions: Vec<&str> = body(&responses, 3)["instructions"].as_array().unwrap().iter()
+            .map(|instruction| instruction["instruction"].as_str().unwrap())
+            .collect();
+        assert_eq!(instructions.len(), 4);
+        assert_eq!(instructions[0], "??");
+        assert!(instructions[1].starts_with("LDA"), "{:?}", instructions);
+    }
+
+    #[test]
+    fn bounds_disassemble_requests() {
+        let responses = session("bounds", &[
+            request(3, "disassemble", json!({ "memoryReference": "0xFFFFFFFF", "offset": 1000, "instructionCount": u32::MAX })),
+            request(4, "disassemble", json!({ "memoryReference": "0x000000", "instructionOffset": i32::MIN, "instructionCount": 2 })),
+        ]);
+        let far = body(&responses, 3)["instructions"].as_array().unwrap();
+        assert_eq!(far.len(), MAX_DISASSEMBLY);
+        assert!(far.iter().all(|instruction| instruction["instruction"] == "??"));
+        assert_eq!(body(&responses, 4)["instructions"].as_array().unwrap().len(), 2);
+    }
+}
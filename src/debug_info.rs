@@ -0,0 +1,69 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use utoipa::ToSchema;
+
+/// Addresses of one statement's code and where that statement is in the
+/// source.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct SourceRange {
+    /// First address
+    pub start: usize,
+    /// One past the last address
+    pub end: usize,
+    /// Source file, relative to the main source's directory
+    pub file: String,
+    pub line: usize,
+    /// 1-based column of the statement's opcode
+    pub column: usize,
+    pub block: String,
+    /// The statement's label, or the literal for literal pool entries
+    pub symbol: Option<String>,
+}
+
+impl fmt::Display for SourceRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.column)
+    }
+}
+
+/// Source-level debug information: which statement each address of the
+/// program was assembled from. Ranges are sorted by address and do not
+/// overlap.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct DebugInfo {
+    pub program: String,
+    pub entry: Option<usize>,
+    pub ranges: Vec<SourceRange>,
+}
+
+impl DebugInfo {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads debug information written by `--emit debug`.
+    pub fn parse(json: &str) -> Result<Self, String> {
+        serde_json::from_str(json).map_err(|e| format!("Invalid debug info: {}", e))
+    }
+
+    /// The range containing `address`.
+    pub fn lookup(&self, address: usize) -> Option<&SourceRange> {
+        let index = self.ranges.partition_point(|range| range.end <= address);
+        self.ranges.get(index).filter(|range| range.start <= address)
+    }
+
+    /// Ranges assembled from `line` of `file`, in address order.
+    pub fn ranges_for_line<'a>(&'a self, file: &'a str, line: usize) -> impl Iterator<Item = &'a SourceRange> + 'a {
+        self.ranges.iter().filter(move |range| range.file == file && range.line == line)
+    }
+
+    /// Moves every address by `offset`, for a program loaded somewhere
+    /// other than its assembled start.
+    pub fn relocate(&mut self, offset: isize) {
+        for range in &mut self.ranges {
+            range.start = range.start.wrapping_add_signed(offset);
+            range.end = range.end.wrapping_add_signed(offset);
+        }
+        self.entry = self.entry.map(|entry| entry.wrapping_add_signed(offset));
+    }
+}
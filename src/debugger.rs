@@ -0,0 +1,353 @@
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::ops::Range;
+
+use crate::conversions::get_register_name;
+use crate::debug_info::DebugInfo;
+use crate::disassembler::Instruction;
+use crate::emulator::{Halt, Machine, DEFAULT_STEP_LIMIT};
+
+/// Bytes per line of a memory dump.
+const DUMP_WIDTH: usize = 16;
+
+/// What a watchpoint watches.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Watch {
+    /// Bytes `range.start` up to (not including) `range.end`
+    Memory { range: Range<usize> },
+    /// A register by its format 2 number (`F` is 6, `PC` 8, `SW` 9)
+    Register { register: u8 },
+}
+
+impl fmt::Display for Watch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Watch::Memory { range } if range.len() == 1 => write!(f, "{:06X}", range.start),
+            Watch::Memory { range } => write!(f, "{:06X}-{:06X}", range.start, range.end),
+            Watch::Register { register } => write!(f, "{}", get_register_name(*register).unwrap_or("?")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Watchpoint {
+    pub id: usize,
+    pub watch: Watch,
+}
+
+/// Why the debugger gave control back.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum Stop {
+    /// A step or step-over finished
+    Step,
+    /// `PC` reached a breakpoint
+    Breakpoint { address: usize },
+    /// `PC` reached the address given to [`Debugger::run_to`]
+    Cursor { address: usize },
+    /// The instruction at `address` changed a watched value
+    Watchpoint { id: usize, address: usize, old: Vec<u8>, new: Vec<u8> },
+    /// The machine stopped on its own; a `StepLimit` halt means
+    /// [`Debugger::step_limit`] instructions ran without another stop
+    Halted { halt: Halt },
+}
+
+impl fmt::Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Stop::Step => write!(f, "stepped"),
+            Stop::Breakpoint { address } => write!(f, "breakpoint at {:06X}", address),
+            Stop::Cursor { address } => write!(f, "reached {:06X}", address),
+            Stop::Watchpoint { id, address, old, new } => write!(
+                f, "watchpoint {} changed by the instruction at {:06X}: {} -> {}", id, address, hex(old), hex(new),
+            ),
+            Stop::Halted { halt } => write!(f, "{}", halt),
+        }
+    }
+}
+
+/// Drives a [`Machine`] one instruction at a time, stopping at breakpoints
+/// and when watched memory or registers change. Locations can be given as
+/// hex addresses or as labels from `symbols`.
+pub struct Debugger {
+    pub machine: Machine,
+    /// Labels and their addresses, e.g. from `Assembly::symbols` or
+    /// `LoadMap::estab()`
+    pub symbols: HashMap<String, usize>,
+    /// Source lines by address, e.g. from `Assembly::debug_info`
+    pub debug_info: DebugInfo,
+    /// Most instructions one `resume`, `run_to` or `step_over` executes
+    pub step_limit: u64,
+    breakpoints: BTreeSet<usize>,
+    watchpoints: Vec<Watchpoint>,
+    next_watch_id: usize,
+}
+
+impl Debugger {
+    pub fn new(machine: Machine) -> Self {
+        Self {
+            machine,
+            symbols: HashMap::new(),
+            debug_info: DebugInfo::new(),
+            step_limit: DEFAULT_STEP_LIMIT,
+            breakpoints: BTreeSet::new(),
+            watchpoints: Vec::new(),
+            next_watch_id: 1,
+        }
+    }
+
+    /// Address of a hex address, a label, or a label plus a hex offset
+    /// (`BUFFER+3`). Labels are tried first, so a label such as `ADD` is not
+    /// read as a number.
+    pub fn resolve(&self, location: &str) -> Result<usize, String> {
+        let (name, offset) = match location.split_once('+') {
+            Some((name, offset)) => {
+                let offset = usize::from_str_radix(offset.trim(), 16)
+                    .map_err(|_| format!("'{}' is not a hex offset", offset.trim()))?;
+                (name.trim(), offset)
+            }
+            None => (location.trim(), 0),
+        };
+        let base = match self.symbols.get(&name.to_uppercase()) {
+            Some(&address) => address,
+            None => usize::from_str_radix(name, 16)
+                .map_err(|_| format!("'{}' is neither a label nor a hex address", name))?,
+        };
+        Ok(base + offset)
+    }
+
+    /// Labels by address, for showing instructions and locations.
+    pub fn labels(&self) -> BTreeMap<usize, String> {
+        let mut labels = BTreeMap::new();
+        for (name, &address) in &self.symbols {
+            // several names for one address: show the first alphabetically
+            let entry = labels.entry(address).or_insert_with(|| name.clone());
+            if name < entry {
+                *entry = name.clone();
+            }
+        }
+        labels
+    }
+
+    /// Adds a breakpoint; `false` when one was already set there.
+    pub fn set_breakpoint(&mut self, address: usize) -> bool {
+        self.breakpoints.insert(address)
+    }
+
+    /// Removes a breakpoint; `false` when none was set there.
+    pub fn clear_breakpoint(&mut self, address: usize) -> bool {
+        self.breakpoints.remove(&address)
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = usize> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    /// Adds a watchpoint and returns its id.
+    pub fn watch(&mut self, watch: Watch) -> Result<usize, String> {
+        if let Watch::Register { register } = watch {
+            if register != 6 {
+                self.machine.registers.get(register)?;
+            }
+        }
+        let id = self.next_watch_id;
+        self.next_watch_id += 1;
+        self.watchpoints.push(Watchpoint { id, watch });
+        Ok(id)
+    }
+
+    /// Removes a watchpoint; `false` when there is none with that id.
+    pub fn unwatch(&mut self, id: usize) -> bool {
+        let before = self.watchpoints.len();
+        self.watchpoints.retain(|watchpoint| watchpoint.id != id);
+        self.watchpoints.len() != before
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    /// Executes one instruction.
+    pub fn step(&mut self) -> Stop {
+        self.execute().unwrap_or(Stop::Step)
+    }
+
+    /// Executes one instruction, or a whole subroutine when it is a `JSUB`:
+    /// runs until control comes back to the instruction after it.
+    pub fn step_over(&mut self) -> Stop {
+        let pc = self.machine.registers.pc as usize;
+        match self.machine.fetch(pc) {
+            Ok(instruction) if instruction.mnemonic == "JSUB" => match self.resume_until(Some(pc + instruction.size())) {
+                Stop::Cursor { .. } => Stop::Step,
+                stop => stop,
+            },
+            _ => self.step(),
+        }
+    }
+
+    /// Runs until a breakpoint, a watchpoint or a halt. The instruction at
+    /// `PC` is executed even when it has a breakpoint, so resuming from a
+    /// breakpoint moves on.
+    pub fn resume(&mut self) -> Stop {
+        self.resume_until(None)
+    }
+
+    /// Runs until `PC` reaches `address`, stopping earlier at breakpoints,
+    /// watchpoints and halts.
+    pub fn run_to(&mut self, address: usize) -> Stop {
+        self.resume_until(Some(address))
+    }
+
+    fn resume_until(&mut self, cursor: Option<usize>) -> Stop {
+        for k in 0..self.step_limit {
+            let pc = self.machine.registers.pc as usize;
+            if k > 0 && cursor == Some(pc) {
+                return Stop::Cursor { address: pc };
+            }
+            if k > 0 && self.breakpoints.contains(&pc) {
+                return Stop::Breakpoint { address: pc };
+            }
+            if let Some(stop) = self.execute() {
+                return stop;
+            }
+        }
+        Stop::Halted { halt: Halt::StepLimit }
+    }
+
+    // one instruction; `Some` when the machine halted or a watched value changed
+    fn execute(&mut self) -> Option<Stop> {
+        let address = self.machine.registers.pc as usize;
+        let before: Vec<Vec<u8>> = self.watchpoints.iter().map(|watchpoint| self.value(&watchpoint.watch)).collect();
+        if let Some(halt) = self.machine.step() {
+            return Some(Stop::Halted { halt });
+        }
+        self.watchpoints.iter().zip(before).find_map(|(watchpoint, old)| {
+            let new = self.value(&watchpoint.watch);
+            (new != old).then_some(Stop::Watchpoint { id: watchpoint.id, address, old, new })
+        })
+    }
+
+    // big-endian bytes of a watched value; memory outside the machine reads empty
+    fn value(&self, watch: &Watch) -> Vec<u8> {
+        match watch {
+            Watch::Memory { range } => self.machine.read(range.start, range.len()).unwrap_or_default(),
+            Watch::Register { register: 6 } => self.machine.registers.f.to_bits().to_be_bytes().to_vec(),
+            Watch::Register { register } => {
+                let value = self.machine.registers.get(*register).unwrap_or(0);
+                value.to_be_bytes()[1..].to_vec()
+            }
+        }
+    }
+
+    /// Decodes `count` instructions from `address` on, stopping at bytes that
+    /// are not an instruction. Operands use the current `B` register and show
+    /// labels by name.
+    pub fn disassemble(&self, address: usize, count: usize) -> Vec<(Instruction, String)> {
+        let labels = self.labels();
+        let base = Some(self.machine.registers.b as usize);
+        let mut instructions = Vec::new();
+        let mut address = address;
+        for _ in 0..count {
+            let Ok(instruction) = self.machine.fetch(address) else {
+                break;
+            };
+            address += instruction.size();
+            let text = instruction.text(base, &labels);
+            instructions.push((instruction, text));
+        }
+        instructions
+    }
+
+    /// `len` bytes from `address` as hex, 16 to a line, with the printable
+    /// characters alongside.
+    pub fn dump(&self, address: usize, len: usize) -> Result<String, String> {
+        let bytes = self.machine.read(address, len)?;
+        let mut out = String::new();
+        for (k, line) in bytes.chunks(DUMP_WIDTH).enumerate() {
+            let hex: Vec<String> = line.iter().map(|byte| format!("{:02X}", byte)).collect();
+            let text: String = line.iter()
+                .map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' })
+                .collect();
+            out.push_str(&format!("{:06X}  {:<47}  {}\n", address + k * DUMP_WIDTH, hex.join(" "), text));
+        }
+        Ok(out)
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02X}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::{assemble_text, AssemblyOptions};
+    use crate::object_program::ObjectProgram;
+
+    // a debugger stopped at the entry of a program that calls a subroutine
+    fn debugger() -> Debugger {
+        let assembly = assemble_text("\
+PROG    START  0
+FIRST   STL    RETADR
+        JSUB   DOUBLE
+        STA    RESULT
+        LDL    RETADR
+        RSUB
+DOUBLE  LDA    #21
+        ADD    #21
+        RSUB
+RETADR  RESW   1
+RESULT  RESW   1
+        END    FIRST
+", &AssemblyOptions::default()).unwrap();
+        let mut machine = Machine::new();
+        machine.load(&ObjectProgram::parse(&assembly.object_program).unwrap()).unwrap();
+        let mut debugger = Debugger::new(machine);
+        debugger.symbols = assembly.symbols.into_iter().map(|entry| (entry.name, entry.address)).collect();
+        debugger
+    }
+
+    #[test]
+    fn resolves_labels_and_addresses() {
+        let debugger = debugger();
+        assert_eq!(debugger.resolve("double"), Ok(0x0F));
+        assert_eq!(debugger.resolve("DOUBLE+3"), Ok(0x12));
+        assert_eq!(debugger.resolve("1A"), Ok(0x1A));
+        assert!(debugger.resolve("NOWHERE").is_err());
+        assert_eq!(debugger.labels()[&0x0F], "DOUBLE");
+    }
+
+    #[test]
+    fn stops_at_breakpoints_and_steps_over_subroutines() {
+        let mut debugger = debugger();
+        assert!(debugger.set_breakpoint(0x0F));
+        assert!(!debugger.set_breakpoint(0x0F));
+        assert_eq!(debugger.resume(), Stop::Breakpoint { address: 0x0F });
+        assert!(debugger.clear_breakpoint(0x0F));
+
+        let mut debugger = self::debugger();
+        assert_eq!(debugger.step(), Stop::Step);
+        assert_eq!(debugger.step_over(), Stop::Step);
+        assert_eq!(debugger.machine.registers.pc, 6);
+        assert_eq!(debugger.machine.registers.a, 42);
+        assert_eq!(debugger.resume(), Stop::Halted { halt: Halt::Returned });
+    }
+
+    #[test]
+    fn stops_when_a_watched_value_changes() {
+        let mut debugger = debugger();
+        let result = debugger.resolve("RESULT").unwrap();
+        let id = debugger.watch(Watch::Memory { range: result..result + 3 }).unwrap();
+        assert_eq!(debugger.resume(), Stop::Watchpoint { id, address: 6, old: vec![0, 0, 0], new: vec![0, 0, 42] });
+    }
+
+    #[test]
+    fn disassembles_with_labels() {
+        let debugger = debugger();
+        let lines: Vec<String> = debugger.disassemble(0, 2).into_iter().map(|(_, text)| text).collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[1].contains("DOUBLE"), "{:?}", lines);
+    }
+}
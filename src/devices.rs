@@ -0,0 +1,216 @@
+use serde::Serialize;
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+/// How a device answers `TD`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Readiness {
+    #[default]
+    Always,
+    Never,
+    /// Busy for `n` tests, then ready for one, repeating; exercises the
+    /// `TD`/`JEQ` polling loop
+    Every(u32),
+}
+
+/// What `RD` does once a device's input is used up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EndOfFile {
+    /// Read this byte, as often as asked
+    Byte(u8),
+    /// Read nothing: `TD` reports the device busy from then on
+    NotReady,
+    /// Stop the run with a fault
+    Fault,
+}
+
+impl Default for EndOfFile {
+    fn default() -> Self {
+        EndOfFile::Byte(0)
+    }
+}
+
+enum Input {
+    Bytes(VecDeque<u8>),
+    Stdin,
+}
+
+/// One SIC/XE device. Input comes from a byte buffer, a file read when the
+/// device is created, or standard input; everything written is captured and,
+/// for file and standard output devices, also written through.
+pub struct Device {
+    input: Input,
+    sink: Option<Box<dyn Write + Send>>,
+    captured: Vec<u8>,
+    tests: u32,
+    exhausted: bool,
+    pub readiness: Readiness,
+    pub end_of_file: EndOfFile,
+}
+
+impl Device {
+    /// Reads `input`; writes are only captured.
+    pub fn buffer(input: impl Into<Vec<u8>>) -> Self {
+        Self::with_input(Input::Bytes(input.into().into()), None)
+    }
+
+    pub fn input_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self::buffer(fs::read(path)?))
+    }
+
+    /// Writes to `path`, replacing its contents.
+    pub fn output_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self::with_input(Input::Bytes(VecDeque::new()), Some(Box::new(File::create(path)?))))
+    }
+
+    pub fn stdin() -> Self {
+        Self::with_input(Input::Stdin, None)
+    }
+
+    pub fn stdout() -> Self {
+        Self::with_input(Input::Bytes(VecDeque::new()), Some(Box::new(io::stdout())))
+    }
+
+    fn with_input(input: Input, sink: Option<Box<dyn Write + Send>>) -> Self {
+        Self {
+            input,
+            sink,
+            captured: Vec::new(),
+            tests: 0,
+            exhausted: false,
+            readiness: Readiness::default(),
+            end_of_file: EndOfFile::default(),
+        }
+    }
+
+    /// Everything written to the device so far.
+    pub fn output(&self) -> &[u8] {
+        &self.captured
+    }
+
+    // buffered input is known to be used up before it is read; standard
+    // input only once a read comes back empty
+    fn at_end(&self) -> bool {
+        match &self.input {
+            Input::Bytes(bytes) => bytes.is_empty(),
+            Input::Stdin => self.exhausted,
+        }
+    }
+
+    fn test(&mut self) -> bool {
+        if self.end_of_file == EndOfFile::NotReady && self.at_end() {
+            return false;
+        }
+        match self.readiness {
+            Readiness::Always => true,
+            Readiness::Never => false,
+            Readiness::Every(busy) => {
+                self.tests += 1;
+                if self.tests > busy {
+                    self.tests = 0;
+                    true
+                } else {
+                    false
+                }
+            }
+        }
+    }
+
+    fn read(&mut self) -> Result<u8, String> {
+        let next = match &mut self.input {
+            Input::Bytes(bytes) => bytes.pop_front(),
+            Input::Stdin => {
+                let mut byte = [0u8; 1];
+                match io::stdin().read(&mut byte) {
+                    Ok(0) => None,
+                    Ok(_) => Some(byte[0]),
+                    Err(e) => return Err(format!("Failed to read standard input: {}", e)),
+                }
+            }
+        };
+        if let Some(byte) = next {
+            return Ok(byte);
+        }
+
+        self.exhausted = true;
+        match self.end_of_file {
+            EndOfFile::Byte(byte) => Ok(byte),
+            EndOfFile::NotReady => Ok(0),
+            EndOfFile::Fault => Err("Read past the end of the device's input".to_string()),
+        }
+    }
+
+    fn write(&mut self, byte: u8) -> Result<(), String> {
+        self.captured.push(byte);
+        if let Some(sink) = &mut self.sink {
+            sink.write_all(&[byte])
+                .and_then(|_| sink.flush())
+                .map_err(|e| format!("Failed to write device output: {}", e))?;
+        }
+        Ok(())
+    }
+}
+
+/// Bytes written to one device during a run.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DeviceOutput {
+    pub device: u8,
+    /// `bytes` as UTF-8, with invalid sequences replaced
+    pub text: String,
+    pub bytes: Vec<u8>,
+}
+
+/// The devices attached to a machine, by device number. A device that is
+/// used without being attached behaves like an empty buffer: always ready,
+/// reads give 00 and writes are captured.
+#[derive(Default)]
+pub struct Devices {
+    devices: BTreeMap<u8, Device>,
+}
+
+impl Devices {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn attach(&mut self, number: u8, device: Device) {
+        self.devices.insert(number, device);
+    }
+
+    pub fn get(&self, number: u8) -> Option<&Device> {
+        self.devices.get(&number)
+    }
+
+    fn device(&mut self, number: u8) -> &mut Device {
+        self.devices.entry(number).or_insert_with(|| Device::buffer(Vec::new()))
+    }
+
+    /// `TD`: whether the device is ready.
+    pub fn test(&mut self, number: u8) -> bool {
+        self.device(number).test()
+    }
+
+    /// `RD`: the next input byte.
+    pub fn read(&mut self, number: u8) -> Result<u8, String> {
+        self.device(number).read().map_err(|e| format!("Device {:02X}: {}", number, e))
+    }
+
+    /// `WD`
+    pub fn write(&mut self, number: u8, byte: u8) -> Result<(), String> {
+        self.device(number).write(byte).map_err(|e| format!("Device {:02X}: {}", number, e))
+    }
+
+    /// Captured output of every device written to, by device number.
+    pub fn output(&self) -> Vec<DeviceOutput> {
+        self.devices.iter()
+            .filter(|(_, device)| !device.captured.is_empty())
+            .map(|(&number, device)| DeviceOutput {
+                device: number,
+                text: String::from_utf8_lossy(&device.captured).into_owned(),
+                bytes: device.captured.clone(),
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,133 @@
+use serde::Serialize;
+use std::fmt;
+use utoipa::ToSchema;
+
+use crate::source::SourceLocation;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+}
+
+/// A secondary location attached to a diagnostic, e.g. the first definition
+/// of a duplicated label.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct RelatedLocation {
+    pub location: SourceLocation,
+    pub message: String,
+}
+
+/// An error or warning produced while assembling. `code` is a short stable
+/// name (`duplicate-label`, `register-label`, ...) that tools can match on.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: String,
+    pub message: String,
+    pub location: SourceLocation,
+    pub related: Vec<RelatedLocation>,
+}
+
+impl Diagnostic {
+    pub fn error(code: &str, message: String, location: &SourceLocation) -> Self {
+        Self::new(Severity::Error, code, message, location)
+    }
+
+    pub fn warning(code: &str, message: String, location: &SourceLocation) -> Self {
+        Self::new(Severity::Warning, code, message, location)
+    }
+
+    fn new(severity: Severity, code: &str, message: String, location: &SourceLocation) -> Self {
+        Self {
+            severity,
+            code: code.to_string(),
+            message,
+            location: location.clone(),
+            related: Vec::new(),
+        }
+    }
+
+    pub fn with_related(mut self, location: &SourceLocation, message: &str) -> Self {
+        self.related.push(RelatedLocation {
+            location: location.clone(),
+            message: message.to_string(),
+        });
+        self
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    /// Multi-line report quoting the offending source line, with carets under
+    /// the name the message refers to (or the whole statement when the name
+    /// is not found on the line). `line` is the text at `self.location`.
+    ///
+    /// ```text
+    /// error[duplicate-label]: Label 'LOOP' is already defined
+    ///  --> main.asm:7
+    ///   |
+    /// 7 | LOOP     LDA    #0
+    ///   | ^^^^
+    ///   = first defined here at main.asm:3
+    /// ```
+    pub fn render(&self, line: Option<&str>) -> String {
+        let number = self.location.line.to_string();
+        let gutter = " ".repeat(number.len());
+
+        let mut out = format!("{}[{}]: {}\n{}--> {}\n", self.severity.as_str(), self.code, self.message, gutter, self.location);
+        if let Some(line) = line {
+            let line = line.replace('\t', " ");
+            let (start, len) = self.caret_span(&line);
+            out.push_str(&format!("{} |\n", gutter));
+            out.push_str(&format!("{} | {}\n", number, line.trim_end()));
+            out.push_str(&format!("{} | {}{}\n", gutter, " ".repeat(start), "^".repeat(len.max(1))));
+        }
+        for related in &self.related {
+            out.push_str(&format!("{} = {} at {}\n", gutter, related.message, related.location));
+        }
+        out
+    }
+
+    // (column, width) of the first quoted name in the message as a whole word
+    // on the line, else of the statement without its comment
+    fn caret_span(&self, line: &str) -> (usize, usize) {
+        let upper = line.to_uppercase();
+        let name = self.message.split('\'').nth(1).filter(|name| !name.is_empty());
+        if let Some(name) = name.map(str::to_uppercase) {
+            let is_word = |c: Option<char>| c.is_some_and(|c| c.is_ascii_alphanumeric() || c == '_');
+            for (start, _) in upper.match_indices(&name) {
+                let before = upper[..start].chars().next_back();
+                let after = upper[start + name.len()..].chars().next();
+                if !is_word(before) && !is_word(after) {
+                    return (upper[..start].chars().count(), name.chars().count());
+                }
+            }
+        }
+
+        let code = line.split(';').next().unwrap_or("").trim_end();
+        let start = code.len() - code.trim_start().len();
+        (code[..start].chars().count(), code[start..].chars().count())
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at {}", self.message, self.location)?;
+        for related in &self.related {
+            write!(f, " ({} at {})", related.message, related.location)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,431 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::conversions::get_register_name;
+use crate::debug_info::DebugInfo;
+use crate::formatter::{pad_to, COMMENT_COLUMN, OPCODE_COLUMN, OPERAND_COLUMN};
+use crate::object_program::ObjectProgram;
+use crate::pass2::{Pass2, FORMAT1, FORMAT2, FORMAT4};
+
+/// Most bytes written on one `BYTE X'..'` line.
+const DATA_CHUNK: usize = 16;
+const CONDITIONS: [&str; 4] = ["Z", "N", "C", "V"];
+
+/// Operand fields of a decoded instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Operands {
+    /// Format 1
+    None,
+    /// Format 2: the two register nibbles
+    Registers(u8, u8),
+    /// Formats 3 and 4: the nixbpe flags and the 12-bit displacement or
+    /// 20-bit address
+    Memory { n: bool, i: bool, x: bool, b: bool, p: bool, e: bool, disp: usize },
+    /// Format 4F: register, condition (0-3 for Z, N, C, V) and 20-bit address
+    Conditional { register: u8, condition: u8, address: usize },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instruction {
+    pub address: usize,
+    pub mnemonic: String,
+    pub bytes: Vec<u8>,
+    pub operands: Operands,
+}
+
+impl Instruction {
+    pub fn size(&self) -> usize {
+        self.bytes.len()
+    }
+
+    /// Address the operand refers to, before any indirection. `base` is the
+    /// B register, needed for base-relative operands. `None` for register
+    /// instructions and format 3 immediate constants; a format 4 immediate
+    /// may be an address (`+LDB #TABLE`), so it is returned.
+    pub fn target(&self, base: Option<usize>) -> Option<usize> {
+        match self.operands {
+            Operands::Memory { n: false, i: true, b: false, p: false, e: false, .. } => None,
+            Operands::Memory { b: false, p: false, disp, .. } => Some(disp),
+            Operands::Memory { p: true, disp, .. } => {
+                // 12-bit two's complement displacement from the next instruction
+                let offset = if disp & 0x800 != 0 { disp as i64 - 0x1000 } else { disp as i64 };
+                usize::try_from((self.address + self.size()) as i64 + offset).ok()
+            }
+            Operands::Memory { b: true, disp, .. } => base.map(|base| base + disp),
+            Operands::Conditional { address, .. } => Some(address),
+            _ => None,
+        }
+    }
+
+    /// The instruction as an assembler statement, e.g. `+JSUB RDREC`, with
+    /// targets found in `labels` shown by name.
+    pub fn text(&self, base: Option<usize>, labels: &BTreeMap<usize, String>) -> String {
+        let opcode = if self.is_format4() { format!("+{}", self.mnemonic) } else { self.mnemonic.clone() };
+        let operand = operand_text(self, base, labels);
+        if operand.is_empty() { opcode } else { format!("{} {}", opcode, operand) }
+    }
+
+    pub fn is_format4(&self) -> bool {
+        matches!(self.operands, Operands::Memory { e: true, .. })
+    }
+
+    // a jump through memory (`J @RETADR`) has no target known statically
+    fn is_indirect(&self) -> bool {
+        matches!(self.operands, Operands::Memory { n: true, i: false, .. })
+    }
+}
+
+/// Decodes SIC/XE machine code with the opcode table Pass 2 assembles with.
+pub struct Decoder {
+    opcodes: HashMap<u8, String>,
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        let opcodes = Pass2::new().opcode_table.into_iter()
+            .filter_map(|(name, opcode)| u8::from_str_radix(&opcode, 16).ok().map(|opcode| (opcode, name)))
+            .collect();
+        Self { opcodes }
+    }
+
+    /// Mnemonic for an opcode byte, with the n/i bits already cleared for
+    /// formats 3 and 4.
+    pub fn mnemonic(&self, opcode: u8) -> Option<&str> {
+        self.opcodes.get(&opcode).map(String::as_str)
+    }
+
+    /// Decodes the instruction at `address`, reading bytes through `read`.
+    /// `None` when the bytes are not a valid instruction or run out.
+    pub fn decode(&self, address: usize, read: &dyn Fn(usize) -> Option<u8>) -> Option<Instruction> {
+        let first = read(address)?;
+        let bytes = |count: usize| (0..count).map(|k| read(address + k)).collect::<Option<Vec<u8>>>();
+        let instruction = |mnemonic: &str, bytes: Vec<u8>, operands| Instruction {
+            address,
+            mnemonic: mnemonic.to_string(),
+            bytes,
+            operands,
+        };
+
+        // formats 1 and 2 use the whole first byte as the opcode
+        if let Some(name) = self.opcodes.get(&first) {
+            if FORMAT1.contains(&name.as_str()) {
+                return Some(instruction(name, vec![first], Operands::None));
+            }
+            if FORMAT2.contains(&name.as_str()) {
+                let bytes = bytes(2)?;
+                let operands = Operands::Registers(bytes[1] >> 4, bytes[1] & 0xF);
+                return Some(instruction(name, bytes, operands));
+            }
+        }
+
+        let name = self.opcodes.get(&(first & 0xFC))?;
+        if FORMAT1.contains(&name.as_str()) || FORMAT2.contains(&name.as_str()) {
+            return None;
+        }
+
+        if FORMAT4.contains(&name.as_str()) {
+            let bytes = bytes(4)?;
+            let operands = Operands::Conditional {
+                register: ((bytes[0] & 0x3) << 2) | (bytes[1] >> 6),
+                condition: (bytes[1] >> 4) & 0x3,
+                address: (((bytes[1] & 0xF) as usize) << 16) | ((bytes[2] as usize) << 8) | bytes[3] as usize,
+            };
+            return Some(instruction(name, bytes, operands));
+        }
+
+        let second = read(address + 1)?;
+        let (n, i) = (first & 0x2 != 0, first & 0x1 != 0);
+        let (x, b, p, e) = (second & 0x80 != 0, second & 0x40 != 0, second & 0x20 != 0, second & 0x10 != 0);
+        // SIC-format (ni = 00) instructions and contradictory bp/e bits
+        if (!n && !i) || (b && p) || (e && (b || p)) {
+            return None;
+        }
+
+        let bytes = bytes(if e { 4 } else { 3 })?;
+        let high = (second & 0xF) as usize;
+        let disp = if e {
+            (high << 16) | ((bytes[2] as usize) << 8) | bytes[3] as usize
+        } else {
+            (high << 8) | bytes[2] as usize
+        };
+        Some(instruction(name, bytes, Operands::Memory { n, i, x, b, p, e, disp }))
+    }
+}
+
+/// Turns an object program, as read by [`ObjectProgram::parse`], back into
+/// source that this assembler accepts.
+///
+/// Code is found by following control flow from the entry point, so data
+/// between instructions is not mistaken for code; everything else becomes
+/// `BYTE`/`WORD` data, and addresses no text record covers become
+/// `RESB`/`RESW`. Jump and data targets get synthesized labels (`L1006` for
+/// code, `D1047` for data), `BASE` is emitted after each `LDB #label`, and
+/// every line carries its address and object code as a comment. With
+/// `debug_info`, the comment also names the source line the code came from.
+pub fn disassemble(object_program: &str, debug_info: Option<&DebugInfo>) -> Result<String, String> {
+    let program = ObjectProgram::parse(object_program).map_err(|errors| {
+        errors.iter().map(ToString::to_string).collect::<Vec<_>>().join("\n")
+    })?;
+    let memory = program.memory();
+    let decoder = Decoder::new();
+    let read = |address: usize| memory.get(&address).copied();
+
+    let low = memory.keys().next().map_or(program.header.start, |&first| first.min(program.header.start));
+    let high = memory.keys().next_back()
+        .map_or(program.header.start + program.header.length, |&last| (last + 1).max(program.header.start + program.header.length));
+    let in_program = |address: usize| (low..high).contains(&address);
+
+    let code = trace_code(&decoder, program.entry.unwrap_or(program.header.start), &read);
+
+    // the base register as set by the latest LDB #label in address order
+    let mut bases = BTreeMap::new();
+    for instruction in code.values() {
+        if instruction.mnemonic == "LDB" {
+            if let (Operands::Memory { n: false, i: true, .. }, Some(target)) = (&instruction.operands, instruction.target(None)) {
+                bases.insert(instruction.address, target);
+            }
+        }
+    }
+    let base_at = |address: usize| bases.range(..address).next_back().map(|(_, &base)| base);
+
+    // labels may only go where a line starts, not inside an instruction
+    let inside_instruction = |address: usize| {
+        code.range(..address).next_back().is_some_and(|(_, ins)| address < ins.address + ins.size())
+    };
+    let mut labels: BTreeMap<usize, String> = BTreeMap::new();
+    let mut add_label = |address: usize| {
+        if in_program(address) && !inside_instruction(address) {
+            let prefix = if code.contains_key(&address) { "L" } else { "D" };
+            labels.entry(address).or_insert_with(|| format!("{}{:04X}", prefix, address));
+        }
+    };
+    if let Some(entry) = program.entry {
+        add_label(entry);
+    }
+    for instruction in code.values() {
+        // a format 4 immediate is usually a plain number unless it sets the base
+        let constant = matches!(instruction.operands, Operands::Memory { n: false, i: true, e: true, .. })
+            && instruction.mnemonic != "LDB";
+        if let (Some(target), false) = (instruction.target(base_at(instruction.address)), constant) {
+            add_label(target);
+        }
+    }
+
+    let mut out = String::new();
+    out.push_str(&statement(&program.header.name, "START", &format!("{:X}", low), ""));
+
+    let mut address = low;
+    while address < high {
+        let label = labels.get(&address).map_or("", String::as_str);
+
+        if let Some(instruction) = code.get(&address) {
+            let opcode = if instruction.is_format4() {
+                format!("+{}", instruction.mnemonic)
+            } else {
+                instruction.mnemonic.clone()
+            };
+            let operand = operand_text(instruction, base_at(address), &labels);
+            out.push_str(&statement(label, &opcode, &operand, &listing_comment(address, &instruction.bytes, debug_info)));
+
+            if let (Some(&base), "LDB") = (bases.get(&address), instruction.mnemonic.as_str()) {
+                if let Some(base_label) = labels.get(&base) {
+                    out.push_str(&statement("", "BASE", base_label, ""));
+                }
+            }
+            address += instruction.size();
+            continue;
+        }
+
+        // data or a gap runs until the next line that needs its own start
+        let has_data = memory.contains_key(&address);
+        let mut end = address + 1;
+        while end < high
+            && !labels.contains_key(&end)
+            && !code.contains_key(&end)
+            && memory.contains_key(&end) == has_data
+            && (!has_data || end - address < DATA_CHUNK)
+        {
+            end += 1;
+        }
+
+        if has_data {
+            let bytes: Vec<u8> = (address..end).map(|a| memory[&a]).collect();
+            let hex: String = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+            // text reads best as characters and a labelled three-byte item
+            // as a word
+            let (opcode, operand) = if bytes.len() > 1 && bytes.iter().all(|&b| is_source_char(b)) {
+                ("BYTE", format!("C'{}'", bytes.iter().map(|&b| b as char).collect::<String>()))
+            } else if !label.is_empty() && bytes.len() == 3 {
+                ("WORD", usize::from_str_radix(&hex, 16).unwrap_or(0).to_string())
+            } else {
+                ("BYTE", format!("X'{}'", hex))
+            };
+            out.push_str(&statement(label, opcode, &operand, &listing_comment(address, &bytes, debug_info)));
+        } else {
+            let size = end - address;
+            let (opcode, count) = if size % 3 == 0 { ("RESW", size / 3) } else { ("RESB", size) };
+            out.push_str(&statement(label, opcode, &count.to_string(), &format!("{:06X}", address)));
+        }
+        address = end;
+    }
+
+    let entry = program.entry.and_then(|entry| labels.get(&entry)).map_or("", String::as_str);
+    out.push_str(&statement("", "END", entry, ""));
+    Ok(out)
+}
+
+// decodes everything reachable from `entry`, following jumps, conditional
+// branches and subroutine calls
+fn trace_code(decoder: &Decoder, entry: usize, read: &dyn Fn(usize) -> Option<u8>) -> BTreeMap<usize, Instruction> {
+    let mut code: BTreeMap<usize, Instruction> = BTreeMap::new();
+    let mut pending = vec![entry];
+
+    while let Some(address) = pending.pop() {
+        if code.contains_key(&address) {
+            continue;
+        }
+        let Some(instruction) = decoder.decode(address, read) else {
+            continue;
+        };
+        let end = address + instruction.size();
+        // overlapping an instruction decoded from another path
+        let overlaps = code.range(..end).next_back().is_some_and(|(_, other)| other.address + other.size() > address);
+        if overlaps {
+            continue;
+        }
+
+        let target = if instruction.is_indirect() { None } else { instruction.target(None) };
+        match instruction.mnemonic.as_str() {
+            "J" => pending.extend(target),
+            "RSUB" => {}
+            "JEQ" | "JGT" | "JLT" | "JSUB" | "CJUMP" => {
+                pending.push(end);
+                pending.extend(target);
+            }
+            _ => pending.push(end),
+        }
+        code.insert(address, instruction);
+    }
+    code
+}
+
+fn operand_text(instruction: &Instruction, base: Option<usize>, labels: &BTreeMap<usize, String>) -> String {
+    let register = |value: u8| get_register_name(value).map_or_else(|| value.to_string(), str::to_string);
+
+    match instruction.operands {
+        Operands::None => String::new(),
+        Operands::Registers(r1, r2) => match instruction.mnemonic.as_str() {
+            "CLEAR" | "TIXR" => register(r1),
+            "SVC" => r1.to_string(),
+            "SHIFTL" | "SHIFTR" => format!("{},{}", register(r1), r2 + 1),
+            _ => format!("{},{}", register(r1), register(r2)),
+        },
+        Operands::Conditional { register: r, condition, address } => {
+            // Pass 2 reads a 4F address that is not a symbol as decimal
+            let memory = labels.get(&address).cloned().unwrap_or_else(|| address.to_string());
+            let flag = CONDITIONS[condition as usize];
+            if instruction.mnemonic == "CJUMP" {
+                format!("{},{}", memory, flag)
+            } else {
+                format!("{},{},{}", register(r), memory, flag)
+            }
+        }
+        Operands::Memory { n, i, x, b, p, e, disp } => {
+            if instruction.mnemonic == "RSUB" {
+                return String::new();
+            }
+            let prefix = match (n, i) {
+                (false, true) => "#",
+                (true, false) => "@",
+                _ => "",
+            };
+            let value = if b || p {
+                let target = instruction.target(base);
+                match target.and_then(|target| labels.get(&target)) {
+                    Some(label) => label.clone(),
+                    None => target.unwrap_or(disp).to_string(),
+                }
+            } else if e {
+                // format 4 numbers are read as decimal, symbols resolve directly
+                labels.get(&disp).cloned().unwrap_or_else(|| disp.to_string())
+            } else {
+                disp.to_string()
+            };
+            format!("{}{}{}", prefix, value, if x { ",X" } else { "" })
+        }
+    }
+}
+
+// characters that survive a trip through Pass 1 unchanged inside C'...':
+// source is upper-cased and split at whitespace and ';'
+fn is_source_char(byte: u8) -> bool {
+    byte.is_ascii_graphic() && !byte.is_ascii_lowercase() && byte != b'\'' && byte != b';'
+}
+
+fn listing_comment(address: usize, bytes: &[u8], debug_info: Option<&DebugInfo>) -> String {
+    let hex: String = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+    match debug_info.and_then(|info| info.lookup(address)) {
+        Some(range) => format!("{:06X}  {}  {}:{}", address, hex, range.file, range.line),
+        None => format!("{:06X}  {}", address, hex),
+    }
+}
+
+// one source line in the formatter's columns
+fn statement(label: &str, opcode: &str, operand: &str, comment: &str) -> String {
+    let mut line = label.to_string();
+    pad_to(&mut line, OPCODE_COLUMN);
+    line.push_str(opcode);
+    if !operand.is_empty() {
+        pad_to(&mut line, OPERAND_COLUMN);
+        line.push_str(operand);
+    }
+    if !comment.is_empty() {
+        pad_to(&mut line, COMMENT_COLUMN);
+        line.push_str("; ");
+        line.push_str(comment);
+    }
+    line.push('\n');
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::{assemble_text, AssemblyOptions};
+
+    const SOURCE: &str = "\
+PROG    START  0
+FIRST   +LDT   #4096
+        +LDA   #1000000
+        +JSUB  ROUT
+        CADD   A,VALUE,Z
+        CLOAD  A,1000,N
+        RSUB
+ROUT    LDA    VALUE
+        RSUB
+VALUE   WORD   7
+        END    FIRST
+";
+
+    #[test]
+    fn prints_format4_constants_in_decimal() {
+        let object_program = assemble_text(SOURCE, &AssemblyOptions::default()).unwrap().object_program;
+        let source = disassemble(&object_program, None).unwrap();
+        assert!(source.contains("+LDT   #4096 "));
+        assert!(source.contains("+LDA   #1000000 "));
+        assert!(source.contains("CLOAD  A,1000,N "));
+    }
+
+    #[test]
+    fn reassembles_to_the_same_object_program() {
+        let options = AssemblyOptions::default();
+        let object_program = assemble_text(SOURCE, &options).unwrap().object_program;
+        let source = disassemble(&object_program, None).unwrap();
+        assert_eq!(assemble_text(&source, &options).unwrap().object_program, object_program);
+    }
+}
@@ -0,0 +1,817 @@
+use serde::Serialize;
+use std::fmt;
+
+use crate::conversions::get_register_name;
+use crate::devices::{DeviceOutput, Devices};
+use crate::disassembler::{Decoder, Instruction, Operands};
+use crate::loader;
+use crate::object_program::ObjectProgram;
+
+/// Bytes of SIC/XE memory (1 MB, 20-bit addresses).
+pub const MEMORY_SIZE: usize = 1 << 20;
+/// What `L` holds when a program starts. Returning to it (`RSUB` from the
+/// main routine, `J @RETADR` after `STL RETADR`) ends the run.
+pub const RETURN_ADDRESS: u32 = 0xFFFFFF;
+pub const DEFAULT_STEP_LIMIT: u64 = 1_000_000;
+
+const WORD_MASK: u32 = 0xFFFFFF;
+const ADDRESS_MASK: usize = MEMORY_SIZE - 1;
+
+// SW layout: format 4F condition flags in bits 0-3 (indexed by the 4F
+// condition field), the COMP condition code in bits 6-7
+const FLAG_Z: u32 = 1 << 0;
+const FLAG_N: u32 = 1 << 1;
+const FLAG_C: u32 = 1 << 2;
+const FLAG_V: u32 = 1 << 3;
+const CC_SHIFT: u32 = 6;
+
+/// Result of the last comparison (`COMP`, `COMPR`, `COMPF`, `TIX`, `TIXR`)
+/// or device test (`TD`), as tested by `JLT`, `JEQ` and `JGT`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConditionCode {
+    Less,
+    Equal,
+    Greater,
+}
+
+/// The SIC/XE registers. Integer registers hold 24-bit values; `F` holds the
+/// 48-bit floating-point register as an `f64`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Registers {
+    pub a: u32,
+    pub x: u32,
+    pub l: u32,
+    pub b: u32,
+    pub s: u32,
+    pub t: u32,
+    pub f: f64,
+    pub pc: u32,
+    pub sw: u32,
+}
+
+impl Default for Registers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Registers {
+    pub fn new() -> Self {
+        Self { a: 0, x: 0, l: RETURN_ADDRESS, b: 0, s: 0, t: 0, f: 0.0, pc: 0, sw: 0 }
+    }
+
+    pub fn condition_code(&self) -> ConditionCode {
+        match (self.sw >> CC_SHIFT) & 0x3 {
+            0b01 => ConditionCode::Less,
+            0b10 => ConditionCode::Greater,
+            _ => ConditionCode::Equal,
+        }
+    }
+
+    fn set_condition_code(&mut self, code: ConditionCode) {
+        let bits = match code {
+            ConditionCode::Less => 0b01,
+            ConditionCode::Equal => 0b00,
+            ConditionCode::Greater => 0b10,
+        };
+        self.sw = (self.sw & !(0x3 << CC_SHIFT)) | (bits << CC_SHIFT);
+    }
+
+    /// Format 4F condition flag: 0-3 for Z, N, C, V.
+    pub fn flag(&self, condition: u8) -> bool {
+        self.sw & (1 << condition) != 0
+    }
+
+    fn set_flags(&mut self, result: u32, carry: bool, overflow: bool) {
+        let mut flags = 0;
+        if result == 0 {
+            flags |= FLAG_Z;
+        }
+        if result & 0x800000 != 0 {
+            flags |= FLAG_N;
+        }
+        if carry {
+            flags |= FLAG_C;
+        }
+        if overflow {
+            flags |= FLAG_V;
+        }
+        self.sw = (self.sw & !(FLAG_Z | FLAG_N | FLAG_C | FLAG_V)) | flags;
+    }
+
+    /// Integer register by its format 2 number.
+    pub fn get(&self, register: u8) -> Result<u32, String> {
+        match register {
+            0 => Ok(self.a),
+            1 => Ok(self.x),
+            2 => Ok(self.l),
+            3 => Ok(self.b),
+            4 => Ok(self.s),
+            5 => Ok(self.t),
+            8 => Ok(self.pc),
+            9 => Ok(self.sw),
+            _ => Err(register_error(register)),
+        }
+    }
+
+    pub fn set(&mut self, register: u8, value: u32) -> Result<(), String> {
+        let value = value & WORD_MASK;
+        match register {
+            0 => self.a = value,
+            1 => self.x = value,
+            2 => self.l = value,
+            3 => self.b = value,
+            4 => self.s = value,
+            5 => self.t = value,
+            8 => self.pc = value,
+            9 => self.sw = value,
+            _ => return Err(register_error(register)),
+        }
+        Ok(())
+    }
+}
+
+fn register_error(register: u8) -> String {
+    match get_register_name(register) {
+        Some(name) => format!("Register {} cannot be used as an integer register", name),
+        None => format!("Invalid register number {}", register),
+    }
+}
+
+/// Why a run stopped.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum Halt {
+    /// Control returned to [`RETURN_ADDRESS`]
+    Returned,
+    /// An instruction jumped to itself (`HALT J HALT`)
+    Loop { address: usize },
+    /// `SVC n`
+    SupervisorCall { code: u8 },
+    /// The step limit ran out first
+    StepLimit,
+    /// The instruction at `address` could not be executed
+    Fault { address: usize, message: String },
+}
+
+impl fmt::Display for Halt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Halt::Returned => write!(f, "returned"),
+            Halt::Loop { address } => write!(f, "halted at {:06X}", address),
+            Halt::SupervisorCall { code } => write!(f, "SVC {}", code),
+            Halt::StepLimit => write!(f, "step limit reached"),
+            Halt::Fault { address, .. } => write!(f, "fault at {:06X}", address),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct EmulatorOptions {
+    pub step_limit: u64,
+}
+
+impl Default for EmulatorOptions {
+    fn default() -> Self {
+        Self { step_limit: DEFAULT_STEP_LIMIT }
+    }
+}
+
+/// Final state of a run.
+#[derive(Debug, Clone, Serialize)]
+pub struct Run {
+    pub halt: Halt,
+    pub steps: u64,
+    pub registers: Registers,
+    /// What the program wrote, per device
+    pub output: Vec<DeviceOutput>,
+    #[serde(skip)]
+    pub memory: Vec<u8>,
+}
+
+/// Loads `program` into a fresh machine with `devices` attached and runs it
+/// from its entry point.
+pub fn run(program: &ObjectProgram, options: &EmulatorOptions, devices: Devices) -> Result<Run, String> {
+    let mut machine = Machine::new();
+    machine.devices = devices;
+    machine.load(program)?;
+    let halt = machine.run(options.step_limit);
+    Ok(machine.finish(halt))
+}
+
+/// A data read or write made by an instruction.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MemoryAccess {
+    pub address: usize,
+    /// The bytes read, or the bytes written
+    pub bytes: Vec<u8>,
+    pub write: bool,
+}
+
+// a format 3/4 operand after addressing modes are applied
+#[derive(Debug, Clone, Copy)]
+enum Operand {
+    Immediate(u32),
+    Memory(usize),
+}
+
+/// A SIC/XE machine: registers, 1 MB of memory, devices and a step counter.
+pub struct Machine {
+    pub registers: Registers,
+    pub memory: Vec<u8>,
+    pub devices: Devices,
+    pub steps: u64,
+    /// Interval timer as set by `STI`; nothing counts it down
+    pub timer: u32,
+    /// Whether `step` records `accesses`
+    pub record_accesses: bool,
+    /// Memory the last instruction read or wrote as data, in order, when
+    /// `record_accesses` is set; fetching the instruction is not included
+    pub accesses: Vec<MemoryAccess>,
+    decoder: Decoder,
+}
+
+impl Default for Machine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Machine {
+    pub fn new() -> Self {
+        Self {
+            registers: Registers::new(),
+            memory: vec![0; MEMORY_SIZE],
+            devices: Devices::new(),
+            steps: 0,
+            timer: 0,
+            record_accesses: false,
+            accesses: Vec::new(),
+            decoder: Decoder::new(),
+        }
+    }
+
+    /// Loads `program` at its own start address: text records are copied
+    /// into memory, `M` records relocate by the start address (or by a
+    /// symbol the program itself defines), and `PC` is set to the entry
+    /// point. Several control sections go through [`loader::link`] first.
+    pub fn load(&mut self, program: &ObjectProgram) -> Result<(), String> {
+        let linked = loader::link(std::slice::from_ref(program), program.header.start).map_err(|errors| {
+            errors.iter().map(ToString::to_string).collect::<Vec<_>>().join("\n")
+        })?;
+        for record in &linked.program.text {
+            self.write(record.address, &record.bytes)?;
+        }
+        self.registers.pc = linked.map.entry as u32;
+        Ok(())
+    }
+
+    /// The machine's final state, for a run that stopped with `halt`.
+    pub fn finish(self, halt: Halt) -> Run {
+        Run {
+            halt,
+            steps: self.steps,
+            registers: self.registers,
+            output: self.devices.output(),
+            memory: self.memory,
+        }
+    }
+
+    /// Runs at most `max_steps` instructions.
+    pub fn run(&mut self, max_steps: u64) -> Halt {
+        for _ in 0..max_steps {
+            if let Some(halt) = self.step() {
+                return halt;
+            }
+        }
+        Halt::StepLimit
+    }
+
+    /// Executes the instruction at `PC`. `Some` when the machine stopped.
+    pub fn step(&mut self) -> Option<Halt> {
+        let address = self.registers.pc as usize;
+        if self.registers.pc == RETURN_ADDRESS {
+            return Some(Halt::Returned);
+        }
+        self.accesses.clear();
+        let result = self.fetch(address).and_then(|instruction| {
+            self.registers.pc = ((address + instruction.size()) & ADDRESS_MASK) as u32;
+            self.execute(&instruction)
+        });
+        match result {
+            Ok(halt) => {
+                self.steps += 1;
+                if halt.is_some() {
+                    halt
+                } else if self.registers.pc == RETURN_ADDRESS {
+                    Some(Halt::Returned)
+                } else if self.registers.pc as usize == address {
+                    Some(Halt::Loop { address })
+                } else {
+                    None
+                }
+            }
+            Err(message) => {
+                self.registers.pc = address as u32;
+                Some(Halt::Fault { address, message })
+            }
+        }
+    }
+
+    /// Decodes the instruction at `address` without executing it.
+    pub fn fetch(&self, address: usize) -> Result<Instruction, String> {
+        let read = |a: usize| self.memory.get(a).copied();
+        if let Some(instruction) = self.decoder.decode(address, &read) {
+            return Ok(instruction);
+        }
+
+        // SIC format (n = i = 0): 15-bit address and the x bit. With n or i
+        // set the decoder only refuses contradictory b/p/e bits, which must
+        // fault rather than run as a SIC instruction.
+        let first = read(address).ok_or_else(|| outside_memory(address))?;
+        if first & 0x3 != 0 {
+            let second = read(address + 1).ok_or_else(|| outside_memory(address + 1))?;
+            return Err(format!("Invalid addressing flags in {:02X}{:02X} at {:06X}", first, second, address));
+        }
+        let invalid = || format!("Invalid instruction {:02X} at {:06X}", first, address);
+        let mnemonic = self.decoder.mnemonic(first).ok_or_else(invalid)?;
+        let bytes = self.read(address, 3)?;
+        let operands = Operands::Memory {
+            n: false,
+            i: false,
+            x: bytes[1] & 0x80 != 0,
+            b: false,
+            p: false,
+            e: false,
+            disp: (((bytes[1] & 0x7F) as usize) << 8) | bytes[2] as usize,
+        };
+        Ok(Instruction { address, mnemonic: mnemonic.to_string(), bytes, operands })
+    }
+
+    fn execute(&mut self, instruction: &Instruction) -> Result<Option<Halt>, String> {
+        match instruction.operands {
+            Operands::None => self.execute_format1(&instruction.mnemonic),
+            Operands::Registers(r1, r2) => return self.execute_format2(&instruction.mnemonic, r1, r2),
+            Operands::Memory { .. } => {
+                let operand = self.operand(instruction)?;
+                self.execute_memory(&instruction.mnemonic, operand)?;
+            }
+            Operands::Conditional { register, condition, address } => {
+                if self.registers.flag(condition) {
+                    self.execute_format4f(&instruction.mnemonic, register, address)?;
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    fn execute_format1(&mut self, mnemonic: &str) {
+        match mnemonic {
+            "FIX" => self.registers.a = (self.registers.f.trunc() as i64 as u32) & WORD_MASK,
+            "FLOAT" => self.registers.f = signed(self.registers.a) as f64,
+            // F is kept as an f64, which is always normalized
+            "NORM" => {}
+            // no I/O channels are attached: SIO and HIO do nothing and TIO
+            // reports the channel busy
+            "SIO" | "HIO" => {}
+            "TIO" => self.registers.set_condition_code(ConditionCode::Equal),
+            _ => {}
+        }
+    }
+
+    fn execute_format2(&mut self, mnemonic: &str, r1: u8, r2: u8) -> Result<Option<Halt>, String> {
+        match mnemonic {
+            "ADDR" => {
+                let sum = self.add(self.registers.get(r2)?, self.registers.get(r1)?);
+                self.registers.set(r2, sum)?;
+            }
+            "SUBR" => {
+                let difference = self.subtract(self.registers.get(r2)?, self.registers.get(r1)?);
+                self.registers.set(r2, difference)?;
+            }
+            "MULR" => {
+                let product = self.multiply(self.registers.get(r2)?, self.registers.get(r1)?);
+                self.registers.set(r2, product)?;
+            }
+            "DIVR" => {
+                let quotient = self.divide(self.registers.get(r2)?, self.registers.get(r1)?)?;
+                self.registers.set(r2, quotient)?;
+            }
+            "COMPR" => self.compare(self.registers.get(r1)?, self.registers.get(r2)?),
+            "CLEAR" => self.registers.set(r1, 0)?,
+            "RMO" => self.registers.set(r2, self.registers.get(r1)?)?,
+            // the second nibble holds n - 1
+            "SHIFTL" => {
+                let value = self.registers.get(r1)?;
+                let n = (r2 as u32 + 1) % 24;
+                self.registers.set(r1, (value << n) | (value >> (24 - n)))?;
+            }
+            "SHIFTR" => {
+                let value = signed(self.registers.get(r1)?);
+                self.registers.set(r1, (value >> (r2 as u32 + 1).min(23)) as u32)?;
+            }
+            "TIXR" => {
+                self.registers.x = (self.registers.x + 1) & WORD_MASK;
+                self.compare(self.registers.x, self.registers.get(r1)?);
+            }
+            "SVC" => return Ok(Some(Halt::SupervisorCall { code: r1 })),
+            _ => return Err(format!("Unknown register instruction {}", mnemonic)),
+        }
+        Ok(None)
+    }
+
+    fn execute_memory(&mut self, mnemonic: &str, operand: Operand) -> Result<(), String> {
+        match mnemonic {
+            "LDA" => self.registers.a = self.load_word(operand)?,
+            "LDX" => self.registers.x = self.load_word(operand)?,
+            "LDL" => self.registers.l = self.load_word(operand)?,
+            "LDB" => self.registers.b = self.load_word(operand)?,
+            "LDS" => self.registers.s = self.load_word(operand)?,
+            "LDT" => self.registers.t = self.load_word(operand)?,
+            "LDF" => self.registers.f = self.load_float(operand)?,
+            "LDCH" => self.registers.a = (self.registers.a & !0xFF) | self.load_byte(operand)? as u32,
+            "STA" => self.store_word(operand, self.registers.a)?,
+            "STX" => self.store_word(operand, self.registers.x)?,
+            "STL" => self.store_word(operand, self.registers.l)?,
+            "STB" => self.store_word(operand, self.registers.b)?,
+            "STS" => self.store_word(operand, self.registers.s)?,
+            "STT" => self.store_word(operand, self.registers.t)?,
+            "STSW" => self.store_word(operand, self.registers.sw)?,
+            "STF" => {
+                let address = store_address(operand)?;
+                self.store(address, &float_to_bytes(self.registers.f))?;
+            }
+            "STCH" => {
+                let address = store_address(operand)?;
+                self.store(address, &[self.registers.a as u8])?;
+            }
+            "ADD" => {
+                let value = self.load_word(operand)?;
+                self.registers.a = self.add(self.registers.a, value);
+            }
+            "SUB" => {
+                let value = self.load_word(operand)?;
+                self.registers.a = self.subtract(self.registers.a, value);
+            }
+            "MUL" => {
+                let value = self.load_word(operand)?;
+                self.registers.a = self.multiply(self.registers.a, value);
+            }
+            "DIV" => {
+                let value = self.load_word(operand)?;
+                self.registers.a = self.divide(self.registers.a, value)?;
+            }
+            "AND" => self.registers.a &= self.load_word(operand)?,
+            "OR" => self.registers.a |= self.load_word(operand)?,
+            "COMP" => {
+                let value = self.load_word(operand)?;
+                self.compare(self.registers.a, value);
+            }
+            "TIX" => {
+                self.registers.x = (self.registers.x + 1) & WORD_MASK;
+                let value = self.load_word(operand)?;
+                self.compare(self.registers.x, value);
+            }
+            "ADDF" => self.registers.f += self.load_float(operand)?,
+            "SUBF" => self.registers.f -= self.load_float(operand)?,
+            "MULF" => self.registers.f *= self.load_float(operand)?,
+            "DIVF" => {
+                let divisor = self.load_float(operand)?;
+                if divisor == 0.0 {
+                    return Err("Division by zero".to_string());
+                }
+                self.registers.f /= divisor;
+            }
+            "COMPF" => {
+                let value = self.load_float(operand)?;
+                let code = match self.registers.f.partial_cmp(&value) {
+                    Some(std::cmp::Ordering::Less) => ConditionCode::Less,
+                    Some(std::cmp::Ordering::Greater) => ConditionCode::Greater,
+                    _ => ConditionCode::Equal,
+                };
+                self.registers.set_condition_code(code);
+            }
+            "J" => self.registers.pc = jump_address(operand),
+            "JEQ" => self.jump_if(ConditionCode::Equal, operand),
+            "JGT" => self.jump_if(ConditionCode::Greater, operand),
+            "JLT" => self.jump_if(ConditionCode::Less, operand),
+            "JSUB" => {
+                self.registers.l = self.registers.pc;
+                self.registers.pc = jump_address(operand);
+            }
+            "RSUB" => self.registers.pc = self.registers.l,
+            // `<` means ready, `=` busy
+            "TD" => {
+                let device = self.load_byte(operand)?;
+                let ready = self.devices.test(device);
+                self.registers.set_condition_code(if ready { ConditionCode::Less } else { ConditionCode::Equal });
+            }
+            "RD" => {
+                let device = self.load_byte(operand)?;
+                let byte = self.devices.read(device)?;
+                self.registers.a = (self.registers.a & !0xFF) | byte as u32;
+            }
+            "WD" => {
+                let device = self.load_byte(operand)?;
+                self.devices.write(device, self.registers.a as u8)?;
+            }
+            "LPS" => {
+                let address = store_address(operand)?;
+                self.registers.sw = self.load_word_at(address)?;
+                self.registers.pc = self.load_word_at(address + 3)? & ADDRESS_MASK as u32;
+            }
+            "STI" => self.timer = self.load_word(operand)?,
+            // no storage protection keys
+            "SSK" => {}
+            _ => return Err(format!("Unknown instruction {}", mnemonic)),
+        }
+        Ok(())
+    }
+
+    fn execute_format4f(&mut self, mnemonic: &str, register: u8, address: usize) -> Result<(), String> {
+        match mnemonic {
+            "CADD" => {
+                let value = self.load_word_at(address)?;
+                let sum = self.add(self.registers.get(register)?, value);
+                self.registers.set(register, sum)?;
+            }
+            "CSUB" => {
+                let value = self.load_word_at(address)?;
+                let difference = self.subtract(self.registers.get(register)?, value);
+                self.registers.set(register, difference)?;
+            }
+            "CLOAD" => {
+                let value = self.load_word_at(address)?;
+                self.registers.set(register, value)?;
+            }
+            "CSTORE" => self.store_word_at(address, self.registers.get(register)?)?,
+            "CJUMP" => self.registers.pc = address as u32,
+            _ => return Err(format!("Unknown format 4F instruction {}", mnemonic)),
+        }
+        Ok(())
+    }
+
+    // target address with PC/base-relative displacement, indexing and
+    // indirection applied; immediate operands carry their value
+    fn operand(&mut self, instruction: &Instruction) -> Result<Operand, String> {
+        let Operands::Memory { n, i, x, b, p, e, disp } = instruction.operands else {
+            return Err("Instruction has no memory operand".to_string());
+        };
+        let mut target = if p && !e {
+            let offset = if disp & 0x800 != 0 { disp as i64 - 0x1000 } else { disp as i64 };
+            (self.registers.pc as i64 + offset) as usize
+        } else if b && !e {
+            self.registers.b as usize + disp
+        } else {
+            disp
+        };
+        if x {
+            target += self.registers.x as usize;
+        }
+        let target = target & ADDRESS_MASK;
+
+        Ok(match (n, i) {
+            (false, true) => Operand::Immediate(target as u32),
+            // not masked, so `J @RETADR` can reach RETURN_ADDRESS
+            (true, false) => Operand::Memory(self.load_word_at(target)? as usize),
+            _ => Operand::Memory(target),
+        })
+    }
+
+    fn jump_if(&mut self, code: ConditionCode, operand: Operand) {
+        if self.registers.condition_code() == code {
+            self.registers.pc = jump_address(operand);
+        }
+    }
+
+    fn add(&mut self, left: u32, right: u32) -> u32 {
+        let sum = left + right;
+        let result = sum & WORD_MASK;
+        let overflow = sign(left) == sign(right) && sign(result) != sign(left);
+        self.registers.set_flags(result, sum > WORD_MASK, overflow);
+        result
+    }
+
+    fn subtract(&mut self, left: u32, right: u32) -> u32 {
+        let result = left.wrapping_sub(right) & WORD_MASK;
+        let overflow = sign(left) != sign(right) && sign(result) != sign(left);
+        self.registers.set_flags(result, right > left, overflow);
+        result
+    }
+
+    fn multiply(&mut self, left: u32, right: u32) -> u32 {
+        let product = signed(left) as i64 * signed(right) as i64;
+        let result = product as u32 & WORD_MASK;
+        self.registers.set_flags(result, false, product != signed(result) as i64);
+        result
+    }
+
+    fn divide(&mut self, left: u32, right: u32) -> Result<u32, String> {
+        if right == 0 {
+            return Err("Division by zero".to_string());
+        }
+        let quotient = signed(left) as i64 / signed(right) as i64;
+        let result = quotient as u32 & WORD_MASK;
+        self.registers.set_flags(result, false, quotient != signed(result) as i64);
+        Ok(result)
+    }
+
+    // sets the condition code from a signed comparison and the 4F flags as
+    // for `left - right`
+    fn compare(&mut self, left: u32, right: u32) {
+        self.subtract(left, right);
+        let code = match signed(left).cmp(&signed(right)) {
+            std::cmp::Ordering::Less => ConditionCode::Less,
+            std::cmp::Ordering::Equal => ConditionCode::Equal,
+            std::cmp::Ordering::Greater => ConditionCode::Greater,
+        };
+        self.registers.set_condition_code(code);
+    }
+
+    fn load_word(&mut self, operand: Operand) -> Result<u32, String> {
+        match operand {
+            Operand::Immediate(value) => Ok(value),
+            Operand::Memory(address) => self.load_word_at(address),
+        }
+    }
+
+    fn load_byte(&mut self, operand: Operand) -> Result<u8, String> {
+        match operand {
+            Operand::Immediate(value) => Ok(value as u8),
+            Operand::Memory(address) => Ok(self.load_bytes(address, 1)?[0]),
+        }
+    }
+
+    fn load_float(&mut self, operand: Operand) -> Result<f64, String> {
+        match operand {
+            Operand::Immediate(_) => Err("Floating-point instructions need a memory operand".to_string()),
+            Operand::Memory(address) => Ok(float_from_bytes(&self.load_bytes(address, 6)?)),
+        }
+    }
+
+    fn store_word(&mut self, operand: Operand, value: u32) -> Result<(), String> {
+        self.store_word_at(store_address(operand)?, value)
+    }
+
+    // data reads and writes made by instructions, recorded in `accesses`;
+    // `read` and `write` are for looking at and loading memory
+    fn load_bytes(&mut self, address: usize, len: usize) -> Result<Vec<u8>, String> {
+        let bytes = self.read(address, len)?;
+        if self.record_accesses {
+            self.accesses.push(MemoryAccess { address, bytes: bytes.clone(), write: false });
+        }
+        Ok(bytes)
+    }
+
+    fn load_word_at(&mut self, address: usize) -> Result<u32, String> {
+        let bytes = self.load_bytes(address, 3)?;
+        Ok(((bytes[0] as u32) << 16) | ((bytes[1] as u32) << 8) | bytes[2] as u32)
+    }
+
+    fn store(&mut self, address: usize, bytes: &[u8]) -> Result<(), String> {
+        self.write(address, bytes)?;
+        if self.record_accesses {
+            self.accesses.push(MemoryAccess { address, bytes: bytes.to_vec(), write: true });
+        }
+        Ok(())
+    }
+
+    fn store_word_at(&mut self, address: usize, value: u32) -> Result<(), String> {
+        self.store(address, &[(value >> 16) as u8, (value >> 8) as u8, value as u8])
+    }
+
+    pub fn read(&self, address: usize, len: usize) -> Result<Vec<u8>, String> {
+        self.memory.get(address..address + len)
+            .map(<[u8]>::to_vec)
+            .ok_or_else(|| outside_memory(address))
+    }
+
+    pub fn read_word(&self, address: usize) -> Result<u32, String> {
+        let bytes = self.read(address, 3)?;
+        Ok(((bytes[0] as u32) << 16) | ((bytes[1] as u32) << 8) | bytes[2] as u32)
+    }
+
+    pub fn write(&mut self, address: usize, bytes: &[u8]) -> Result<(), String> {
+        self.memory.get_mut(address..address + bytes.len())
+            .ok_or_else(|| outside_memory(address))?
+            .copy_from_slice(bytes);
+        Ok(())
+    }
+
+    pub fn write_word(&mut self, address: usize, value: u32) -> Result<(), String> {
+        self.write(address, &[(value >> 16) as u8, (value >> 8) as u8, value as u8])
+    }
+}
+
+fn outside_memory(address: usize) -> String {
+    format!("Address {:06X} is outside memory", address)
+}
+
+fn store_address(operand: Operand) -> Result<usize, String> {
+    match operand {
+        Operand::Immediate(_) => Err("Cannot store to an immediate operand".to_string()),
+        Operand::Memory(address) => Ok(address),
+    }
+}
+
+fn jump_address(operand: Operand) -> u32 {
+    match operand {
+        Operand::Immediate(value) => value,
+        Operand::Memory(address) => address as u32,
+    }
+}
+
+fn sign(value: u32) -> bool {
+    value & 0x800000 != 0
+}
+
+// a 24-bit word as a signed integer
+fn signed(value: u32) -> i32 {
+    ((value << 8) as i32) >> 8
+}
+
+// 48-bit SIC/XE float: sign bit, 11-bit exponent biased by 1024 and a
+// 36-bit fraction f with value 0.f * 2^(exponent - 1024)
+fn float_from_bytes(bytes: &[u8]) -> f64 {
+    let bits = bytes.iter().fold(0u64, |acc, &b| (acc << 8) | b as u64);
+    let fraction = bits & ((1 << 36) - 1);
+    if fraction == 0 {
+        return 0.0;
+    }
+    let exponent = ((bits >> 36) & 0x7FF) as i32 - 1024;
+    let value = fraction as f64 / (1u64 << 36) as f64 * 2f64.powi(exponent);
+    if bits & (1 << 47) != 0 { -value } else { value }
+}
+
+fn float_to_bytes(value: f64) -> [u8; 6] {
+    let mut bits = 0u64;
+    if value != 0.0 && value.is_finite() {
+        // normalize to 0.5 <= fraction < 1
+        let mut exponent = value.abs().log2().floor() as i32 + 1;
+        let mut fraction = (value.abs() / 2f64.powi(exponent) * (1u64 << 36) as f64).round() as u64;
+        if fraction >= 1 << 36 {
+            fraction >>= 1;
+            exponent += 1;
+        } else if fraction < 1 << 35 {
+            fraction <<= 1;
+            exponent -= 1;
+        }
+        let biased = (exponent + 1024).clamp(0, 0x7FF) as u64;
+        bits = (biased << 36) | fraction;
+        if value < 0.0 {
+            bits |= 1 << 47;
+        }
+    }
+    let bytes = bits.to_be_bytes();
+    [bytes[2], bytes[3], bytes[4], bytes[5], bytes[6], bytes[7]]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::{assemble_text, AssemblyOptions};
+
+    fn run_source(source: &str) -> Run {
+        let assembly = assemble_text(source, &AssemblyOptions::default()).unwrap();
+        let program = ObjectProgram::parse(&assembly.object_program).unwrap();
+        run(&program, &EmulatorOptions::default(), Devices::new()).unwrap()
+    }
+
+    #[test]
+    fn divides_floats() {
+        let run = run_source("\
+PROG    START  0
+FIRST   LDA    #2
+        FLOAT
+        STF    TWO
+        LDA    #7
+        FLOAT
+        DIVF   TWO
+        STF    HALF
+        FIX
+        RSUB
+TWO     RESB   6
+HALF    RESB   6
+        END    FIRST
+");
+        assert_eq!(run.halt, Halt::Returned);
+        assert_eq!(run.registers.f, 3.5);
+        assert_eq!(run.registers.a, 3);
+    }
+
+    #[test]
+    fn faults_on_invalid_addressing_flags() {
+        let mut machine = Machine::new();
+        // LDA with both b and p set
+        machine.write(0, &[0x03, 0x60, 0x00]).unwrap();
+        assert!(matches!(machine.run(10), Halt::Fault { address: 0, .. }));
+
+        // n=i=0 is a SIC instruction with a 15-bit address
+        let mut machine = Machine::new();
+        machine.write(0, &[0x00, 0x00, 0x06, 0x4C, 0x00, 0x00, 0x00, 0x00, 0x2A]).unwrap();
+        machine.registers.l = RETURN_ADDRESS;
+        assert_eq!(machine.run(10), Halt::Returned);
+        assert_eq!(machine.registers.a, 0x2A);
+    }
+}
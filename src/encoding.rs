@@ -0,0 +1,250 @@
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::conversions::get_register_value;
+use crate::pass2::Pass2;
+use crate::source::SourceLocation;
+
+/// One bit field of an instruction's object code.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct EncodingField {
+    /// `opcode`, `n`, `i`, `x`, `b`, `p`, `e`, `disp`, `address`, `r1`,
+    /// `r2`, `r` or `cond`
+    pub name: String,
+    /// The field's bits, most significant first
+    pub bits: String,
+    /// Why the field has this value
+    pub reason: String,
+}
+
+/// How one instruction's object code was built. Its fields, in order, make
+/// up the object code's bits.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct Encoding {
+    pub address: usize,
+    pub location: Option<SourceLocation>,
+    /// Mnemonic and operand as written, e.g. `+JSUB RDREC`
+    pub statement: String,
+    /// `1`, `2`, `3`, `4`, or `4F` for the conditional format 4 instructions
+    pub format: String,
+    pub fields: Vec<EncodingField>,
+    /// Memory address the operand refers to, for formats 3, 4 and 4F
+    pub target: Option<usize>,
+    /// How the displacement was worked out, for format 3
+    pub displacement: Option<String>,
+    pub object_code: String,
+}
+
+/// Explains the object code Pass 2 generated for instruction `instr` at
+/// `address`, reusing the addressing-mode and displacement logic it was
+/// generated with. `None` for directives and literals.
+pub fn explain(pass2: &Pass2, instr: &str, operand: &str, address: usize, object_code: &str) -> Option<Encoding> {
+    let mnemonic = instr.trim_start_matches('+');
+    let opcode = u8::from_str_radix(&pass2.get_opcode(mnemonic)?, 16).ok()?;
+    let code = u64::from_str_radix(object_code, 16).ok()?;
+    let width = object_code.len() * 4;
+    let bits = |from: usize, len: usize| format!("{:0len$b}", (code >> (width - from - len)) & ((1 << len) - 1), len = len);
+    let field = |name: &str, from: usize, len: usize, reason: String| EncodingField { name: name.to_string(), bits: bits(from, len), reason };
+
+    let mut encoding = Encoding {
+        address,
+        location: None,
+        statement: if operand == "&" { instr.to_string() } else { format!("{} {}", instr, operand) },
+        format: String::new(),
+        fields: Vec::new(),
+        target: None,
+        displacement: None,
+        object_code: object_code.to_string(),
+    };
+
+    if pass2.is_format4f(instr) {
+        encoding.format = "4F".to_string();
+        let target = (code & 0xFFFFF) as usize;
+        encoding.target = Some(target);
+        let parts: Vec<&str> = operand.split(',').map(str::trim).collect();
+        let (register, memory, condition) = if mnemonic == "CJUMP" {
+            (None, parts.first().copied().unwrap_or(""), parts.get(1).copied().unwrap_or(""))
+        } else {
+            (parts.first().copied(), parts.get(1).copied().unwrap_or(""), parts.get(2).copied().unwrap_or(""))
+        };
+        encoding.fields = vec![
+            field("opcode", 0, 6, format!("{} is {:02X}; the top 6 bits are kept", mnemonic, opcode)),
+            field("r", 6, 4, match register {
+                Some(register) => format!("{} is register {}", register, get_register_value(register)),
+                None => "CJUMP has no register".to_string(),
+            }),
+            field("cond", 10, 2, format!("condition flag {} (Z=00, N=01, C=10, V=11)", if condition.is_empty() { "Z" } else { condition })),
+            field("address", 12, 20, address_reason(pass2, memory, target)),
+        ];
+        return Some(encoding);
+    }
+
+    match pass2.detect_instruction_format(instr) {
+        1 => {
+            encoding.format = "1".to_string();
+            encoding.fields = vec![field("opcode", 0, 8, format!("{} is {:02X}", mnemonic, opcode))];
+        }
+        2 => {
+            encoding.format = "2".to_string();
+            let mut registers = operand.split(',').map(str::trim);
+            let register_reason = |register: Option<&str>| match register {
+                Some(register) if register != "&" && !register.is_empty() => {
+                    format!("{} is register {}", register, get_register_value(register))
+                }
+                _ => "no second register: 0".to_string(),
+            };
+            encoding.fields = vec![
+                field("opcode", 0, 8, format!("{} is {:02X}", mnemonic, opcode)),
+                field("r1", 8, 4, register_reason(registers.next())),
+                field("r2", 12, 4, register_reason(registers.next())),
+            ];
+        }
+        format => {
+            let extended = format == 4;
+            encoding.format = format.to_string();
+            let (immediate, indirect, indexed) = pass2.detect_addressing_mode(operand);
+            let (n_reason, i_reason) = if immediate {
+                ("operand starts with #: immediate, so n=0", "immediate: i=1")
+            } else if indirect {
+                ("indirect: n=1", "operand starts with @: indirect, so i=0")
+            } else {
+                ("simple addressing: n=1", "simple addressing: i=1")
+            };
+            let x_reason = if indexed { "operand ends in ,X: indexed" } else { "not indexed" };
+            let name = operand.trim_start_matches('#').trim_start_matches('@').trim_end_matches(",X").trim();
+
+            let mut fields = vec![
+                field("opcode", 0, 6, format!("{} is {:02X}; the top 6 bits are kept", mnemonic, opcode)),
+                field("n", 6, 1, n_reason.to_string()),
+                field("i", 7, 1, i_reason.to_string()),
+                field("x", 8, 1, x_reason.to_string()),
+            ];
+            if extended {
+                let target = (code & 0xFFFFF) as usize;
+                encoding.target = Some(target);
+                fields.push(field("b", 9, 1, "format 4 uses a direct address, not base-relative".to_string()));
+                fields.push(field("p", 10, 1, "format 4 uses a direct address, not PC-relative".to_string()));
+                fields.push(field("e", 11, 1, "+ prefix: format 4 with a 20-bit address".to_string()));
+                fields.push(field("address", 12, 20, address_reason(pass2, name, target)));
+            } else {
+                let (b_reason, p_reason, displacement) = displacement_reasons(pass2, operand, name, address, &mut encoding.target)?;
+                encoding.displacement = Some(displacement.clone());
+                fields.push(field("b", 9, 1, b_reason));
+                fields.push(field("p", 10, 1, p_reason));
+                fields.push(field("e", 11, 1, "no + prefix: format 3".to_string()));
+                fields.push(field("disp", 12, 12, displacement));
+            }
+            encoding.fields = fields;
+        }
+    }
+    Some(encoding)
+}
+
+// b and p reasons and the displacement working for a format 3 operand, as
+// decided by `Pass2::calculate_displacement`
+fn displacement_reasons(
+    pass2: &Pass2,
+    operand: &str,
+    name: &str,
+    address: usize,
+    target: &mut Option<usize>,
+) -> Option<(String, String, String)> {
+    let (disp, use_pc, use_base) = pass2.calculate_displacement(operand, address, pass2.base_addr)?;
+    let pc = address + 3;
+
+    if name == "&" {
+        return Some((
+            "no operand: not base-relative".to_string(),
+            "no operand: not PC-relative".to_string(),
+            "no operand: displacement 0".to_string(),
+        ));
+    }
+    if use_pc {
+        let value = (pc as i32 + disp) as usize;
+        *target = Some(value);
+        return Some((
+            "PC-relative reaches, so base-relative is not needed".to_string(),
+            format!("target {:04X} is within -2048..2047 of PC {:04X}: PC-relative", value, pc),
+            format!("target {:04X} - PC {:04X} = {}", value, pc, signed_hex(disp)),
+        ));
+    }
+    if use_base {
+        let base = pass2.base_addr?;
+        let value = base + disp as usize;
+        *target = Some(value);
+        return Some((
+            format!("BASE {:04X} gives a displacement in 0..4095: base-relative", base),
+            format!("target {:04X} is more than 2048 bytes from PC {:04X}", value, pc),
+            format!("target {:04X} - BASE {:04X} = {}", value, base, signed_hex(disp)),
+        ));
+    }
+
+    let constant = pass2.absolute_symbols.contains(name) || name.parse::<i32>().is_ok();
+    if constant {
+        let fits = if (0..=0xFFF).contains(&disp) { "" } else { ", truncated to 12 bits; use + for format 4" };
+        return Some((
+            format!("{} is a constant, not an address: not base-relative", name),
+            format!("{} is a constant, not an address: not PC-relative", name),
+            format!("{} is used as the displacement: {}{}", name, signed_hex(disp), fits),
+        ));
+    }
+    let value = (pc as i32 + disp) as usize;
+    *target = Some(value);
+    let base = match pass2.base_addr {
+        Some(base) => format!("target {:04X} is not within 0..4095 of BASE {:04X}", value, base),
+        None => "no BASE is in effect".to_string(),
+    };
+    Some((
+        base,
+        format!("target {:04X} is not within -2048..2047 of PC {:04X}", value, pc),
+        format!("target {:04X} - PC {:04X} = {}, which does not fit in 12 bits; use + for format 4", value, pc, signed_hex(disp)),
+    ))
+}
+
+// where a format 4 or 4F address came from
+fn address_reason(pass2: &Pass2, name: &str, target: usize) -> String {
+    if name.starts_with('=') {
+        format!("literal {} is at {:05X}", name, target)
+    } else if pass2.symbol_table.contains_key(name) {
+        format!("{} is at {:05X}", name, target)
+    } else {
+        // numeric operands are decimal, as Pass 2 reads them
+        format!("constant {} is {:05X}", name, target)
+    }
+}
+
+// a displacement and, when negative, the 12 bits that hold it, e.g. `-3 (FFD)`
+fn signed_hex(disp: i32) -> String {
+    if disp < 0 {
+        format!("-{:X} ({:03X})", -disp, disp & 0xFFF)
+    } else {
+        format!("{:03X}", disp)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn explains_format4_constants_as_written() {
+        let pass2 = Pass2::new();
+        let encoding = explain(&pass2, "+LDT", "#4096", 0, "75101000").unwrap();
+        assert_eq!(encoding.format, "4");
+        assert_eq!(encoding.target, Some(0x1000));
+        let address = encoding.fields.iter().find(|field| field.name == "address").unwrap();
+        assert_eq!(address.bits, "00000001000000000000");
+        assert_eq!(address.reason, "constant 4096 is 01000");
+    }
+
+    #[test]
+    fn explains_pc_relative_displacements() {
+        let mut pass2 = Pass2::new();
+        pass2.symbol_table.insert("VALUE".to_string(), "000010".to_string());
+        let encoding = explain(&pass2, "LDA", "VALUE", 0, "03200D").unwrap();
+        assert_eq!(encoding.target, Some(0x10));
+        let p = encoding.fields.iter().find(|field| field.name == "p").unwrap();
+        assert_eq!(p.bits, "1");
+        assert!(encoding.displacement.unwrap().contains("00D"));
+    }
+}
@@ -0,0 +1,199 @@
+/// Evaluates an operand expression such as `FIRST`, `BUFEND-BUFFER` or
+/// `TABLE+3*2`. Terms are decimal numbers or symbols resolved through
+/// `lookup`; `+ - * /` follow the usual precedence and parentheses group.
+pub fn evaluate(expr: &str, lookup: &dyn Fn(&str) -> Option<i64>) -> Result<i64, String> {
+    let tokens = tokenize(expr)?;
+    let mut parser = Parser { tokens: &tokens, pos: 0, lookup };
+    let value = parser.parse_sum()?;
+    match parser.tokens.get(parser.pos) {
+        None => Ok(value),
+        Some(token) => Err(format!("Unexpected '{}' in expression '{}'", token, expr)),
+    }
+}
+
+/// Net number of relative terms in `expr`: 1 for a relative (relocatable)
+/// expression, 0 for an absolute one; anything else is not a legal operand.
+/// Found by shifting every relative symbol and seeing how far the result moves.
+pub fn relative_terms(expr: &str, lookup: &dyn Fn(&str) -> Option<(i64, bool)>) -> Result<i64, String> {
+    const SHIFT: i64 = 1 << 24;
+    let base = evaluate(expr, &|name| lookup(name).map(|(value, _)| value))?;
+    let shifted = evaluate(expr, &|name| {
+        lookup(name).map(|(value, relative)| if relative { value.saturating_add(SHIFT) } else { value })
+    })?;
+    Ok(shifted.checked_sub(base).ok_or_else(overflow)? / SHIFT)
+}
+
+fn overflow() -> String {
+    "Expression overflows".to_string()
+}
+
+/// Symbols referenced by `expr`, in order of appearance.
+pub fn symbols(expr: &str) -> Vec<String> {
+    tokenize(expr)
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|token| match token {
+            Token::Symbol(name) => Some(name),
+            _ => None,
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(i64),
+    Symbol(String),
+    Op(char),
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Token::Number(n) => write!(f, "{}", n),
+            Token::Symbol(s) => write!(f, "{}", s),
+            Token::Op(c) => write!(f, "{}", c),
+        }
+    }
+}
+
+fn tokenize(expr: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = expr.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if "+-*/()".contains(c) {
+            tokens.push(Token::Op(c));
+            i += 1;
+        } else if c.is_ascii_digit() {
+            let start = i;
+            while i < chars.len() && chars[i].is_ascii_digit() {
+                i += 1;
+            }
+            let text: String = chars[start..i].iter().collect();
+            let value = text.parse::<i64>().map_err(|_| format!("Number '{}' is too large", text))?;
+            tokens.push(Token::Number(value));
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push(Token::Symbol(chars[start..i].iter().collect()));
+        } else {
+            return Err(format!("Invalid character '{}' in expression '{}'", c, expr));
+        }
+    }
+
+    if tokens.is_empty() {
+        return Err("Empty expression".to_string());
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+    lookup: &'a dyn Fn(&str) -> Option<i64>,
+}
+
+impl Parser<'_> {
+    fn parse_sum(&mut self) -> Result<i64, String> {
+        let mut value = self.parse_product()?;
+        while let Some(Token::Op(op @ ('+' | '-'))) = self.tokens.get(self.pos) {
+            self.pos += 1;
+            let rhs = self.parse_product()?;
+            let sum = if *op == '+' { value.checked_add(rhs) } else { value.checked_sub(rhs) };
+            value = sum.ok_or_else(overflow)?;
+        }
+        Ok(value)
+    }
+
+    fn parse_product(&mut self) -> Result<i64, String> {
+        let mut value = self.parse_term()?;
+        while let Some(Token::Op(op @ ('*' | '/'))) = self.tokens.get(self.pos) {
+            self.pos += 1;
+            let rhs = self.parse_term()?;
+            value = if *op == '*' {
+                value.checked_mul(rhs).ok_or_else(overflow)?
+            } else if rhs == 0 {
+                return Err("Division by zero in expression".to_string());
+            } else {
+                value.checked_div(rhs).ok_or_else(overflow)?
+            };
+        }
+        Ok(value)
+    }
+
+    fn parse_term(&mut self) -> Result<i64, String> {
+        let token = self.tokens.get(self.pos).ok_or("Expression ends unexpectedly")?;
+        self.pos += 1;
+
+        match token {
+            Token::Number(n) => Ok(*n),
+            Token::Symbol(name) => {
+                (self.lookup)(name).ok_or_else(|| format!("Undefined symbol '{}'", name))
+            }
+            Token::Op('-') => self.parse_term()?.checked_neg().ok_or_else(overflow),
+            Token::Op('(') => {
+                let value = self.parse_sum()?;
+                match self.tokens.get(self.pos) {
+                    Some(Token::Op(')')) => {
+                        self.pos += 1;
+                        Ok(value)
+                    }
+                    _ => Err("Missing ')' in expression".to_string()),
+                }
+            }
+            Token::Op(c) => Err(format!("Unexpected '{}' in expression", c)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn symbol(name: &str) -> Option<i64> {
+        match name {
+            "TABLE" => Some(0x1000),
+            "BUFEND" => Some(0x2000),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn evaluates_with_precedence_and_parentheses() {
+        assert_eq!(evaluate("TABLE+3*2", &symbol), Ok(0x1006));
+        assert_eq!(evaluate("(TABLE+3)*2", &symbol), Ok(0x2006));
+        assert_eq!(evaluate("BUFEND-TABLE", &symbol), Ok(0x1000));
+        assert_eq!(evaluate("-7/2", &symbol), Ok(-3));
+    }
+
+    #[test]
+    fn reports_bad_expressions() {
+        assert_eq!(evaluate("NOWHERE", &symbol), Err("Undefined symbol 'NOWHERE'".to_string()));
+        assert_eq!(evaluate("1/0", &symbol), Err("Division by zero in expression".to_string()));
+        assert!(evaluate("(1+2", &symbol).is_err());
+        assert!(evaluate("1 2", &symbol).is_err());
+    }
+
+    #[test]
+    fn reports_overflow() {
+        let overflows = Err("Expression overflows".to_string());
+        assert_eq!(evaluate("9999999999*9999999999", &symbol), overflows);
+        assert_eq!(evaluate("9223372036854775807+1", &symbol), overflows);
+        assert_eq!(evaluate("0-9223372036854775807-2", &symbol), overflows);
+        assert_eq!(evaluate("(0-9223372036854775807-1)/(0-1)", &symbol), overflows);
+    }
+
+    #[test]
+    fn counts_relative_terms() {
+        let lookup = |name: &str| symbol(name).map(|value| (value, true));
+        assert_eq!(relative_terms("TABLE+3", &lookup), Ok(1));
+        assert_eq!(relative_terms("BUFEND-TABLE", &lookup), Ok(0));
+        assert_eq!(relative_terms("TABLE+BUFEND", &lookup), Ok(2));
+    }
+}
@@ -0,0 +1,145 @@
+use crate::pass2::takes_no_operand;
+
+/// Column where the opcode starts (0-based), matching the fixed-column layout.
+pub const OPCODE_COLUMN: usize = 9;
+/// Column where the operand starts.
+pub const OPERAND_COLUMN: usize = 16;
+/// Column where a trailing comment starts.
+pub const COMMENT_COLUMN: usize = 35;
+
+/// Re-emits SIC/XE source with label, opcode, operand and comment aligned to
+/// the textbook columns and mnemonics in upper case. Labels, operands,
+/// character constants and comment text are kept as written, and `.`
+/// comment lines are left alone. Formatting is idempotent: formatting the
+/// output again returns it unchanged.
+pub fn format_source(source: &str) -> String {
+    let mut out = String::new();
+    for line in source.lines() {
+        out.push_str(&format_line(line));
+        out.push('\n');
+    }
+    out
+}
+
+pub fn format_line(line: &str) -> String {
+    // a full-line comment in the fixed-column layout
+    if line.starts_with('.') {
+        return line.trim_end().to_string();
+    }
+    let (code, comment) = split_comment(line);
+    let tokens = tokenize(code);
+
+    let (label, opcode, operand) = match tokens.len() {
+        0 => {
+            return comment.map(|c| c.trim_end().to_string()).unwrap_or_default();
+        }
+        1 => ("", tokens[0], ""),
+        2 if tokens[0] == "*" || takes_no_operand(tokens[1]) => (tokens[0], tokens[1], ""),
+        2 => ("", tokens[0], tokens[1]),
+        3 => (tokens[0], tokens[1], tokens[2]),
+        // the assembler does not accept these lines; leave them for the user
+        _ => return line.trim_end().to_string(),
+    };
+
+    let mut out = String::from(label);
+    pad_to(&mut out, OPCODE_COLUMN);
+    out.push_str(&opcode.to_uppercase());
+    if !operand.is_empty() {
+        pad_to(&mut out, OPERAND_COLUMN);
+        out.push_str(operand);
+    }
+    if let Some(comment) = comment {
+        pad_to(&mut out, COMMENT_COLUMN);
+        out.push_str(comment.trim_end());
+    }
+    out.trim_end().to_string()
+}
+
+// pads with spaces up to `column`, or adds a single separating space when the
+// text already reaches it
+pub(crate) fn pad_to(out: &mut String, column: usize) {
+    let width = out.chars().count();
+    if width < column {
+        out.push_str(&" ".repeat(column - width));
+    } else if width > 0 {
+        out.push(' ');
+    }
+}
+
+// splits at the first ';' outside a quoted constant; the comment keeps its
+// ';'. Without one, text starting in the fixed-column comment field after a
+// statement is the comment, as the fixed-column reader takes it.
+fn split_comment(line: &str) -> (&str, Option<&str>) {
+    let mut in_quote = false;
+    let mut fixed_comment = None;
+    let mut previous = ' ';
+    for (column, (i, c)) in line.char_indices().enumerate() {
+        match c {
+            '\'' => in_quote = !in_quote,
+            ';' if !in_quote && fixed_comment.is_none() => return (&line[..i], Some(&line[i..])),
+            _ if fixed_comment.is_none() && column >= COMMENT_COLUMN && !in_quote && previous.is_whitespace() && !c.is_whitespace() => {
+                fixed_comment = Some(i);
+            }
+            _ => {}
+        }
+        previous = c;
+    }
+    match fixed_comment {
+        Some(i) if !line[..i].trim().is_empty() => (&line[..i], Some(&line[i..])),
+        _ => (line, None),
+    }
+}
+
+// whitespace-separated tokens, keeping quoted constants such as C'A B' whole
+fn tokenize(code: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let mut start = None;
+    let mut in_quote = false;
+
+    for (i, c) in code.char_indices() {
+        if c == '\'' {
+            in_quote = !in_quote;
+        }
+        if c.is_whitespace() && !in_quote {
+            if let Some(s) = start.take() {
+                tokens.push(&code[s..i]);
+            }
+        } else if start.is_none() {
+            start = Some(i);
+        }
+    }
+    if let Some(s) = start {
+        tokens.push(&code[s..]);
+    }
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aligns_fields_to_the_textbook_columns() {
+        assert_eq!(format_line("copy start 1000"), "copy     START  1000");
+        assert_eq!(format_line("  lda   =C'EOF'   ; load it"), "         LDA    =C'EOF'            ; load it");
+        assert_eq!(format_line("first rsub"), "first    RSUB");
+        assert_eq!(format_line("  rsub"), "         RSUB");
+    }
+
+    #[test]
+    fn keeps_comments() {
+        assert_eq!(format_line(".  a comment  line  "), ".  a comment  line");
+        assert_eq!(format_line("   ; on its own"), "; on its own");
+        // fixed-column comment without a ';'
+        let fixed = "SCRATCH  WORD   0                  keep this; as is";
+        assert_eq!(format_line(fixed), fixed);
+        assert_eq!(format_line("  byte c'A;B'"), "         BYTE   c'A;B'");
+    }
+
+    #[test]
+    fn is_idempotent() {
+        let source = "copy start 1000\n.comment\nfirst  stl retadr ; save\n  +jsub rdrec\nretadr resw 1\n end first\n";
+        let formatted = format_source(source);
+        assert_eq!(format_source(&formatted), formatted);
+    }
+}
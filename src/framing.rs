@@ -0,0 +1,70 @@
+use serde_json::Value;
+use std::io::{self, BufRead, Write};
+
+/// Reads one JSON message framed by a `Content-Length` header, as used by
+/// the Debug Adapter and Language Server protocols. `None` at end of input.
+pub fn read_message(input: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+    let length = length.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Message without Content-Length"))?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    serde_json::from_slice(&body).map(Some).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Writes `message` with its `Content-Length` header.
+pub fn write_message(output: &mut impl Write, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+/// Frames `messages` as a client sends them, runs `serve` over them and
+/// returns every message it wrote back, for tests of the protocol servers.
+#[cfg(test)]
+pub(crate) fn exchange(messages: &[Value], serve: impl FnOnce(&[u8], &mut Vec<u8>) -> io::Result<()>) -> Vec<Value> {
+    let mut input = Vec::new();
+    for message in messages {
+        write_message(&mut input, message).unwrap();
+    }
+    let mut output = Vec::new();
+    serve(&input, &mut output).unwrap();
+    let mut output = output.as_slice();
+    std::iter::from_fn(|| read_message(&mut output).unwrap()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn reads_back_what_it_writes() {
+        let mut buffer = Vec::new();
+        write_message(&mut buffer, &json!({ "text": "é" })).unwrap();
+        assert!(buffer.starts_with(b"Content-Length: 13\r\n\r\n"));
+        let mut input = buffer.as_slice();
+        assert_eq!(read_message(&mut input).unwrap(), Some(json!({ "text": "é" })));
+        assert_eq!(read_message(&mut input).unwrap(), None);
+    }
+
+    #[test]
+    fn rejects_messages_without_a_length() {
+        let mut input: &[u8] = b"Content-Type: x\r\n\r\n{}";
+        assert!(read_message(&mut input).is_err());
+    }
+}
@@ -2,17 +2,52 @@ use actix_web::{web, HttpResponse, Responder};
 use sqlx::PgPool;
 use uuid::Uuid;
 use chrono::Utc;
+use sqlx::types::Json;
 use std::fs;
+use std::path::{Component, Path};
 use utoipa::ToSchema;
 
 use crate::models::AssemblyJob;
 use crate::error::AppError;
 use crate::auth::AuthenticatedUser;
+use crate::assembler::{self, AssembleError, AssemblyOptions};
+use crate::debug_info::DebugInfo;
+use crate::diagnostics::Diagnostic;
+use crate::encoding::Encoding;
+use crate::formatter;
+use crate::image::{self, ExportOptions, ImageFormat, MemoryImage};
+use crate::object_program::{ObjectError, ObjectProgram};
+use crate::pass_trace::PassTrace;
+use crate::source::SourceFormat;
+use crate::tables::{self, TableEntry, TableOrder};
 
 #[derive(Debug, serde::Deserialize, ToSchema)]
 pub struct AssembleRequest {
     pub code: String,
     pub title: Option<String>,
+    /// Additional project files that `INCLUDE`/`COPY` directives can refer to
+    pub files: Option<Vec<ProjectFile>>,
+    /// Maximum object code bytes per text record (1-255, default 30)
+    pub max_record_length: Option<usize>,
+    /// Sort symbol and literal tables by `name` (default) or `address`
+    pub table_order: Option<TableOrder>,
+    /// Enforce the SIC 6-character label limit
+    pub strict: Option<bool>,
+    /// Run the lint pass and add its warnings to `diagnostics`
+    pub lint: Option<bool>,
+    /// `free` (default) or `fixed` for textbook column layout
+    pub source_format: Option<SourceFormat>,
+    /// Return `encodings`, explaining how each instruction was encoded
+    pub explain: Option<bool>,
+    /// Return `trace`, recording what each pass did with each statement
+    pub trace: Option<bool>,
+}
+
+#[derive(Debug, serde::Deserialize, ToSchema)]
+pub struct ProjectFile {
+    /// Path relative to the main source, e.g. `subs/io.asm`
+    pub name: String,
+    pub code: String,
 }
 
 #[derive(Debug, serde::Serialize, ToSchema)]
@@ -23,6 +58,54 @@ pub struct AssembleResponse {
     pub symb_table: String,
     pub lit_table: String,
     pub object_program: String,
+    pub symbols: Vec<TableEntry>,
+    pub literals: Vec<TableEntry>,
+    pub symb_table_csv: String,
+    pub lit_table_csv: String,
+    /// Warnings collected while assembling
+    pub diagnostics: Vec<Diagnostic>,
+    /// Which source line each address of the object code came from
+    pub debug_info: DebugInfo,
+    /// How each instruction's object code was built, present when
+    /// `explain` was requested
+    pub encodings: Option<Vec<Encoding>>,
+    /// Both passes step by step, present when `trace` was requested
+    pub trace: Option<PassTrace>,
+}
+
+#[derive(Debug, serde::Deserialize, ToSchema)]
+pub struct FormatRequest {
+    pub code: String,
+}
+
+#[derive(Debug, serde::Serialize, ToSchema)]
+pub struct FormatResponse {
+    pub code: String,
+}
+
+#[derive(Debug, serde::Deserialize, ToSchema)]
+pub struct ValidateRequest {
+    pub object_program: String,
+}
+
+#[derive(Debug, serde::Serialize, ToSchema)]
+pub struct ValidateResponse {
+    pub valid: bool,
+    /// The parsed records, present only when `valid` is true
+    pub program: Option<ObjectProgram>,
+    pub errors: Vec<ObjectError>,
+}
+
+/// A job's files on disk, removed when dropped so that requests failing
+/// validation or assembly do not leave their uploads behind.
+struct JobDir {
+    path: String,
+}
+
+impl Drop for JobDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
 }
 
 #[utoipa::path(
@@ -40,9 +123,9 @@ pub async fn assemble(
     user: Option<AuthenticatedUser>,
 ) -> Result<impl Responder, AppError> {
     let job_id = Uuid::new_v4().to_string();
-    let job_dir = format!("jobs/{}", job_id);
-    let input_dir = format!("{}/input", job_dir);
-    let output_dir = format!("{}/output", job_dir);
+    let job_dir = JobDir { path: format!("jobs/{}", job_id) };
+    let input_dir = format!("{}/input", job_dir.path);
+    let output_dir = format!("{}/output", job_dir.path);
     
     // create directories
     fs::create_dir_all(&input_dir)
@@ -54,40 +137,50 @@ pub async fn assemble(
     let input_path = format!("{}/in.txt", input_dir);
     fs::write(&input_path, &req.code)
         .map_err(|e| AppError::InternalError(format!("Failed to write input file: {}", e)))?;
+
+    // write the rest of the project next to in.txt so includes resolve against it
+    for file in req.files.iter().flatten() {
+        let relative = Path::new(&file.name);
+        if file.name == "in.txt" || !relative.components().all(|c| matches!(c, Component::Normal(_))) {
+            return Err(AppError::BadRequest(format!("Invalid project file name '{}'", file.name)));
+        }
+
+        let file_path = Path::new(&input_dir).join(relative);
+        if let Some(parent) = file_path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| AppError::InternalError(format!("Failed to create project directory: {}", e)))?;
+        }
+        fs::write(&file_path, &file.code)
+            .map_err(|e| AppError::InternalError(format!("Failed to write project file: {}", e)))?;
+    }
     
-    // run assembler 
-    let mut pass1_engine = crate::pass1::Pass1::new();
-    let _ = pass1_engine.process_file(&input_path)
-        .map_err(|e| AppError::BadRequest(format!("Failed to process source file: {}", e)))?;
-    
-    pass1_engine.pass1_generator(&output_dir)
-        .map_err(|e| AppError::BadRequest(format!("Assembly Error (Pass 1): {}", e)))?;
-        
-    let intermediate_path = format!("{}/intermediate.txt", output_dir);
-    let symbol_path = format!("{}/symbTable.txt", output_dir);
-    let literal_path = format!("{}/litTable.txt", output_dir);
-    let object_path = format!("{}/objectProgram.txt", output_dir);
-    
-    let mut pass2_engine = crate::pass2::Pass2::new();
-    let _ = pass2_engine.pass2_generator(&intermediate_path, &symbol_path, &literal_path, &object_path)
-        .map_err(|e| AppError::BadRequest(format!("Assembly Error (Pass 2): {}", e)))?;
-        
-    // read generated files to return them in the response and database
-    let intermediate = fs::read_to_string(&intermediate_path)
-        .map_err(|e| AppError::InternalError(format!("Failed to read intermediate file: {}", e)))?;
-        
-    let pass1 = fs::read_to_string(&intermediate_path)
-        .map_err(|e| AppError::InternalError(format!("Failed to read intermediate file: {}", e)))?;
-        
-    let symb_table = fs::read_to_string(&symbol_path)
-        .map_err(|e| AppError::InternalError(format!("Failed to read symbol table file: {}", e)))?;
-        
-    let lit_table = fs::read_to_string(&literal_path)
-        .map_err(|e| AppError::InternalError(format!("Failed to read literal table file: {}", e)))?;
-        
-    let object_program = fs::read_to_string(&object_path)
-        .map_err(|e| AppError::InternalError(format!("Failed to read object program file: {}", e)))?;
-        
+    // run assembler
+    let mut options = AssemblyOptions {
+        source_format: req.source_format.unwrap_or_default(),
+        strict: req.strict.unwrap_or(false),
+        lint: req.lint.unwrap_or(false),
+        table_order: req.table_order.unwrap_or_default(),
+        explain: req.explain.unwrap_or(false),
+        trace: req.trace.unwrap_or(false),
+        ..AssemblyOptions::default()
+    };
+    if let Some(max_len) = req.max_record_length {
+        if !(1..=0xFF).contains(&max_len) {
+            return Err(AppError::BadRequest("max_record_length must be between 1 and 255".to_string()));
+        }
+        options.max_record_length = max_len;
+    }
+
+    let assembly = assembler::assemble_file(&input_path, &output_dir, &options).map_err(|e| match e {
+        AssembleError::Output(msg) => AppError::InternalError(msg),
+        e => AppError::BadRequest(e.to_string()),
+    })?;
+    let intermediate = assembly.intermediate;
+    let pass1 = intermediate.clone();
+    let symb_table = assembly.symb_table;
+    let lit_table = assembly.lit_table;
+    let object_program = assembly.object_program;
+
     // write pass1.txt for compatibility / backup
     fs::write(format!("{}/pass1.txt", output_dir), &pass1)
         .map_err(|e| AppError::InternalError(format!("Failed to write pass1 backup file: {}", e)))?;
@@ -101,8 +194,8 @@ pub async fn assemble(
         let now = Utc::now();
         
         sqlx::query(
-            "INSERT INTO assembly_jobs (id, user_id, title, code, intermediate, pass1, symb_table, lit_table, object_program, created_at)
-             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)"
+            "INSERT INTO assembly_jobs (id, user_id, title, code, intermediate, pass1, symb_table, lit_table, object_program, debug_info, created_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)"
         )
         .bind(job_uuid)
         .bind(user.user_id)
@@ -113,13 +206,11 @@ pub async fn assemble(
         .bind(&symb_table)
         .bind(&lit_table)
         .bind(&object_program)
+        .bind(Json(&assembly.debug_info))
         .bind(now)
         .execute(pool.get_ref())
         .await?;
     }
-    
-    // clean up temporary job files from disk
-    let _ = fs::remove_dir_all(&job_dir);
 
     let response = AssembleResponse {
         job_id,
@@ -128,11 +219,51 @@ pub async fn assemble(
         symb_table,
         lit_table,
         object_program,
+        symb_table_csv: tables::render_csv(&assembly.symbols),
+        lit_table_csv: tables::render_csv(&assembly.literals),
+        symbols: assembly.symbols,
+        literals: assembly.literals,
+        diagnostics: assembly.diagnostics,
+        debug_info: assembly.debug_info,
+        encodings: options.explain.then_some(assembly.encodings),
+        trace: assembly.trace,
     };
     
     Ok(HttpResponse::Ok().json(response))
 }
 
+#[utoipa::path(
+    post,
+    path = "/format",
+    request_body = FormatRequest,
+    responses(
+        (status = 200, description = "Source re-emitted with aligned columns", body = FormatResponse)
+    ),
+    tag = "Assembly"
+)]
+pub async fn format(req: web::Json<FormatRequest>) -> Result<impl Responder, AppError> {
+    Ok(HttpResponse::Ok().json(FormatResponse {
+        code: formatter::format_source(&req.code),
+    }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/validate",
+    request_body = ValidateRequest,
+    responses(
+        (status = 200, description = "Object program checked; `errors` lists every problem found", body = ValidateResponse)
+    ),
+    tag = "Assembly"
+)]
+pub async fn validate(req: web::Json<ValidateRequest>) -> Result<impl Responder, AppError> {
+    let response = match ObjectProgram::parse(&req.object_program) {
+        Ok(program) => ValidateResponse { valid: true, program: Some(program), errors: Vec::new() },
+        Err(errors) => ValidateResponse { valid: false, program: None, errors },
+    };
+    Ok(HttpResponse::Ok().json(response))
+}
+
 #[utoipa::path(
     get,
     path = "/history",
@@ -196,6 +327,91 @@ pub async fn get_job(
     Ok(HttpResponse::Ok().json(job))
 }
 
+#[derive(Debug, serde::Deserialize)]
+pub struct ImageQuery {
+    pub format: Option<ImageFormat>,
+    /// Gap fill byte, two hex digits
+    pub fill: Option<String>,
+    /// First address to export (hex)
+    pub start: Option<String>,
+    /// Address after the last one to export (hex)
+    pub end: Option<String>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/history/{id}/image",
+    params(
+        ("id" = String, Path, description = "Job ID"),
+        ("format" = Option<ImageFormat>, Query, description = "binary (default), ihex or srec"),
+        ("fill" = Option<String>, Query, description = "Gap fill byte in hex; binary images use 00 without it, the record formats skip gaps"),
+        ("start" = Option<String>, Query, description = "First address to export, in hex"),
+        ("end" = Option<String>, Query, description = "Address after the last one to export, in hex")
+    ),
+    responses(
+        (status = 200, description = "Memory image file, sent as an attachment"),
+        (status = 400, description = "Invalid job ID, export options or stored object program"),
+        (status = 401, description = "Unauthorized - invalid or missing token"),
+        (status = 404, description = "Job not found")
+    ),
+    security(
+        ("bearer_auth" = [])
+    ),
+    tag = "Assembly"
+)]
+pub async fn get_job_image(
+    pool: web::Data<PgPool>,
+    user: AuthenticatedUser,
+    path: web::Path<String>,
+    query: web::Query<ImageQuery>,
+) -> Result<impl Responder, AppError> {
+    let job_uuid = Uuid::parse_str(&path.into_inner())
+        .map_err(|_| AppError::BadRequest("Invalid job ID".to_string()))?;
+
+    let hex = |name: &str, value: &Option<String>| {
+        value.as_deref()
+            .map(|text| usize::from_str_radix(text, 16).map_err(|_| AppError::BadRequest(format!("'{}' is not a hex {}", text, name))))
+            .transpose()
+    };
+    let fill = match hex("fill byte", &query.fill)? {
+        Some(byte) if byte > 0xFF => return Err(AppError::BadRequest(format!("Fill byte {:X} is over FF", byte))),
+        fill => fill.map(|byte| byte as u8),
+    };
+    let start = hex("address", &query.start)?;
+    let end = hex("address", &query.end)?;
+
+    let job = sqlx::query_as::<_, AssemblyJob>(
+        "SELECT * FROM assembly_jobs WHERE id = $1 AND user_id = $2"
+    )
+    .bind(job_uuid)
+    .bind(user.user_id)
+    .fetch_optional(pool.get_ref())
+    .await?
+    .ok_or_else(|| AppError::NotFound("Job not found".to_string()))?;
+
+    let program = ObjectProgram::parse(job.object_program.as_deref().unwrap_or(""))
+        .map_err(|errors| AppError::BadRequest(format!("Stored object program is invalid: {}", errors[0])))?;
+    let image = MemoryImage::from(&program);
+
+    // a missing bound defaults to the image's own extent
+    let extent = image.extent();
+    let range = match (start, end) {
+        (None, None) => None,
+        (start, end) => Some(start.unwrap_or(extent.start)..end.unwrap_or(extent.end)),
+    };
+    if let Some(range) = &range {
+        image::check_range(range).map_err(AppError::BadRequest)?;
+    }
+
+    let format = query.format.unwrap_or(ImageFormat::Binary);
+    let bytes = image.export(format, &ExportOptions { fill, range });
+    let name = if program.header.name.is_empty() { "program" } else { program.header.name.as_str() };
+    Ok(HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header(("Content-Disposition", format!("attachment; filename=\"{}.{}\"", name, format.extension())))
+        .body(bytes))
+}
+
 #[utoipa::path(
     delete,
     path = "/history/{id}",
@@ -21,8 +21,11 @@ use utoipa::OpenApi;
         auth::github_auth,
         auth::github_callback,
         assembly::assemble,
+        assembly::format,
+        assembly::validate,
         assembly::get_history,
         assembly::get_job,
+        assembly::get_job_image,
         assembly::delete_job,
         assembly::delete_all_jobs,
     ),
@@ -38,11 +41,38 @@ use utoipa::OpenApi;
             crate::models::LoginRequest,
             crate::models::AuthResponse,
             assembly::AssembleRequest,
+            assembly::ProjectFile,
+            crate::tables::TableEntry,
+            crate::tables::TableOrder,
+            crate::diagnostics::Diagnostic,
+            crate::diagnostics::RelatedLocation,
+            crate::diagnostics::Severity,
+            crate::source::SourceLocation,
+            crate::source::SourceFormat,
             assembly::AssembleResponse,
+            assembly::FormatRequest,
+            assembly::FormatResponse,
+            assembly::ValidateRequest,
+            assembly::ValidateResponse,
+            crate::object_program::ObjectProgram,
+            crate::object_program::HeaderRecord,
+            crate::object_program::ExternalSymbol,
+            crate::object_program::TextRecord,
+            crate::object_program::ModificationRecord,
+            crate::object_program::ObjectError,
+            crate::image::ImageFormat,
+            crate::debug_info::DebugInfo,
+            crate::debug_info::SourceRange,
+            crate::encoding::Encoding,
+            crate::encoding::EncodingField,
+            crate::pass_trace::PassTrace,
+            crate::pass_trace::Pass1Step,
+            crate::pass_trace::Pass2Step,
+            crate::pass_trace::TracedSymbol,
         )
     )
 )]
 pub struct ApiDoc;
 
 pub use auth::{register, login, me, delete_account, logout, google_auth, google_callback, github_auth, github_callback};
-pub use assembly::{assemble, get_history, get_job, delete_job, delete_all_jobs};
\ No newline at end of file
+pub use assembly::{assemble, format, validate, get_history, get_job, get_job_image, delete_job, delete_all_jobs};
\ No newline at end of file
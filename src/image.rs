@@ -0,0 +1,321 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ops::Range;
+use utoipa::ToSchema;
+
+use crate::emulator::MEMORY_SIZE;
+use crate::object_program::ObjectProgram;
+use crate::source::SourceLocation;
+
+/// Data bytes per Intel HEX or S-record line.
+const RECORD_BYTES: usize = 16;
+
+/// Formats a memory image can be exported in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageFormat {
+    /// Flat bytes from the first to the last exported address
+    Binary,
+    /// Intel HEX, with extended linear address records above 64K
+    Ihex,
+    /// Motorola S-records: S1/S9 for 16-bit addresses, S2/S8 above that
+    Srec,
+}
+
+impl ImageFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            ImageFormat::Binary => "bin",
+            ImageFormat::Ihex => "hex",
+            ImageFormat::Srec => "srec",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ImageFormat::Binary => "application/octet-stream",
+            ImageFormat::Ihex | ImageFormat::Srec => "text/plain",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ExportOptions {
+    /// Byte for addresses no object code covers. Binary images always fill
+    /// gaps (with 00 when this is `None`); Intel HEX and S-records only
+    /// fill them when it is set and otherwise skip them.
+    pub fill: Option<u8>,
+    /// Addresses to export; the image's own extent when `None`
+    pub range: Option<Range<usize>>,
+}
+
+/// Checks an export range: it must not end before it starts or reach past
+/// the machine's memory, which also bounds how much a fill can produce.
+pub fn check_range(range: &Range<usize>) -> Result<(), String> {
+    if range.end < range.start {
+        return Err(format!("Range end {:X} is before its start {:X}", range.end, range.start));
+    }
+    if range.end > MEMORY_SIZE {
+        return Err(format!("Range {:X}-{:X} goes past the end of memory ({:X})", range.start, range.end, MEMORY_SIZE));
+    }
+    Ok(())
+}
+
+/// The statement a byte of the image was assembled from.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct Provenance {
+    /// Index of the statement in Pass 2's statement list
+    pub statement: usize,
+    /// `None` when the statements did not come from Pass 1
+    pub location: Option<SourceLocation>,
+    pub block: String,
+}
+
+/// Bytes already in the image that a `write` replaced.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Overlap {
+    pub address: usize,
+    pub length: usize,
+    /// Where the replaced bytes came from; `None` for bytes added with `insert`
+    pub previous: Option<Provenance>,
+}
+
+/// Object code as bytes by address, with the program name and entry point
+/// the record formats carry. Bytes written by the assembler remember the
+/// statement they came from.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MemoryImage {
+    pub name: String,
+    pub entry: Option<usize>,
+    // each byte with the index of its provenance in `origins`
+    bytes: BTreeMap<usize, (u8, Option<usize>)>,
+    origins: Vec<Provenance>,
+}
+
+impl From<&ObjectProgram> for MemoryImage {
+    fn from(program: &ObjectProgram) -> Self {
+        Self {
+            name: program.header.name.clone(),
+            entry: program.entry,
+            bytes: program.memory().into_iter().map(|(address, byte)| (address, (byte, None))).collect(),
+            origins: Vec::new(),
+        }
+    }
+}
+
+impl MemoryImage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets bytes without provenance, replacing any already there.
+    pub fn insert(&mut self, address: usize, bytes: &[u8]) {
+        for (k, &byte) in bytes.iter().enumerate() {
+            self.bytes.insert(address + k, (byte, None));
+        }
+    }
+
+    /// Sets the bytes `origin` assembled to, replacing any already there.
+    /// Returns the replaced bytes, one entry per run with the same origin.
+    pub fn write(&mut self, address: usize, bytes: &[u8], origin: Provenance) -> Vec<Overlap> {
+        let index = self.origins.len();
+        self.origins.push(origin);
+
+        let mut overlaps: Vec<Overlap> = Vec::new();
+        for (k, &byte) in bytes.iter().enumerate() {
+            let Some((_, previous)) = self.bytes.insert(address + k, (byte, Some(index))) else {
+                continue;
+            };
+            let previous = previous.map(|i| self.origins[i].clone());
+            match overlaps.last_mut() {
+                Some(overlap) if overlap.address + overlap.length == address + k && overlap.previous == previous => {
+                    overlap.length += 1;
+                }
+                _ => overlaps.push(Overlap { address: address + k, length: 1, previous }),
+            }
+        }
+        overlaps
+    }
+
+    pub fn get(&self, address: usize) -> Option<u8> {
+        self.bytes.get(&address).map(|&(byte, _)| byte)
+    }
+
+    /// The statement the byte at `address` was assembled from.
+    pub fn origin(&self, address: usize) -> Option<&Provenance> {
+        self.bytes.get(&address).and_then(|&(_, origin)| origin).map(|i| &self.origins[i])
+    }
+
+    /// All bytes in address order.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, u8)> + '_ {
+        self.bytes.iter().map(|(&address, &(byte, _))| (address, byte))
+    }
+
+    /// The bytes from `address` on that were written together with it, i.e.
+    /// what remains of that statement's code from there.
+    pub fn item(&self, address: usize) -> Vec<u8> {
+        let Some(&(_, Some(origin))) = self.bytes.get(&address) else {
+            return Vec::new();
+        };
+        self.bytes.range(address..)
+            .enumerate()
+            .take_while(|&(k, (&a, &(_, o)))| a == address + k && o == Some(origin))
+            .map(|(_, (_, &(byte, _)))| byte)
+            .collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    /// From the lowest address with code to one past the highest.
+    pub fn extent(&self) -> Range<usize> {
+        match (self.bytes.keys().next(), self.bytes.keys().next_back()) {
+            (Some(&low), Some(&high)) => low..high + 1,
+            _ => 0..0,
+        }
+    }
+
+    pub fn export(&self, format: ImageFormat, options: &ExportOptions) -> Vec<u8> {
+        match format {
+            ImageFormat::Binary => self.to_binary(options),
+            ImageFormat::Ihex => self.to_intel_hex(options).into_bytes(),
+            ImageFormat::Srec => self.to_srec(options).into_bytes(),
+        }
+    }
+
+    pub fn to_binary(&self, options: &ExportOptions) -> Vec<u8> {
+        let fill = options.fill.unwrap_or(0);
+        self.range(options).map(|address| self.get(address).unwrap_or(fill)).collect()
+    }
+
+    pub fn to_intel_hex(&self, options: &ExportOptions) -> String {
+        let mut out = String::new();
+        let mut upper = 0;
+        // a record's 16-bit address must not wrap, so runs break at 64K
+        for (address, bytes) in self.runs(options, Some(0x10000)) {
+            if address >> 16 != upper {
+                upper = address >> 16;
+                out.push_str(&intel_record(4, 0, &[(upper >> 8) as u8, upper as u8]));
+            }
+            out.push_str(&intel_record(0, address & 0xFFFF, &bytes));
+        }
+        if let Some(entry) = self.entry {
+            out.push_str(&intel_record(5, 0, &(entry as u32).to_be_bytes()));
+        }
+        out.push_str(&intel_record(1, 0, &[]));
+        out
+    }
+
+    pub fn to_srec(&self, options: &ExportOptions) -> String {
+        let runs = self.runs(options, None);
+        let last = runs.last().map_or(0, |(address, bytes)| address + bytes.len() - 1);
+        let wide = last > 0xFFFF || self.entry.is_some_and(|entry| entry > 0xFFFF);
+        let (data, end, address_len) = if wide { ('2', '8', 3) } else { ('1', '9', 2) };
+
+        let mut out = srec_record('0', 0, 2, self.name.as_bytes());
+        for (address, bytes) in &runs {
+            out.push_str(&srec_record(data, *address, address_len, bytes));
+        }
+        if runs.len() <= 0xFFFF {
+            out.push_str(&srec_record('5', runs.len(), 2, &[]));
+        }
+        out.push_str(&srec_record(end, self.entry.unwrap_or(0), address_len, &[]));
+        out
+    }
+
+    fn range(&self, options: &ExportOptions) -> Range<usize> {
+        options.range.clone().unwrap_or_else(|| self.extent())
+    }
+
+    // exported bytes in runs of consecutive addresses, at most RECORD_BYTES
+    // long and not crossing a multiple of `boundary`
+    fn runs(&self, options: &ExportOptions, boundary: Option<usize>) -> Vec<(usize, Vec<u8>)> {
+        let range = self.range(options);
+        let bytes: Box<dyn Iterator<Item = (usize, u8)>> = match options.fill {
+            Some(fill) => Box::new(range.map(move |address| (address, self.get(address).unwrap_or(fill)))),
+            None => Box::new(self.bytes.range(range).map(|(&address, &(byte, _))| (address, byte))),
+        };
+
+        let mut runs: Vec<(usize, Vec<u8>)> = Vec::new();
+        for (address, byte) in bytes {
+            match runs.last_mut() {
+                Some((start, run))
+                    if *start + run.len() == address
+                        && run.len() < RECORD_BYTES
+                        && boundary.is_none_or(|b| address % b != 0) => run.push(byte),
+                _ => runs.push((address, vec![byte])),
+            }
+        }
+        runs
+    }
+}
+
+// `:LLAAAATT<data>CC`, where CC makes all bytes sum to 0
+fn intel_record(kind: u8, address: usize, data: &[u8]) -> String {
+    let mut bytes = vec![data.len() as u8, (address >> 8) as u8, address as u8, kind];
+    bytes.extend_from_slice(data);
+    let checksum = bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)).wrapping_neg();
+    bytes.push(checksum);
+    format!(":{}\n", hex(&bytes))
+}
+
+// `S<type><count><address><data><checksum>`; the count covers address,
+// data and checksum, and the checksum is the ones' complement of their sum
+fn srec_record(kind: char, address: usize, address_len: usize, data: &[u8]) -> String {
+    let mut bytes = vec![(address_len + data.len() + 1) as u8];
+    bytes.extend((0..address_len).rev().map(|k| (address >> (8 * k)) as u8));
+    bytes.extend_from_slice(data);
+    let checksum = !bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+    bytes.push(checksum);
+    format!("S{}{}\n", kind, hex(&bytes))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02X}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image() -> MemoryImage {
+        let mut image = MemoryImage::new();
+        image.name = "T".to_string();
+        image.entry = Some(0x1000);
+        image.insert(0x1000, &[1, 2, 3]);
+        image
+    }
+
+    #[test]
+    fn checks_export_ranges() {
+        assert!(check_range(&(0..MEMORY_SIZE)).is_ok());
+        assert!(check_range(&Range { start: 0x20, end: 0x10 }).is_err());
+        assert!(check_range(&(0..MEMORY_SIZE + 1)).is_err());
+    }
+
+    #[test]
+    fn fills_gaps_in_binary_images() {
+        let mut image = image();
+        image.insert(0x1005, &[9]);
+        assert_eq!(image.to_binary(&ExportOptions::default()), [1, 2, 3, 0, 0, 9]);
+        let options = ExportOptions { fill: Some(0xFF), range: Some(0x0FFE..0x1004) };
+        assert_eq!(image.to_binary(&options), [0xFF, 0xFF, 1, 2, 3, 0xFF]);
+    }
+
+    #[test]
+    fn writes_intel_hex() {
+        let mut image = image();
+        assert_eq!(image.to_intel_hex(&ExportOptions::default()), ":03100000010203E7\n:0400000500001000E7\n:00000001FF\n");
+
+        // bytes past 64K need an extended linear address record
+        image.insert(0x10000, &[4]);
+        let hex = image.to_intel_hex(&ExportOptions::default());
+        assert!(hex.contains(":020000040001F9\n:0100000004FB\n"));
+    }
+
+    #[test]
+    fn writes_s_records() {
+        assert_eq!(image().to_srec(&ExportOptions::default()), "S004000054A7\nS1061000010203E3\nS5030001FB\nS9031000EC\n");
+    }
+}
@@ -1,6 +1,28 @@
 pub mod pass1;
 pub mod pass2;
+pub mod pass_trace;
+pub mod assembler;
 pub mod conversions;
+pub mod dap;
+pub mod debug_info;
+pub mod debugger;
+pub mod devices;
+pub mod diagnostics;
+pub mod disassembler;
+pub mod emulator;
+pub mod encoding;
+pub mod expression;
+pub mod formatter;
+pub mod framing;
+pub mod image;
+pub mod lint;
+pub mod loader;
+pub mod lsp;
+pub mod object_program;
+pub mod profiler;
+pub mod source;
+pub mod tables;
+pub mod testing;
 pub mod models;
 pub mod error;
 pub mod auth;
@@ -0,0 +1,247 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::diagnostics::Diagnostic;
+use crate::expression;
+use crate::pass1::Pass1;
+use crate::pass2::{FORMAT1, FORMAT2, FORMAT3, FORMAT4};
+
+const DATA_DIRECTIVES: [&str; 4] = ["WORD", "BYTE", "RESW", "RESB"];
+const STORE_INSTRUCTIONS: [&str; 10] = ["STA", "STB", "STCH", "STF", "STL", "STS", "STSW", "STT", "STX", "CSTORE"];
+
+/// Optional static checks over a program that has been through Pass 1.
+/// Each warning can be silenced on its own line with `; lint: allow(<code>)`.
+///
+/// - `unused-label`: a label that no operand refers to
+/// - `unreachable-code`: an unlabelled instruction right after `J` or `RSUB`
+/// - `base-without-ldb`: `BASE` with no `LDB` loading the same operand
+/// - `write-to-code`: a store whose target label is on an instruction
+/// - `jsub-without-stl`: a subroutine calling `JSUB` before saving `L` with `STL`
+/// - `data-in-code`: data that the preceding instruction falls through into
+pub fn lint(pass1: &Pass1) -> Vec<Diagnostic> {
+    let mut warnings = Vec::new();
+    let blocks = statement_blocks(pass1);
+
+    check_unused_labels(pass1, &mut warnings);
+    check_control_flow(pass1, &blocks, &mut warnings);
+    check_base(pass1, &mut warnings);
+    check_code_writes(pass1, &mut warnings);
+    check_subroutines(pass1, &mut warnings);
+
+    warnings.retain(|(i, diagnostic): &(usize, Diagnostic)| pass1.allows(*i, &diagnostic.code));
+    warnings.sort_by_key(|(i, _)| *i);
+    warnings.into_iter().map(|(_, diagnostic)| diagnostic).collect()
+}
+
+fn is_instruction(instr: &str) -> bool {
+    let name = instr.trim_start_matches('+');
+    FORMAT1.contains(&name) || FORMAT2.contains(&name) || FORMAT3.contains(&name) || FORMAT4.contains(&name)
+}
+
+fn is_unconditional_transfer(instr: &str) -> bool {
+    matches!(instr.trim_start_matches('+'), "J" | "RSUB")
+}
+
+fn has_label(pass1: &Pass1, i: usize) -> bool {
+    pass1.labels[i] != "&" && pass1.labels[i] != "*"
+}
+
+// the block each statement is assembled into
+fn statement_blocks(pass1: &Pass1) -> Vec<String> {
+    let mut current = "DEFAULTB".to_string();
+    pass1.instr.iter()
+        .zip(&pass1.ref_data)
+        .map(|(instr, operand)| {
+            if instr == "USE" {
+                current = operand.clone();
+            }
+            current.clone()
+        })
+        .collect()
+}
+
+// symbols an operand refers to, ignoring addressing prefixes, literals and
+// character/hex constants
+fn referenced_symbols(operand: &str) -> Vec<String> {
+    if operand == "&" || operand.starts_with('=') || operand.contains('\'') {
+        return Vec::new();
+    }
+    operand.trim_start_matches(['#', '@'])
+        .split(',')
+        .flat_map(expression::symbols)
+        .collect()
+}
+
+fn check_unused_labels(pass1: &Pass1, warnings: &mut Vec<(usize, Diagnostic)>) {
+    let referenced: HashSet<String> = pass1.ref_data.iter()
+        .flat_map(|operand| referenced_symbols(operand))
+        .collect();
+
+    for i in 0..pass1.labels.len() {
+        let label = &pass1.labels[i];
+        if has_label(pass1, i) && pass1.instr[i] != "START" && !referenced.contains(label) {
+            warnings.push((i, Diagnostic::warning(
+                "unused-label",
+                format!("Label '{}' is never referenced", label),
+                &pass1.sources[i],
+            )));
+        }
+    }
+}
+
+fn check_control_flow(pass1: &Pass1, blocks: &[String], warnings: &mut Vec<(usize, Diagnostic)>) {
+    // last statement that occupies space in each block
+    let mut previous: HashMap<&str, usize> = HashMap::new();
+
+    for (i, block) in blocks.iter().enumerate() {
+        let instr = pass1.instr[i].as_str();
+        let is_data = DATA_DIRECTIVES.contains(&instr);
+        if !is_instruction(instr) && !is_data {
+            continue;
+        }
+
+        if let Some(&prev) = previous.get(block.as_str()) {
+            let prev_instr = pass1.instr[prev].as_str();
+            if is_instruction(prev_instr) {
+                if is_unconditional_transfer(prev_instr) && is_instruction(instr) && !has_label(pass1, i) {
+                    warnings.push((i, Diagnostic::warning(
+                        "unreachable-code",
+                        format!("'{}' can never be reached after '{}'", instr, prev_instr),
+                        &pass1.sources[i],
+                    ).with_related(&pass1.sources[prev], "control leaves here")));
+                } else if !is_unconditional_transfer(prev_instr) && is_data {
+                    warnings.push((i, Diagnostic::warning(
+                        "data-in-code",
+                        format!("'{}' is defined in the middle of instruction flow; '{}' falls through into it", instr, prev_instr),
+                        &pass1.sources[i],
+                    ).with_related(&pass1.sources[prev], "falls through from here")));
+                }
+            }
+        }
+        previous.insert(block.as_str(), i);
+    }
+}
+
+fn check_base(pass1: &Pass1, warnings: &mut Vec<(usize, Diagnostic)>) {
+    let loaded: HashSet<&str> = (0..pass1.instr.len())
+        .filter(|&i| pass1.instr[i].trim_start_matches('+') == "LDB")
+        .map(|i| pass1.ref_data[i].trim_start_matches(['#', '@']))
+        .collect();
+
+    for i in 0..pass1.instr.len() {
+        if pass1.instr[i] == "BASE" && !loaded.contains(pass1.ref_data[i].as_str()) {
+            warnings.push((i, Diagnostic::warning(
+                "base-without-ldb",
+                format!("BASE {} is declared but no LDB loads it into the base register", pass1.ref_data[i]),
+                &pass1.sources[i],
+            )));
+        }
+    }
+}
+
+fn check_code_writes(pass1: &Pass1, warnings: &mut Vec<(usize, Diagnostic)>) {
+    let code_labels: HashMap<&str, usize> = (0..pass1.labels.len())
+        .filter(|&i| has_label(pass1, i) && is_instruction(&pass1.instr[i]))
+        .map(|i| (pass1.labels[i].as_str(), i))
+        .collect();
+
+    for i in 0..pass1.instr.len() {
+        let instr = pass1.instr[i].trim_start_matches('+');
+        // immediate and indirect operands do not write to the labelled address
+        if !STORE_INSTRUCTIONS.contains(&instr) || pass1.ref_data[i].starts_with(['#', '@']) {
+            continue;
+        }
+        // CSTORE R,M,F stores to its second operand
+        let target = if instr == "CSTORE" {
+            pass1.ref_data[i].split(',').nth(1).unwrap_or("")
+        } else {
+            pass1.ref_data[i].split(',').next().unwrap_or("")
+        };
+
+        if let Some(&def) = code_labels.get(target) {
+            warnings.push((i, Diagnostic::warning(
+                "write-to-code",
+                format!("'{}' writes into '{}', which labels an instruction", instr, target),
+                &pass1.sources[i],
+            ).with_related(&pass1.sources[def], "instruction defined here")));
+        }
+    }
+}
+
+fn check_subroutines(pass1: &Pass1, warnings: &mut Vec<(usize, Diagnostic)>) {
+    let targets: HashSet<&str> = (0..pass1.instr.len())
+        .filter(|&i| pass1.instr[i].trim_start_matches('+') == "JSUB")
+        .map(|i| pass1.ref_data[i].trim_start_matches(['#', '@']))
+        .collect();
+
+    // (statement that starts the subroutine, whether L has been saved)
+    let mut current: Option<(usize, bool)> = None;
+    for i in 0..pass1.instr.len() {
+        if has_label(pass1, i) && targets.contains(pass1.labels[i].as_str()) {
+            current = Some((i, false));
+        }
+        let Some((start, saved)) = current.as_mut() else {
+            continue;
+        };
+
+        match pass1.instr[i].trim_start_matches('+') {
+            "STL" => *saved = true,
+            "JSUB" if !*saved => {
+                warnings.push((i, Diagnostic::warning(
+                    "jsub-without-stl",
+                    format!("JSUB inside subroutine '{}' overwrites L before it is saved with STL", pass1.labels[*start]),
+                    &pass1.sources[i],
+                ).with_related(&pass1.sources[*start], "subroutine starts here")));
+            }
+            "RSUB" => current = None,
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::{assemble_text, AssemblyOptions};
+
+    // (code, line) of each lint warning for `source`
+    fn warnings(source: &str) -> Vec<(String, usize)> {
+        let options = AssemblyOptions { lint: true, ..AssemblyOptions::default() };
+        assemble_text(source, &options).unwrap().diagnostics.into_iter()
+            .map(|diagnostic| (diagnostic.code, diagnostic.location.line))
+            .collect()
+    }
+
+    #[test]
+    fn warns_about_suspicious_code() {
+        let source = "\
+PROG    START  0
+FIRST   LDA    #1
+        STA    LOOP
+        JSUB   ROUT
+LOOP    J      LOOP
+        LDA    #2
+ROUT    JSUB   OTHER
+        RSUB
+OTHER   RSUB
+SPARE   WORD   0
+        END    FIRST
+";
+        assert_eq!(warnings(source), [
+            ("write-to-code".to_string(), 3),
+            ("unreachable-code".to_string(), 6),
+            ("jsub-without-stl".to_string(), 7),
+            ("unused-label".to_string(), 10),
+        ]);
+    }
+
+    #[test]
+    fn honours_allow_pragmas() {
+        let source = "\
+PROG    START  0
+FIRST   RSUB
+SPARE   WORD   0       ; lint: allow(unused-label)
+OTHER   WORD   0       ; lint: allow(all)
+        END    FIRST
+";
+        assert_eq!(warnings(source), []);
+    }
+}
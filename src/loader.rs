@@ -0,0 +1,273 @@
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use crate::emulator::MEMORY_SIZE;
+use crate::object_program::{ExternalSymbol, HeaderRecord, ObjectProgram, TextRecord, MAX_HALF_BYTES};
+
+/// Where one control section was loaded and the symbols it defines, all at
+/// absolute addresses.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LoadedSection {
+    pub name: String,
+    pub address: usize,
+    pub length: usize,
+    pub symbols: Vec<ExternalSymbol>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LoadMap {
+    pub progaddr: usize,
+    pub entry: usize,
+    pub sections: Vec<LoadedSection>,
+}
+
+impl LoadMap {
+    /// The external symbol table: section names and `D` symbols with their
+    /// absolute addresses.
+    pub fn estab(&self) -> HashMap<String, usize> {
+        self.sections.iter()
+            .flat_map(|section| {
+                std::iter::once((section.name.clone(), section.address))
+                    .chain(section.symbols.iter().map(|symbol| (symbol.name.clone(), symbol.address)))
+            })
+            .collect()
+    }
+
+    /// The load map as printed by the textbook loader.
+    pub fn render(&self) -> String {
+        let mut out = format!("{:<10} {:<10} {:<8} {}\n", "Section", "Symbol", "Address", "Length");
+        for section in &self.sections {
+            out.push_str(&format!("{:<10} {:<10} {:06X}   {:06X}\n", section.name, "", section.address, section.length));
+            for symbol in &section.symbols {
+                out.push_str(&format!("{:<10} {:<10} {:06X}\n", "", symbol.name, symbol.address));
+            }
+        }
+        out.push_str(&format!("\nEntry point: {:06X}\n", self.entry));
+        out
+    }
+}
+
+/// Control sections linked into one absolute program.
+#[derive(Debug, Clone)]
+pub struct Linked {
+    /// The memory image as an absolute object program (no `D`, `R` or `M`
+    /// records), named after the first section; ready for
+    /// `emulator::run` or for writing out
+    pub program: ObjectProgram,
+    pub map: LoadMap,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum LinkError {
+    /// A section name or `D` symbol defined by more than one section
+    DuplicateSymbol { name: String, section: String, first_section: String },
+    /// An `M` record names a symbol no section defines
+    UndefinedSymbol { name: String, section: String, address: usize },
+    /// The section would extend past the end of memory
+    OutOfMemory { section: String, address: usize, length: usize },
+    /// An `M` record's length is more half-bytes than a word holds
+    InvalidModification { section: String, address: usize, half_bytes: usize },
+}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LinkError::DuplicateSymbol { name, section, first_section } => write!(
+                f, "Duplicate external symbol '{}' in {} (already defined in {})", name, section, first_section,
+            ),
+            LinkError::UndefinedSymbol { name, section, address } => write!(
+                f, "Undefined external symbol '{}' in M record at {:06X} of {}", name, address, section,
+            ),
+            LinkError::OutOfMemory { section, address, length } => write!(
+                f, "{} ({:06X} bytes at {:06X}) does not fit in memory", section, length, address,
+            ),
+            LinkError::InvalidModification { section, address, half_bytes } => write!(
+                f, "M record at {:06X} of {} modifies {} half-bytes, more than a word holds", address, section, half_bytes,
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LinkError {}
+
+/// Loads `programs` one after another from `progaddr`, in the usual two
+/// passes: the first assigns each control section its address and builds
+/// the external symbol table, the second places the text records and
+/// applies the modification records. `M` records without a symbol, or
+/// naming the section itself, add how far the section moved from its
+/// header start address. The entry point is the first `E` record address
+/// given, or `progaddr`.
+///
+/// Each program's addresses are taken relative to its header start, so
+/// sections assembled with `START 0` and absolute programs load the same
+/// way. All undefined and duplicate symbols are reported, not just the first.
+pub fn link(programs: &[ObjectProgram], progaddr: usize) -> Result<Linked, Vec<LinkError>> {
+    let mut errors = Vec::new();
+
+    // pass 1: section addresses and ESTAB
+    let mut sections = Vec::new();
+    let mut estab: HashMap<String, (usize, String)> = HashMap::new();
+    let mut csaddr = progaddr;
+    for program in programs {
+        let section = program.header.name.clone();
+        if csaddr + program.header.length > MEMORY_SIZE {
+            errors.push(LinkError::OutOfMemory { section: section.clone(), address: csaddr, length: program.header.length });
+        }
+
+        let mut define = |name: &str, address: usize| match estab.get(name) {
+            Some((_, first_section)) => errors.push(LinkError::DuplicateSymbol {
+                name: name.to_string(),
+                section: section.clone(),
+                first_section: first_section.clone(),
+            }),
+            None => {
+                estab.insert(name.to_string(), (address, section.clone()));
+            }
+        };
+        define(&section, csaddr);
+        let symbols: Vec<ExternalSymbol> = program.definitions.iter()
+            .map(|symbol| ExternalSymbol {
+                name: symbol.name.clone(),
+                address: (csaddr + symbol.address).saturating_sub(program.header.start),
+            })
+            .collect();
+        for symbol in &symbols {
+            define(&symbol.name, symbol.address);
+        }
+
+        sections.push(LoadedSection { name: section, address: csaddr, length: program.header.length, symbols });
+        csaddr += program.header.length;
+    }
+
+    // pass 2: text and modification records; a duplicate symbol keeps its
+    // first definition so undefined symbols are still found
+    let mut memory: BTreeMap<usize, u8> = BTreeMap::new();
+    let mut entry = None;
+    for (program, section) in programs.iter().zip(&sections) {
+        let relocate = |address: usize| section.address + address - program.header.start;
+        // how far the section moved from where it was assembled; wraps when
+        // it is loaded below its START address, which `modify` masks away
+        let delta = section.address.wrapping_sub(program.header.start);
+        for record in &program.text {
+            for (k, &byte) in record.bytes.iter().enumerate() {
+                memory.insert(relocate(record.address + k), byte);
+            }
+        }
+
+        for modification in &program.modifications {
+            if modification.half_bytes > MAX_HALF_BYTES {
+                errors.push(LinkError::InvalidModification {
+                    section: section.name.clone(),
+                    address: modification.address,
+                    half_bytes: modification.half_bytes,
+                });
+                continue;
+            }
+            let value = match &modification.symbol {
+                None => delta,
+                Some(name) if *name == section.name => delta,
+                Some(name) => match estab.get(name) {
+                    Some(&(address, _)) => address,
+                    None => {
+                        errors.push(LinkError::UndefinedSymbol {
+                            name: name.clone(),
+                            section: section.name.clone(),
+                            address: modification.address,
+                        });
+                        continue;
+                    }
+                },
+            };
+            modify(&mut memory, relocate(modification.address), modification.half_bytes, value, modification.subtract);
+        }
+
+        if entry.is_none() {
+            entry = program.entry.map(relocate);
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let entry = entry.unwrap_or(progaddr);
+    let name = sections.first().map_or_else(String::new, |section| section.name.clone());
+    let program = ObjectProgram {
+        header: HeaderRecord { name, start: progaddr, length: csaddr - progaddr },
+        definitions: Vec::new(),
+        references: Vec::new(),
+        text: contiguous_records(&memory),
+        modifications: Vec::new(),
+        entry: Some(entry),
+    };
+    Ok(Linked { program, map: LoadMap { progaddr, entry, sections } })
+}
+
+// adds (or subtracts) `value` to the rightmost `half_bytes` half-bytes of
+// the bytes at `address`; bytes no text record set count as 00
+fn modify(memory: &mut BTreeMap<usize, u8>, address: usize, half_bytes: usize, value: usize, subtract: bool) {
+    let len = half_bytes.div_ceil(2);
+    let current = (address..address + len)
+        .fold(0u64, |acc, a| (acc << 8) | *memory.get(&a).unwrap_or(&0) as u64);
+    let mask = (1u64 << (half_bytes * 4)) - 1;
+    let field = current & mask;
+    let updated = if subtract { field.wrapping_sub(value as u64) } else { field.wrapping_add(value as u64) } & mask;
+    let word = (current & !mask) | updated;
+    for k in 0..len {
+        memory.insert(address + k, (word >> (8 * (len - 1 - k))) as u8);
+    }
+}
+
+// one text record per run of consecutive addresses
+fn contiguous_records(memory: &BTreeMap<usize, u8>) -> Vec<TextRecord> {
+    let mut records: Vec<TextRecord> = Vec::new();
+    for (&address, &byte) in memory {
+        match records.last_mut() {
+            Some(record) if record.address + record.bytes.len() == address => record.bytes.push(byte),
+            _ => records.push(TextRecord { address, bytes: vec![byte] }),
+        }
+    }
+    records
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_program::ModificationRecord;
+
+    fn program(text: &str) -> ObjectProgram {
+        ObjectProgram::parse(text).unwrap()
+    }
+
+    #[test]
+    fn relocates_m_records_by_the_load_delta() {
+        // +JSUB to 001004, assembled with START 1000
+        let source = program("HPROG  001000000007\nT001000074B101004000000\nM00100105\nE001000\n");
+
+        let in_place = link(std::slice::from_ref(&source), 0x1000).unwrap();
+        assert_eq!(in_place.program.text[0].bytes, [0x4B, 0x10, 0x10, 0x04, 0x00, 0x00, 0x00]);
+
+        let moved = link(std::slice::from_ref(&source), 0x4000).unwrap();
+        assert_eq!(moved.program.header.start, 0x4000);
+        assert_eq!(moved.program.text[0].bytes, [0x4B, 0x10, 0x40, 0x04, 0x00, 0x00, 0x00]);
+        assert_eq!(moved.program.entry, Some(0x4000));
+    }
+
+    #[test]
+    fn resolves_external_symbols_across_sections() {
+        let main = program("HMAIN  000000000003\nRSUB\nT00000003000000\nM00000006+SUB\nE000000\n");
+        let sub = program("HSUBS  000000000003\nDSUB   000001\nT000000034C0000\nE\n");
+        let linked = link(&[main, sub], 0x2000).unwrap();
+        assert_eq!(linked.map.estab()["SUB"], 0x2004);
+        assert_eq!(linked.program.text[0].bytes[..3], [0x00, 0x20, 0x04]);
+    }
+
+    #[test]
+    fn reports_m_records_longer_than_a_word() {
+        let mut source = program(&format!("HTEST  000000000010\nT00000010{}\nE000000\n", "00".repeat(16)));
+        source.modifications.push(ModificationRecord { address: 0, half_bytes: 16, subtract: false, symbol: None });
+        let errors = link(&[source], 0).unwrap_err();
+        assert_eq!(errors, [LinkError::InvalidModification { section: "TEST".to_string(), address: 0, half_bytes: 16 }]);
+    }
+}
@@ -0,0 +1,669 @@
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+use crate::assembler::{self, AssembleError, AssemblyOptions};
+use crate::conversions::get_register_value;
+use crate::diagnostics::{Diagnostic, Severity};
+use crate::framing::{read_message, write_message};
+use crate::pass1::DIRECTIVES;
+use crate::pass2::{Pass2, FORMAT1, FORMAT2, FORMAT3, FORMAT4};
+use crate::source::{SourceFormat, SourceLocation};
+use crate::tables::TableEntry;
+
+const REGISTERS: [&str; 9] = ["A", "X", "L", "B", "S", "T", "F", "PC", "SW"];
+
+// LSP enumeration values
+const DIAGNOSTIC_ERROR: u8 = 1;
+const DIAGNOSTIC_WARNING: u8 = 2;
+const COMPLETION_VARIABLE: u8 = 6;
+const COMPLETION_KEYWORD: u8 = 14;
+const COMPLETION_CONSTANT: u8 = 21;
+const SYMBOL_MODULE: u8 = 2;
+const SYMBOL_FUNCTION: u8 = 12;
+const SYMBOL_VARIABLE: u8 = 13;
+const SYMBOL_CONSTANT: u8 = 14;
+const METHOD_NOT_FOUND: i64 = -32601;
+const REQUEST_FAILED: i64 = -32803;
+
+/// Serves Language Server Protocol messages read from `input` until the
+/// client sends `exit` or `input` ends. Open documents are assembled on every
+/// change, from their unsaved text, to produce diagnostics and the symbols
+/// that hover, go-to-definition, references and completion work from.
+pub fn serve(mut input: impl BufRead, output: impl Write) -> io::Result<()> {
+    let mut server = Server::new(output);
+    while let Some(message) = read_message(&mut input)? {
+        if !server.handle(&message)? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+// one source statement of an analysed document or of a file it includes
+struct Statement {
+    path: PathBuf,
+    /// 0-based, as LSP positions are
+    line: usize,
+    text: String,
+    label: Option<String>,
+    opcode: String,
+}
+
+#[derive(Default)]
+struct Analysis {
+    statements: Vec<Statement>,
+    /// From the last assembly that got through Pass 1, so they stay
+    /// available while the source is being edited into shape
+    symbols: Vec<TableEntry>,
+    literals: Vec<TableEntry>,
+}
+
+struct Document {
+    path: PathBuf,
+    text: String,
+    analysis: Analysis,
+    /// Files diagnostics were last published for
+    published: HashSet<PathBuf>,
+}
+
+struct Server<W: Write> {
+    output: W,
+    documents: HashMap<String, Document>,
+    options: AssemblyOptions,
+    work: PathBuf,
+}
+
+impl<W: Write> Server<W> {
+    fn new(output: W) -> Self {
+        Self {
+            output,
+            documents: HashMap::new(),
+            options: AssemblyOptions::default(),
+            work: std::env::temp_dir().join(format!("systems_project-lsp-{}", std::process::id())),
+        }
+    }
+
+    // answers requests and applies notifications; `false` on `exit`
+    fn handle(&mut self, message: &Value) -> io::Result<bool> {
+        let method = message["method"].as_str().unwrap_or_default();
+        let params = &message["params"];
+        let result = match method {
+            "initialize" => Ok(self.initialize(params)),
+            "shutdown" => Ok(Value::Null),
+            "exit" => {
+                let _ = fs::remove_dir_all(&self.work);
+                return Ok(false);
+            }
+            "textDocument/didOpen" => {
+                let document = &params["textDocument"];
+                let uri = document["uri"].as_str().unwrap_or_default().to_string();
+                let text = document["text"].as_str().unwrap_or_default().to_string();
+                self.documents.insert(uri.clone(), Document {
+                    path: uri_to_path(&uri),
+                    text,
+                    analysis: Analysis::default(),
+                    published: HashSet::new(),
+                });
+                self.analyze(&uri)?;
+                return Ok(true);
+            }
+            "textDocument/didChange" => {
+                let uri = params["textDocument"]["uri"].as_str().unwrap_or_default().to_string();
+                // full synchronisation: the last change holds the whole text
+                let text = params["contentChanges"].as_array().and_then(|changes| changes.last()).and_then(|change| change["text"].as_str());
+                if let (Some(document), Some(text)) = (self.documents.get_mut(&uri), text) {
+                    document.text = text.to_string();
+                    self.analyze(&uri)?;
+                }
+                return Ok(true);
+            }
+            "textDocument/didSave" => {
+                // a saved include can change any open document
+                let uris: Vec<String> = self.documents.keys().cloned().collect();
+                for uri in uris {
+                    self.analyze(&uri)?;
+                }
+                return Ok(true);
+            }
+            "textDocument/didClose" => {
+                let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+                if let Some(document) = self.documents.remove(uri) {
+                    for path in document.published {
+                        self.publish(&path, Vec::new())?;
+                    }
+                }
+                return Ok(true);
+            }
+            "textDocument/hover" => self.with_document(params, hover),
+            "textDocument/definition" => self.with_document(params, definition),
+            "textDocument/references" => self.with_document(params, references),
+            "textDocument/completion" => self.with_document(params, completion),
+            "textDocument/documentSymbol" => self.with_document(params, document_symbols),
+            _ if message.get("id").is_none() => return Ok(true),
+            _ => Err((METHOD_NOT_FOUND, format!("Unsupported method '{}'", method))),
+        };
+
+        let Some(id) = message.get("id") else {
+            return Ok(true);
+        };
+        let response = match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err((code, message)) => json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } }),
+        };
+        write_message(&mut self.output, &response)?;
+        Ok(true)
+    }
+
+    // `initializationOptions` may set `lint` and `sourceFormat`
+    fn initialize(&mut self, params: &Value) -> Value {
+        let options = &params["initializationOptions"];
+        self.options.lint = options["lint"].as_bool().unwrap_or(false);
+        if let Ok(format) = serde_json::from_value::<SourceFormat>(options["sourceFormat"].clone()) {
+            self.options.source_format = format;
+        }
+        json!({
+            "capabilities": {
+                "positionEncoding": "utf-16",
+                "textDocumentSync": { "openClose": true, "change": 1, "save": true },
+                "hoverProvider": true,
+                "definitionProvider": true,
+                "referencesProvider": true,
+                "completionProvider": { "triggerCharacters": ["+", "#", "@"] },
+                "documentSymbolProvider": true,
+            },
+            "serverInfo": { "name": "systems_project-lsp" },
+        })
+    }
+
+    fn with_document(&self, params: &Value, request: fn(&Document, &Value) -> Value) -> Result<Value, (i64, String)> {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        match self.documents.get(uri) {
+            Some(document) => Ok(request(document, params)),
+            None => Err((REQUEST_FAILED, format!("'{}' is not open", uri))),
+        }
+    }
+
+    // assembles the document's current text and publishes its diagnostics
+    fn analyze(&mut self, uri: &str) -> io::Result<()> {
+        let Some(document) = self.documents.get(uri) else {
+            return Ok(());
+        };
+        let path = document.path.clone();
+        let name = path.file_name().map_or_else(|| "untitled.asm".into(), |name| name.to_string_lossy().into_owned());
+        let dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+
+        // the unsaved text goes in a scratch directory, so includes are found
+        // through the document's own directory as an include path
+        let source_dir = self.work.join("source");
+        let output_dir = self.work.join("output");
+        fs::create_dir_all(&source_dir)?;
+        fs::create_dir_all(&output_dir)?;
+        let source = source_dir.join(&name);
+        fs::write(&source, &document.text)?;
+        let mut options = self.options.clone();
+        options.include_paths.insert(0, dir.to_string_lossy().into_owned());
+
+        let file_path = |file: &str| {
+            if file == name { path.clone() } else { dir.join(file) }
+        };
+        let mut diagnostics = Vec::new();
+        let mut analysis = Analysis::default();
+
+        match assembler::read_source(&source.to_string_lossy(), &options) {
+            Ok(pass1) => {
+                for (i, location) in pass1.sources.iter().enumerate() {
+                    analysis.statements.push(Statement {
+                        path: file_path(&location.file),
+                        line: location.line.saturating_sub(1),
+                        text: pass1.lines[i].clone(),
+                        label: Some(pass1.labels[i].clone()).filter(|label| label != "&" && label != "*"),
+                        opcode: pass1.instr[i].clone(),
+                    });
+                }
+                match assembler::assemble(pass1, &output_dir.to_string_lossy(), &options) {
+                    Ok(assembly) => {
+                        diagnostics = assembly.diagnostics;
+                        analysis.symbols = assembly.symbols;
+                        analysis.literals = assembly.literals;
+                    }
+                    Err(AssembleError::Pass1 { message, diagnostics: found } | AssembleError::Pass2 { message, diagnostics: found }) => {
+                        diagnostics = found;
+                        if diagnostics.is_empty() {
+                            diagnostics.push(Diagnostic::error("pass1", message, &SourceLocation::new(&name, 1)));
+                        }
+                        analysis.symbols = std::mem::take(&mut self.documents.get_mut(uri).unwrap().analysis.symbols);
+                        analysis.literals = std::mem::take(&mut self.documents.get_mut(uri).unwrap().analysis.literals);
+                    }
+                    Err(e) => diagnostics.push(Diagnostic::error("assembly", e.to_string(), &SourceLocation::new(&name, 1))),
+                }
+            }
+            // an include that cannot be read stops everything else
+            Err(e) => diagnostics.push(Diagnostic::error("source", e.to_string(), &SourceLocation::new(&name, 1))),
+        }
+        let _ = fs::remove_file(&source);
+
+        let mut by_file: HashMap<PathBuf, Vec<Value>> = HashMap::new();
+        for diagnostic in &diagnostics {
+            let lsp = self.diagnostic(diagnostic, &file_path);
+            by_file.entry(file_path(&diagnostic.location.file)).or_default().push(lsp);
+        }
+        by_file.entry(path.clone()).or_default();
+
+        let document = self.documents.get_mut(uri).unwrap();
+        document.analysis = analysis;
+        let stale: Vec<PathBuf> = document.published.iter().filter(|file| !by_file.contains_key(*file)).cloned().collect();
+        document.published = by_file.keys().cloned().collect();
+        for file in stale {
+            self.publish(&file, Vec::new())?;
+        }
+        for (file, diagnostics) in by_file {
+            self.publish(&file, diagnostics)?;
+        }
+        Ok(())
+    }
+
+    fn diagnostic(&self, diagnostic: &Diagnostic, file_path: &dyn Fn(&str) -> PathBuf) -> Value {
+        let severity = match diagnostic.severity {
+            Severity::Error => DIAGNOSTIC_ERROR,
+            Severity::Warning => DIAGNOSTIC_WARNING,
+        };
+        let related: Vec<Value> = diagnostic.related.iter()
+            .map(|related| {
+                let path = file_path(&related.location.file);
+                json!({
+                    "location": { "uri": path_to_uri(&path), "range": self.line_range(&path, related.location.line) },
+                    "message": related.message,
+                })
+            })
+            .collect();
+        json!({
+            "range": self.line_range(&file_path(&diagnostic.location.file), diagnostic.location.line),
+            "severity": severity,
+            "code": diagnostic.code,
+            "source": "sicxe",
+            "message": diagnostic.message,
+            "relatedInformation": related,
+        })
+    }
+
+    // the text of 1-based `line`, from its first non-blank character
+    fn line_range(&self, path: &Path, line: usize) -> Value {
+        let open = self.documents.values().find(|document| document.path == path).map(|document| document.text.clone());
+        let text = open.or_else(|| fs::read_to_string(path).ok()).unwrap_or_default();
+        let line = line.saturating_sub(1);
+        let content = text.lines().nth(line).unwrap_or("");
+        let start = content.chars().take_while(|c| c.is_whitespace()).count();
+        range(content, line, start, content.chars().count().max(start))
+    }
+
+    fn publish(&mut self, path: &Path, diagnostics: Vec<Value>) -> io::Result<()> {
+        write_message(&mut self.output, &json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": { "uri": path_to_uri(path), "diagnostics": diagnostics },
+        }))
+    }
+}
+
+impl Document {
+    // the statement on 0-based `line` of this document
+    fn statement(&self, line: usize) -> Option<&Statement> {
+        self.analysis.statements.iter().find(|statement| statement.path == self.path && statement.line == line)
+    }
+
+    // the word under the cursor, with its character range on the line
+    fn word_at(&self, params: &Value) -> Option<(usize, usize, usize, String)> {
+        let line = params["position"]["line"].as_u64()? as usize;
+        let text = self.text.lines().nth(line)?;
+        let character = char_index(text, params["position"]["character"].as_u64()? as usize);
+        let chars: Vec<char> = text.chars().collect();
+        let is_word = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '$';
+        let mut start = character.min(chars.len());
+        while start > 0 && is_word(chars[start - 1]) {
+            start -= 1;
+        }
+        let mut end = character.min(chars.len());
+        while end < chars.len() && is_word(chars[end]) {
+            end += 1;
+        }
+        (start < end).then(|| (line, start, end, chars[start..end].iter().collect::<String>().to_uppercase()))
+    }
+
+    fn symbol(&self, name: &str) -> Option<&TableEntry> {
+        self.analysis.symbols.iter().find(|entry| entry.name == name)
+    }
+}
+
+fn hover(document: &Document, params: &Value) -> Value {
+    let Some((line, start, end, word)) = document.word_at(params) else {
+        return Value::Null;
+    };
+    let statement = document.statement(line);
+    let line_text = document.text.lines().nth(line).unwrap_or("");
+    let in_opcode_field = statement.is_some_and(|statement| opcode_column(statement) == Some(start));
+
+    let literal = statement.and_then(|statement| literal_at(&statement.text, start));
+    let text = if let Some(literal) = literal {
+        document.analysis.literals.iter().find(|entry| entry.name == literal).map(|entry| {
+            format!("**{}** literal at `{:06X}`{}", entry.name, entry.address, block_text(entry))
+        })
+    } else if in_opcode_field {
+        describe_opcode(&word).map(|text| format!("**{}** {}", word, text))
+    } else if let Some(entry) = document.symbol(&word) {
+        let kind = if entry.relative { "relative" } else { "absolute" };
+        Some(format!("**{}** = `{:06X}` ({}){}", entry.name, entry.address, kind, block_text(entry)))
+    } else if REGISTERS.contains(&word.as_str()) {
+        Some(format!("Register **{}** (number {})", word, get_register_value(&word)))
+    } else {
+        None
+    };
+    match text {
+        Some(text) => json!({
+            "contents": { "kind": "markdown", "value": text },
+            "range": range(line_text, line, start, end),
+        }),
+        None => Value::Null,
+    }
+}
+
+fn definition(document: &Document, params: &Value) -> Value {
+    let Some((_, _, _, word)) = document.word_at(params) else {
+        return Value::Null;
+    };
+    let locations: Vec<Value> = document.analysis.statements.iter()
+        .filter(|statement| statement.label.as_deref() == Some(word.as_str()))
+        .filter_map(|statement| {
+            let start = find_word(&statement.text, &word, 0)?;
+            Some(location(&statement.path, &statement.text, statement.line, start, start + word.len()))
+        })
+        .collect();
+    json!(locations)
+}
+
+fn references(document: &Document, params: &Value) -> Value {
+    let Some((_, _, _, word)) = document.word_at(params) else {
+        return Value::Null;
+    };
+    let include_declaration = params["context"]["includeDeclaration"].as_bool().unwrap_or(true);
+    let mut locations = Vec::new();
+    for statement in &document.analysis.statements {
+        let opcode = opcode_column(statement);
+        for (start, token) in tokens(&statement.text) {
+            // the label field is the declaration and the opcode field a mnemonic
+            let declaration = statement.label.as_deref() == Some(token.as_str()) && Some(start) < opcode;
+            if token == word && Some(start) != opcode && (include_declaration || !declaration) {
+                locations.push(location(&statement.path, &statement.text, statement.line, start, start + token.len()));
+            }
+        }
+    }
+    json!(locations)
+}
+
+fn completion(document: &Document, _params: &Value) -> Value {
+    let pass2 = Pass2::new();
+    let mut mnemonics: Vec<&String> = pass2.opcode_table.keys().collect();
+    mnemonics.sort();
+
+    let mut items = Vec::new();
+    for mnemonic in mnemonics {
+        items.push(json!({ "label": mnemonic, "kind": COMPLETION_KEYWORD, "detail": describe_opcode(mnemonic).unwrap_or_default() }));
+    }
+    for directive in DIRECTIVES {
+        items.push(json!({ "label": directive, "kind": COMPLETION_KEYWORD, "detail": "directive" }));
+    }
+    for register in REGISTERS {
+        items.push(json!({ "label": register, "kind": COMPLETION_CONSTANT, "detail": format!("register {}", get_register_value(register)) }));
+    }
+    for entry in &document.analysis.symbols {
+        items.push(json!({ "label": entry.name, "kind": COMPLETION_VARIABLE, "detail": format!("{:06X}", entry.address) }));
+    }
+    json!(items)
+}
+
+fn document_symbols(document: &Document, _params: &Value) -> Value {
+    let symbols: Vec<Value> = document.analysis.statements.iter()
+        .filter(|statement| statement.path == document.path)
+        .filter_map(|statement| {
+            let label = statement.label.as_ref()?;
+            let start = find_word(&statement.text, label, 0)?;
+            let kind = match statement.opcode.as_str() {
+                "START" => SYMBOL_MODULE,
+                "EQU" => SYMBOL_CONSTANT,
+                "BYTE" | "WORD" | "RESB" | "RESW" => SYMBOL_VARIABLE,
+                _ => SYMBOL_FUNCTION,
+            };
+            Some(json!({
+                "name": label,
+                "detail": statement.opcode,
+                "kind": kind,
+                "range": range(&statement.text, statement.line, 0, statement.text.chars().count()),
+                "selectionRange": range(&statement.text, statement.line, start, start + label.len()),
+            }))
+        })
+        .collect();
+    json!(symbols)
+}
+
+// formats and opcode of a mnemonic, or what a directive does, as plain text
+fn describe_opcode(word: &str) -> Option<String> {
+    let mnemonic = word.trim_start_matches('+');
+    let format = if FORMAT1.contains(&mnemonic) {
+        "format 1"
+    } else if FORMAT2.contains(&mnemonic) {
+        "format 2"
+    } else if FORMAT3.contains(&mnemonic) {
+        "format 3, or format 4 with +"
+    } else if FORMAT4.contains(&mnemonic) {
+        "format 4F: runs only when its condition flag is set"
+    } else {
+        return directive_text(mnemonic).map(|text| format!("directive: {}", text));
+    };
+    let opcode = Pass2::new().get_opcode(mnemonic)?;
+    Some(format!("{}, opcode {}", format, opcode))
+}
+
+fn directive_text(directive: &str) -> Option<&'static str> {
+    Some(match directive {
+        "START" => "names the program and gives its start address",
+        "END" => "ends the program; the operand names the first instruction",
+        "BYTE" => "character (C'...') or hex (X'...') constant",
+        "WORD" => "one-word (3-byte) integer constant",
+        "RESB" => "reserves the given number of bytes",
+        "RESW" => "reserves the given number of words",
+        "BASE" => "the value assumed in B for base-relative addressing",
+        "LTORG" => "places the pending literal pool here",
+        "USE" => "switches to the named program block",
+        "EQU" => "gives the label the operand's value",
+        "INCLUDE" | "COPY" => "inserts another source file",
+        _ => return None,
+    })
+}
+
+fn block_text(entry: &TableEntry) -> String {
+    entry.block.as_ref().map(|block| format!(" in block {}", block)).unwrap_or_default()
+}
+
+// where the statement's opcode starts, looking past the label
+fn opcode_column(statement: &Statement) -> Option<usize> {
+    let after_label = match &statement.label {
+        Some(label) => find_word(&statement.text, label, 0)? + label.len(),
+        None => 0,
+    };
+    find_word(&statement.text, statement.opcode.trim_start_matches('+'), after_label)
+}
+
+// the character where `word` first appears in `text` at or after character
+// `from`, ignoring ASCII case
+fn find_word(text: &str, word: &str, from: usize) -> Option<usize> {
+    let chars: Vec<char> = text.chars().collect();
+    let word: Vec<char> = word.chars().collect();
+    (from..=chars.len().checked_sub(word.len())?)
+        .find(|&k| chars[k..k + word.len()].iter().zip(&word).all(|(a, b)| a.eq_ignore_ascii_case(b)))
+}
+
+// the literal (`=C'EOF'`, `=X'05'`) whose text covers character `at`
+fn literal_at(text: &str, at: usize) -> Option<String> {
+    let chars: Vec<char> = text.to_uppercase().chars().collect();
+    let mut k = 0;
+    while k + 3 < chars.len() {
+        if chars[k] == '=' && matches!(chars[k + 1], 'C' | 'X') && chars[k + 2] == '\'' {
+            let close = k + 3 + chars[k + 3..].iter().position(|&c| c == '\'')?;
+            if (k..=close).contains(&at) {
+                return Some(chars[k..=close].iter().collect());
+            }
+            k = close;
+        }
+        k += 1;
+    }
+    None
+}
+
+// identifiers outside quotes and comments, with their starting character
+fn tokens(text: &str) -> Vec<(usize, String)> {
+    let mut tokens = Vec::new();
+    let mut in_quote = false;
+    let mut current: Option<(usize, String)> = None;
+    for (k, c) in text.chars().enumerate() {
+        if c == ';' && !in_quote {
+            break;
+        }
+        if c == '\'' {
+            in_quote = !in_quote;
+        }
+        if !in_quote && (c.is_ascii_alphanumeric() || c == '_' || c == '$') {
+            current.get_or_insert_with(|| (k, String::new())).1.push(c.to_ascii_uppercase());
+        } else if let Some(token) = current.take() {
+            tokens.push(token);
+        }
+    }
+    tokens.extend(current);
+    tokens
+}
+
+// positions are counted in UTF-16 code units, as LSP requires; `start` and
+// `end` are character indexes into `text`
+fn range(text: &str, line: usize, start: usize, end: usize) -> Value {
+    json!({
+        "start": { "line": line, "character": utf16_column(text, start) },
+        "end": { "line": line, "character": utf16_column(text, end) },
+    })
+}
+
+fn location(path: &Path, text: &str, line: usize, start: usize, end: usize) -> Value {
+    json!({ "uri": path_to_uri(path), "range": range(text, line, start, end) })
+}
+
+// UTF-16 code units before character `index` of `text`
+fn utf16_column(text: &str, index: usize) -> usize {
+    text.chars().take(index).map(char::len_utf16).sum()
+}
+
+// the character at UTF-16 offset `column` of `text`
+fn char_index(text: &str, column: usize) -> usize {
+    let mut units = 0;
+    for (k, c) in text.chars().enumerate() {
+        if units >= column {
+            return k;
+        }
+        units += c.len_utf16();
+    }
+    text.chars().count()
+}
+
+fn uri_to_path(uri: &str) -> PathBuf {
+    let path = uri.strip_prefix("file://").unwrap_or(uri);
+    let mut bytes = Vec::new();
+    let mut rest = path.bytes();
+    while let Some(byte) = rest.next() {
+        let escaped = (byte == b'%').then(|| {
+            let digits = [rest.next()?, rest.next()?];
+            u8::from_str_radix(std::str::from_utf8(&digits).ok()?, 16).ok()
+        });
+        bytes.push(escaped.flatten().unwrap_or(byte));
+    }
+    PathBuf::from(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+fn path_to_uri(path: &Path) -> String {
+    let mut uri = String::from("file://");
+    for byte in path.to_string_lossy().bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' => uri.push(byte as char),
+            _ => uri.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    uri
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::framing::exchange;
+
+    const URI: &str = "file:///tmp/sicxe-lsp-test/main.asm";
+
+    // the responses and notifications for `requests`, sent after opening
+    // `text` as the document at URI
+    fn session(text: &str, requests: &[Value]) -> Vec<Value> {
+        let mut messages = vec![
+            json!({ "jsonrpc": "2.0", "id": 0, "method": "initialize", "params": {} }),
+            json!({ "jsonrpc": "2.0", "method": "textDocument/didOpen", "params": {
+                "textDocument": { "uri": URI, "languageId": "sicxe", "version": 1, "text": text },
+            } }),
+        ];
+        messages.extend_from_slice(requests);
+        exchange(&messages, |input, output| serve(input, output))
+    }
+
+    fn request(id: u64, method: &str, line: usize, character: usize) -> Value {
+        json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": {
+            "textDocument": { "uri": URI },
+            "position": { "line": line, "character": character },
+            "context": { "includeDeclaration": true },
+        } })
+    }
+
+    fn response(messages: &[Value], id: u64) -> &Value {
+        &messages.iter().find(|message| message["id"] == id).unwrap()["result"]
+    }
+
+    #[test]
+    fn counts_columns_in_utf16() {
+        assert_eq!(utf16_column("a😀b", 2), 3);
+        assert_eq!(utf16_column("aéb", 3), 3);
+        assert_eq!(char_index("a😀b", 3), 2);
+        assert_eq!(char_index("ab", 10), 2);
+        assert_eq!(find_word("x é value", "VALUE", 0), Some(4));
+        assert_eq!(find_word("LOOP LOOP", "loop", 1), Some(5));
+    }
+
+    #[test]
+    fn answers_hover_definition_and_references() {
+        let text = "PROG    START  0\nFIRST   LDA    VALUE\n        RSUB\nVALUE   WORD   5\n        END    FIRST\n";
+        let messages = session(text, &[
+            request(1, "textDocument/hover", 1, 17),
+            request(2, "textDocument/definition", 1, 17),
+            request(3, "textDocument/references", 3, 0),
+            request(4, "textDocument/hover", 1, 9),
+        ]);
+        assert_eq!(response(&messages, 0)["capabilities"]["positionEncoding"], "utf-16");
+        assert!(response(&messages, 1)["contents"]["value"].as_str().unwrap().starts_with("**VALUE** = `000006`"));
+        assert_eq!(response(&messages, 2)[0]["range"]["start"], json!({ "line": 3, "character": 0 }));
+        let lines: Vec<&Value> = response(&messages, 3).as_array().unwrap().iter().map(|location| &location["range"]["start"]["line"]).collect();
+        assert_eq!(lines, [1, 3]);
+        assert!(response(&messages, 4)["contents"]["value"].as_str().unwrap().starts_with("**LDA**"));
+    }
+
+    #[test]
+    fn publishes_diagnostics_with_utf16_ranges() {
+        let text = "PROG    START  0\nFIRST   LDA    NOWHERE ; 😀\n        RSUB\n        END    FIRST\n";
+        let messages = session(text, &[]);
+        let published = messages.iter().find(|message| message["method"] == "textDocument/publishDiagnostics").unwrap();
+        let diagnostic = &published["params"]["diagnostics"][0];
+        assert_eq!(diagnostic["code"], "undefined-symbol");
+        assert_eq!(diagnostic["range"]["end"], json!({ "line": 1, "character": 27 }));
+    }
+}
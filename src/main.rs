@@ -1,37 +1,1134 @@
-use systems_project::pass1::Pass1;
-use systems_project::pass2::Pass2;
-use std::env;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use serde::Serialize;
+use systems_project::assembler::{self, AssembleError, Assembly, AssemblyOptions};
+use systems_project::debug_info::DebugInfo;
+use systems_project::debugger::{Debugger, Stop, Watch};
+use systems_project::diagnostics::Diagnostic;
+use systems_project::devices::{Device, DeviceOutput, Devices, EndOfFile, Readiness};
+use systems_project::conversions::get_register_name;
+use systems_project::disassembler;
+use systems_project::emulator::{self, EmulatorOptions, Halt, Machine, Run, DEFAULT_STEP_LIMIT};
+use systems_project::formatter;
+use systems_project::image::{self, ExportOptions, ImageFormat};
+use systems_project::loader::{self, Linked};
+use systems_project::object_program::ObjectProgram;
+use systems_project::pass2::MAX_TEXT_RECORD_LEN;
+use systems_project::profiler::{self, Profiler};
+use systems_project::source::SourceFormat;
+use systems_project::tables::TableOrder;
+use systems_project::testing::{self, TestFile, TestReport};
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Range;
 use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, SystemTime};
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
-    
-    if args.len() < 2 {
-        eprintln!("Usage: cargo run -- <job_id>");
-        std::process::exit(1);
-    }
-    
-    let job_id = &args[1];
-    let input_dir = format!("jobs/{}/input", job_id);
-    let output_dir = format!("jobs/{}/output", job_id);
-    let input_file = format!("{}/in.txt", input_dir);
-    
-    // Create directory structure
-    fs::create_dir_all(&input_dir).expect("Failed to create input directory");
-    fs::create_dir_all(&output_dir).expect("Failed to create output directory");
-    
-    let intermediate_path = format!("{}/intermediate.txt", output_dir);
-    let symbol_path = format!("{}/symbTable.txt", output_dir);
-    let literal_path = format!("{}/litTable.txt", output_dir);
-    let object_path = format!("{}/object_program.txt", output_dir);
-    
-    let mut pass1 = Pass1::new();
-    let _ = pass1.process_file(&input_file);
-    if let Err(e) = pass1.pass1_generator(&output_dir) {
-        eprintln!("Error: {}", e);
-        std::process::exit(1);
-    }
-
-    let mut pass2 = Pass2::new();
-    let _ = pass2.pass2_generator(&intermediate_path, &symbol_path, &literal_path, &object_path);
+/// Name given to source read from standard input in diagnostics.
+const STDIN_NAME: &str = "stdin";
+/// How often `--watch` checks the source files for changes.
+const WATCH_INTERVAL: Duration = Duration::from_millis(300);
+/// Lines and subroutines listed in the `run --profile` summary.
+const PROFILE_SUMMARY_LEN: usize = 10;
+
+#[derive(Parser)]
+#[command(name = "systems_project", about = "SIC/XE assembler", version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Assemble a source file
+    Assemble(AssembleArgs),
+    /// Assemble every .asm file in a directory in parallel
+    Batch(BatchArgs),
+    /// Turn an H/T/E object program back into assembler source
+    Disassemble {
+        /// Object program file, or `-` for standard input
+        #[arg(default_value = "-")]
+        file: String,
+        /// Output file; standard output when omitted or `-`
+        #[arg(short, long)]
+        output: Option<String>,
+        /// Debug info from `--emit debug`, to name each line's source
+        #[arg(long, value_name = "FILE")]
+        debug_info: Option<String>,
+    },
+    /// Run an object program on the emulator and print the final registers
+    Run(RunArgs),
+    /// Debug a program on the emulator with an interactive monitor
+    Debug(DebugArgs),
+    /// Link object programs (control sections) into one absolute program
+    Link {
+        /// Object program files, loaded in this order
+        #[arg(required = true)]
+        files: Vec<String>,
+        /// Load address of the first section (hex) [default: its own start address]
+        #[arg(long, value_name = "ADDR", value_parser = parse_hex_address)]
+        progaddr: Option<usize>,
+        /// Print the load map instead of the linked program
+        #[arg(long)]
+        map: bool,
+        /// Output file; standard output when omitted or `-`
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+    /// Re-emit source with aligned columns
+    Format {
+        /// Source file, or `-` for standard input
+        #[arg(default_value = "-")]
+        file: String,
+        /// Rewrite the file in place instead of printing it
+        #[arg(short, long)]
+        write: bool,
+    },
+    /// Assemble programs and run the test cases in JSON test files
+    Test {
+        /// Test files
+        #[arg(required = true)]
+        files: Vec<String>,
+        /// Print the results as JSON
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(Args)]
+struct AssembleArgs {
+    /// Source file, or `-` for standard input
+    #[arg(default_value = "-")]
+    file: String,
+    /// Output file, or a directory when several artifacts are emitted;
+    /// `-` or no value writes to standard output
+    #[arg(short, long)]
+    output: Option<String>,
+    /// Artifacts to produce, comma-separated
+    #[arg(long, value_enum, value_delimiter = ',', default_value = "object")]
+    emit: Vec<Artifact>,
+    /// Re-assemble whenever the source or a file it includes changes
+    #[arg(short, long)]
+    watch: bool,
+    #[command(flatten)]
+    build: BuildArgs,
+    #[command(flatten)]
+    export: ExportArgs,
+}
+
+#[derive(Args)]
+struct BatchArgs {
+    /// Directory whose .asm files are assembled (not recursive)
+    dir: String,
+    /// Directory for the artifacts and summary.json [default: <DIR>/out]
+    #[arg(short, long)]
+    output: Option<String>,
+    /// Artifacts to produce for each file, comma-separated
+    #[arg(long, value_enum, value_delimiter = ',', default_value = "object")]
+    emit: Vec<Artifact>,
+    /// Number of files assembled at once [default: number of CPUs]
+    #[arg(short, long)]
+    jobs: Option<usize>,
+    #[command(flatten)]
+    build: BuildArgs,
+    #[command(flatten)]
+    export: ExportArgs,
+}
+
+#[derive(Args)]
+struct RunArgs {
+    /// Object program files, or `-` for standard input; several files are
+    /// linked first
+    #[arg(default_value = "-")]
+    files: Vec<String>,
+    /// Link and load at this address (hex) instead of the program's own start
+    #[arg(long, value_name = "ADDR", value_parser = parse_hex_address)]
+    progaddr: Option<usize>,
+    /// Stop after this many instructions
+    #[arg(long, default_value_t = DEFAULT_STEP_LIMIT)]
+    steps: u64,
+    /// Print the result as JSON
+    #[arg(long)]
+    json: bool,
+    /// Debug info from `--emit debug`, to report faults by source line
+    #[arg(long, value_name = "FILE")]
+    debug_info: Option<String>,
+    /// Write every executed instruction to FILE, as CSV for a .csv file and
+    /// JSON otherwise
+    #[arg(long, value_name = "FILE")]
+    trace: Option<String>,
+    /// Write instruction counts per source line and subroutine, and memory
+    /// use per byte, to FILE as JSON, and print a summary
+    #[arg(long, value_name = "FILE")]
+    profile: Option<String>,
+    /// Write how often each byte was executed, read and written to FILE as CSV
+    #[arg(long, value_name = "FILE")]
+    heatmap: Option<String>,
+    #[command(flatten)]
+    devices: DeviceArgs,
+}
+
+#[derive(Args)]
+struct DebugArgs {
+    /// An assembler source file (.asm), assembled first so its labels can be
+    /// used, or object program files, linked when there are several
+    #[arg(required = true)]
+    files: Vec<String>,
+    /// Link and load at this address (hex) instead of the program's own start
+    #[arg(long, value_name = "ADDR", value_parser = parse_hex_address)]
+    progaddr: Option<usize>,
+    /// Debug info from `--emit debug` for object files, to show source lines
+    #[arg(long, value_name = "FILE")]
+    debug_info: Option<String>,
+    #[command(flatten)]
+    devices: DeviceArgs,
+}
+
+#[derive(Args)]
+struct DeviceArgs {
+    /// Read device DEV (hex) from FILE, or from standard input for `-` (repeatable)
+    #[arg(long = "input", value_name = "DEV=FILE")]
+    inputs: Vec<String>,
+    /// Write device DEV (hex) to FILE, or to standard output for `-` (repeatable)
+    #[arg(long = "output", value_name = "DEV=FILE")]
+    outputs: Vec<String>,
+    /// Make the devices given with --input/--output busy for N tests before each ready one
+    #[arg(long, value_name = "N")]
+    busy: Option<u32>,
+    /// What RD reads once a device's input is used up
+    #[arg(long, value_enum, default_value = "zero")]
+    eof: EofBehavior,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum EofBehavior {
+    /// Read 00
+    Zero,
+    /// Report the device busy to TD
+    NotReady,
+    /// Stop with a fault
+    Fault,
+}
+
+impl From<EofBehavior> for EndOfFile {
+    fn from(eof: EofBehavior) -> Self {
+        match eof {
+            EofBehavior::Zero => EndOfFile::Byte(0),
+            EofBehavior::NotReady => EndOfFile::NotReady,
+            EofBehavior::Fault => EndOfFile::Fault,
+        }
+    }
+}
+
+#[derive(Args)]
+struct BuildArgs {
+    /// Additional directory to search for INCLUDE/COPY files (repeatable)
+    #[arg(short = 'I', long = "include-path")]
+    include_paths: Vec<String>,
+    /// Read source in fixed columns (label 1-8, opcode 10-15, operand 17-35)
+    #[arg(long)]
+    fixed: bool,
+    /// Enforce the SIC 6-character label limit
+    #[arg(long)]
+    strict: bool,
+    /// Run the lint pass
+    #[arg(long)]
+    lint: bool,
+    /// Sort the symbol and literal tables by address instead of name
+    #[arg(long)]
+    sort_by_address: bool,
+    /// Maximum object code bytes per text record
+    #[arg(long, default_value_t = MAX_TEXT_RECORD_LEN as u64, value_parser = clap::value_parser!(u64).range(1..=255))]
+    max_record_length: u64,
+}
+
+impl BuildArgs {
+    fn options(&self) -> AssemblyOptions {
+        AssemblyOptions {
+            source_format: if self.fixed { SourceFormat::Fixed } else { SourceFormat::Free },
+            strict: self.strict,
+            lint: self.lint,
+            table_order: if self.sort_by_address { TableOrder::Address } else { TableOrder::Name },
+            include_paths: self.include_paths.clone(),
+            max_record_length: self.max_record_length as usize,
+            explain: false,
+            trace: false,
+        }
+    }
+}
+
+#[derive(Args)]
+struct ExportArgs {
+    /// Byte (hex) for gaps in binary, Intel HEX and S-record images; binary
+    /// images fill gaps with 00 otherwise, the record formats skip them
+    #[arg(long, value_name = "XX", value_parser = parse_hex_byte)]
+    fill: Option<u8>,
+    /// Export only addresses START (inclusive) to END (exclusive), in hex
+    #[arg(long, value_name = "START-END", value_parser = parse_hex_range)]
+    range: Option<Range<usize>>,
+}
+
+impl ExportArgs {
+    fn options(&self) -> ExportOptions {
+        ExportOptions { fill: self.fill, range: self.range.clone() }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Artifact {
+    /// H/T/E object program
+    Object,
+    /// Addresses, statements and their object code
+    Listing,
+    /// Pass 1 intermediate file
+    Intermediate,
+    /// Symbol table
+    Symbols,
+    /// Literal table
+    Literals,
+    /// All of the above plus diagnostics as JSON
+    Json,
+    /// Flat memory image
+    Binary,
+    /// Intel HEX memory image
+    Ihex,
+    /// Motorola S-record memory image
+    Srec,
+    /// Source-level debug info (address ranges to source lines) as JSON
+    Debug,
+    /// How each instruction's object code was built, as JSON
+    Encodings,
+    /// What each pass did with each statement, as JSON
+    Trace,
+}
+
+impl Artifact {
+    // file extension used when artifacts are written to a directory
+    fn extension(self) -> &'static str {
+        match self {
+            Artifact::Object => "obj",
+            Artifact::Listing => "lst",
+            Artifact::Intermediate => "int",
+            Artifact::Symbols => "sym",
+            Artifact::Literals => "lit",
+            Artifact::Json => "json",
+            Artifact::Binary => ImageFormat::Binary.extension(),
+            Artifact::Ihex => ImageFormat::Ihex.extension(),
+            Artifact::Srec => ImageFormat::Srec.extension(),
+            Artifact::Debug => "dbg",
+            Artifact::Encodings => "enc.json",
+            Artifact::Trace => "trace.json",
+        }
+    }
+
+    fn render(self, assembly: &Assembly, export: &ExportOptions) -> Vec<u8> {
+        match self {
+            Artifact::Object => assembly.object_program.clone().into_bytes(),
+            Artifact::Listing => assembly.listing.clone().into_bytes(),
+            Artifact::Intermediate => assembly.intermediate.clone().into_bytes(),
+            Artifact::Symbols => assembly.symb_table.clone().into_bytes(),
+            Artifact::Literals => assembly.lit_table.clone().into_bytes(),
+            Artifact::Json => (serde_json::to_string_pretty(assembly).unwrap_or_default() + "\n").into_bytes(),
+            Artifact::Binary => assembly.image.export(ImageFormat::Binary, export),
+            Artifact::Ihex => assembly.image.export(ImageFormat::Ihex, export),
+            Artifact::Srec => assembly.image.export(ImageFormat::Srec, export),
+            Artifact::Debug => (serde_json::to_string_pretty(&assembly.debug_info).unwrap_or_default() + "\n").into_bytes(),
+            Artifact::Encodings => (serde_json::to_string_pretty(&assembly.encodings).unwrap_or_default() + "\n").into_bytes(),
+            Artifact::Trace => (serde_json::to_string_pretty(&assembly.trace).unwrap_or_default() + "\n").into_bytes(),
+        }
+    }
+}
+
+/// Outcome of one file in a batch run.
+#[derive(Serialize)]
+struct BatchResult {
+    file: String,
+    passed: bool,
+    /// Set when the file failed without diagnostics (unreadable source,
+    /// Pass 2 error, artifacts that could not be written)
+    error: Option<String>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+/// Contents of `summary.json` written by a batch run.
+#[derive(Serialize)]
+struct BatchSummary {
+    total: usize,
+    passed: usize,
+    failed: usize,
+    results: Vec<BatchResult>,
+}
+
+/// Scratch directory holding the intermediate files of one assembly; removed
+/// when dropped.
+struct WorkDir {
+    root: PathBuf,
+}
+
+impl WorkDir {
+    fn new(tag: &str) -> Result<Self, String> {
+        let root = std::env::temp_dir().join(format!("systems_project-{}-{}", std::process::id(), tag));
+        fs::create_dir_all(root.join("output")).map_err(|e| format!("Failed to create work directory: {}", e))?;
+        Ok(Self { root })
+    }
+
+    fn assemble(&self, input_path: &Path, options: &AssemblyOptions) -> Result<Assembly, AssembleError> {
+        let output_dir = self.root.join("output");
+        assembler::assemble_file(&input_path.to_string_lossy(), &output_dir.to_string_lossy(), options)
+    }
+}
+
+impl Drop for WorkDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let result = match cli.command {
+        Command::Assemble(args) if args.watch => watch_command(&args),
+        Command::Assemble(args) => assemble_command(&args),
+        Command::Batch(args) => batch_command(&args),
+        Command::Disassemble { file, output, debug_info } => disassemble_command(&file, output.as_deref(), debug_info.as_deref()),
+        Command::Run(args) => run_command(&args),
+        Command::Debug(args) => debug_command(&args),
+        Command::Link { files, progaddr, map, output } => link_command(&files, progaddr, map, output.as_deref()),
+        Command::Format { file, write } => format_command(&file, write),
+        Command::Test { files, json } => test_command(&files, json),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(message) => {
+            report_error(&message);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+// errors are returned as the message to print; an empty message means the
+// details have already been reported
+fn report_error(message: &str) {
+    if !message.is_empty() {
+        eprintln!("error: {}", message);
+    }
+}
+
+fn assemble_command(args: &AssembleArgs) -> Result<(), String> {
+    let work = WorkDir::new("0")?;
+    let mut options = args.build.options();
+    options.explain = args.emit.contains(&Artifact::Encodings);
+    options.trace = args.emit.contains(&Artifact::Trace);
+
+    // standard input is copied into the work directory so the passes can read
+    // it like any other file; its includes resolve from the current directory
+    let input_path = if args.file == "-" {
+        let source = read_input("-")?;
+        let path = work.root.join(STDIN_NAME);
+        fs::write(&path, source).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+        options.include_paths.insert(0, ".".to_string());
+        path
+    } else {
+        PathBuf::from(&args.file)
+    };
+
+    let assembly = assemble_and_report(&work, &input_path, &options)?;
+    let stem = if args.file == "-" {
+        STDIN_NAME.to_string()
+    } else {
+        file_stem(&input_path)
+    };
+    write_artifacts(&assembly, &args.emit, &args.export.options(), args.output.as_deref(), &stem)
+}
+
+// assembles one file, printing its diagnostics with source snippets
+fn assemble_and_report(work: &WorkDir, input_path: &Path, options: &AssemblyOptions) -> Result<Assembly, String> {
+    let source_root = input_path.parent().map(Path::to_path_buf).unwrap_or_default();
+
+    match work.assemble(input_path, options) {
+        Ok(assembly) => {
+            report(&assembly.diagnostics, &source_root);
+            Ok(assembly)
+        }
+        Err(AssembleError::Pass1 { message, diagnostics } | AssembleError::Pass2 { message, diagnostics }) => {
+            if diagnostics.is_empty() {
+                return Err(message);
+            }
+            report(&diagnostics, &source_root);
+            let errors = diagnostics.iter().filter(|d| d.is_error()).count();
+            eprintln!("error: assembly failed with {} error{}", errors, if errors == 1 { "" } else { "s" });
+            Err(String::new())
+        }
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+// runs until interrupted; errors are reported and the watch goes on
+fn watch_command(args: &AssembleArgs) -> Result<(), String> {
+    if args.file == "-" {
+        return Err("--watch needs a file, not standard input".to_string());
+    }
+    let mut options = args.build.options();
+    options.explain = args.emit.contains(&Artifact::Encodings);
+    options.trace = args.emit.contains(&Artifact::Trace);
+    let input_path = PathBuf::from(&args.file);
+
+    loop {
+        let result = WorkDir::new("0")
+            .and_then(|work| assemble_and_report(&work, &input_path, &options))
+            .and_then(|assembly| write_artifacts(&assembly, &args.emit, &args.export.options(), args.output.as_deref(), &file_stem(&input_path)));
+        if let Err(message) = result {
+            report_error(&message);
+        }
+
+        // a missing main file is still watched so that creating it triggers a run
+        let mut watched = assembler::source_files(&args.file, &options);
+        if watched.is_empty() {
+            watched.push(input_path.clone());
+        }
+        eprintln!("watching {} file{} for changes", watched.len(), if watched.len() == 1 { "" } else { "s" });
+
+        let stamps = modified_times(&watched);
+        while modified_times(&watched) == stamps {
+            thread::sleep(WATCH_INTERVAL);
+        }
+        eprintln!();
+    }
+}
+
+fn modified_times(files: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    files.iter()
+        .map(|file| fs::metadata(file).and_then(|meta| meta.modified()).ok())
+        .collect()
+}
+
+fn batch_command(args: &BatchArgs) -> Result<(), String> {
+    let mut options = args.build.options();
+    options.explain = args.emit.contains(&Artifact::Encodings);
+    options.trace = args.emit.contains(&Artifact::Trace);
+    let export = args.export.options();
+    let mut files: Vec<PathBuf> = fs::read_dir(&args.dir)
+        .map_err(|e| format!("{}: {}", args.dir, e))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("asm")))
+        .collect();
+    files.sort();
+    if files.is_empty() {
+        return Err(format!("No .asm files in {}", args.dir));
+    }
+
+    let output_dir = args.output.as_ref().map(PathBuf::from).unwrap_or_else(|| Path::new(&args.dir).join("out"));
+    fs::create_dir_all(&output_dir).map_err(|e| format!("Failed to create {}: {}", output_dir.display(), e))?;
+
+    let jobs = args.jobs
+        .unwrap_or_else(|| thread::available_parallelism().map_or(1, usize::from))
+        .clamp(1, files.len());
+    let next = AtomicUsize::new(0);
+    let slots: Mutex<Vec<Option<BatchResult>>> = Mutex::new(files.iter().map(|_| None).collect());
+
+    // each worker takes the next unassembled file until none are left
+    thread::scope(|scope| {
+        for _ in 0..jobs {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let Some(file) = files.get(i) else {
+                    break;
+                };
+                let result = batch_one(file, i, &output_dir, &args.emit, &export, &options);
+                slots.lock().unwrap()[i] = Some(result);
+            });
+        }
+    });
+
+    let results: Vec<BatchResult> = slots.into_inner().unwrap().into_iter().flatten().collect();
+    let passed = results.iter().filter(|result| result.passed).count();
+    let summary = BatchSummary {
+        total: results.len(),
+        passed,
+        failed: results.len() - passed,
+        results,
+    };
+
+    print!("{}", render_summary(&summary));
+    let summary_path = output_dir.join("summary.json");
+    let json = serde_json::to_string_pretty(&summary).unwrap_or_default() + "\n";
+    fs::write(&summary_path, json).map_err(|e| format!("Failed to write {}: {}", summary_path.display(), e))?;
+
+    if summary.failed > 0 {
+        return Err(String::new());
+    }
+    Ok(())
+}
+
+fn batch_one(
+    path: &Path,
+    index: usize,
+    output_dir: &Path,
+    emit: &[Artifact],
+    export: &ExportOptions,
+    options: &AssemblyOptions,
+) -> BatchResult {
+    let file = path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+    let failed = |error: Option<String>, diagnostics: Vec<Diagnostic>| BatchResult {
+        file: file.clone(),
+        passed: false,
+        error,
+        diagnostics,
+    };
+
+    let work = match WorkDir::new(&index.to_string()) {
+        Ok(work) => work,
+        Err(message) => return failed(Some(message), Vec::new()),
+    };
+    match work.assemble(path, options) {
+        Ok(assembly) => match write_artifact_files(&assembly, emit, export, output_dir, &file_stem(path)) {
+            Ok(()) => BatchResult {
+                file: file.clone(),
+                passed: true,
+                error: None,
+                diagnostics: assembly.diagnostics,
+            },
+            Err(message) => failed(Some(message), assembly.diagnostics),
+        },
+        Err(AssembleError::Pass1 { message, diagnostics } | AssembleError::Pass2 { message, diagnostics }) => {
+            let error = diagnostics.is_empty().then_some(message);
+            failed(error, diagnostics)
+        }
+        Err(e) => failed(Some(e.to_string()), Vec::new()),
+    }
+}
+
+// one line per file, its diagnostics indented beneath it, then the counts
+fn render_summary(summary: &BatchSummary) -> String {
+    let mut out = String::new();
+    for result in &summary.results {
+        let warnings = result.diagnostics.iter().filter(|d| !d.is_error()).count();
+        out.push_str(&format!("{}  {}", if result.passed { "PASS" } else { "FAIL" }, result.file));
+        if result.passed && warnings > 0 {
+            out.push_str(&format!("  ({} warning{})", warnings, if warnings == 1 { "" } else { "s" }));
+        }
+        out.push('\n');
+
+        if let Some(error) = &result.error {
+            for line in error.lines() {
+                out.push_str(&format!("      {}\n", line));
+            }
+        }
+        for diagnostic in &result.diagnostics {
+            out.push_str(&format!("      {}[{}]: {}\n", diagnostic.severity.as_str(), diagnostic.code, diagnostic));
+        }
+    }
+    out.push_str(&format!(
+        "\n{} file{}: {} passed, {} failed\n",
+        summary.total,
+        if summary.total == 1 { "" } else { "s" },
+        summary.passed,
+        summary.failed,
+    ));
+    out
+}
+
+fn file_stem(path: &Path) -> String {
+    path.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_else(|| "out".to_string())
+}
+
+// one artifact goes to the output file, several go into the output directory
+// as <stem>.<ext>; without an output path everything is printed in order
+fn write_artifacts(
+    assembly: &Assembly,
+    emit: &[Artifact],
+    export: &ExportOptions,
+    output: Option<&str>,
+    stem: &str,
+) -> Result<(), String> {
+    match output {
+        None | Some("-") => {
+            let mut stdout = io::stdout().lock();
+            for artifact in emit {
+                stdout.write_all(&artifact.render(assembly, export))
+                    .map_err(|e| format!("Failed to write output: {}", e))?;
+            }
+            Ok(())
+        }
+        Some(path) if emit.len() == 1 => {
+            fs::write(path, emit[0].render(assembly, export)).map_err(|e| format!("Failed to write {}: {}", path, e))
+        }
+        Some(dir) => write_artifact_files(assembly, emit, export, Path::new(dir), stem),
+    }
+}
+
+fn write_artifact_files(
+    assembly: &Assembly,
+    emit: &[Artifact],
+    export: &ExportOptions,
+    dir: &Path,
+    stem: &str,
+) -> Result<(), String> {
+    fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    for artifact in emit {
+        let path = dir.join(format!("{}.{}", stem, artifact.extension()));
+        fs::write(&path, artifact.render(assembly, export))
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+    }
+    Ok(())
+}
+
+// prints diagnostics to stderr with the source line they point at
+fn report(diagnostics: &[Diagnostic], source_root: &Path) {
+    let mut files: HashMap<String, Option<Vec<String>>> = HashMap::new();
+    for diagnostic in diagnostics {
+        let lines = files.entry(diagnostic.location.file.clone()).or_insert_with(|| {
+            fs::read_to_string(source_root.join(&diagnostic.location.file))
+                .ok()
+                .map(|text| text.lines().map(str::to_string).collect())
+        });
+        let line = lines.as_ref()
+            .and_then(|lines| lines.get(diagnostic.location.line.wrapping_sub(1)))
+            .map(String::as_str);
+        eprint!("{}", diagnostic.render(line));
+    }
+}
+
+// reads `file`, or standard input for `-`
+fn read_input(file: &str) -> Result<String, String> {
+    if file == "-" {
+        let mut text = String::new();
+        io::stdin().read_to_string(&mut text).map_err(|e| format!("Failed to read standard input: {}", e))?;
+        Ok(text)
+    } else {
+        fs::read_to_string(file).map_err(|e| format!("{}: {}", file, e))
+    }
+}
+
+fn disassemble_command(file: &str, output: Option<&str>, debug_info: Option<&str>) -> Result<(), String> {
+    let debug_info = debug_info.map(read_debug_info).transpose()?;
+    let source = disassembler::disassemble(&read_input(file)?, debug_info.as_ref())?;
+    match output {
+        None | Some("-") => {
+            print!("{}", source);
+            Ok(())
+        }
+        Some(path) => fs::write(path, source).map_err(|e| format!("Failed to write {}: {}", path, e)),
+    }
+}
+
+fn read_debug_info(path: &str) -> Result<DebugInfo, String> {
+    let json = fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    DebugInfo::parse(&json).map_err(|e| format!("{}: {}", path, e))
+}
+
+fn parse_hex_address(text: &str) -> Result<usize, String> {
+    usize::from_str_radix(text, 16).map_err(|_| format!("'{}' is not a hex address", text))
+}
+
+fn parse_hex_byte(text: &str) -> Result<u8, String> {
+    u8::from_str_radix(text, 16).map_err(|_| format!("'{}' is not a hex byte", text))
+}
+
+// START-END in hex, END exclusive
+fn parse_hex_range(text: &str) -> Result<Range<usize>, String> {
+    let (start, end) = text.split_once('-').ok_or_else(|| format!("'{}' is not a START-END range", text))?;
+    let range = parse_hex_address(start)?..parse_hex_address(end)?;
+    image::check_range(&range)?;
+    Ok(range)
+}
+
+fn read_object_program(file: &str) -> Result<ObjectProgram, String> {
+    ObjectProgram::parse(&read_input(file)?).map_err(|errors| {
+        errors.iter().map(|e| format!("{}: {}", file, e)).collect::<Vec<_>>().join("\n")
+    })
+}
+
+fn read_object_programs(files: &[String]) -> Result<Vec<ObjectProgram>, String> {
+    files.iter().map(|file| read_object_program(file)).collect()
+}
+
+fn link_programs(programs: &[ObjectProgram], progaddr: usize) -> Result<Linked, String> {
+    loader::link(programs, progaddr).map_err(|errors| {
+        errors.iter().map(ToString::to_string).collect::<Vec<_>>().join("\n")
+    })
+}
+
+fn link_command(files: &[String], progaddr: Option<usize>, map: bool, output: Option<&str>) -> Result<(), String> {
+    let programs = read_object_programs(files)?;
+    let linked = link_programs(&programs, progaddr.unwrap_or(programs[0].header.start))?;
+    let text = if map { linked.map.render() } else { linked.program.to_string() };
+    match output {
+        None | Some("-") => {
+            print!("{}", text);
+            Ok(())
+        }
+        Some(path) => fs::write(path, text).map_err(|e| format!("Failed to write {}: {}", path, e)),
+    }
+}
+
+fn run_command(args: &RunArgs) -> Result<(), String> {
+    let mut programs = read_object_programs(&args.files)?;
+    let start = programs[0].header.start;
+    let mut debug_info = args.debug_info.as_deref().map(read_debug_info).transpose()?;
+    let mut symbols = HashMap::new();
+    let program = if programs.len() == 1 && args.progaddr.is_none() {
+        programs.remove(0)
+    } else {
+        let progaddr = args.progaddr.unwrap_or(start);
+        let linked = link_programs(&programs, progaddr)?;
+        symbols = linked.map.estab();
+        linked.program
+    };
+    // the debug info describes the first program where it was assembled
+    if let Some(info) = &mut debug_info {
+        info.relocate(program.header.start as isize - start as isize);
+    }
+    let devices = attach_devices(&args.devices)?;
+    let profiling = args.trace.is_some() || args.profile.is_some() || args.heatmap.is_some();
+    let run = if profiling {
+        let mut machine = Machine::new();
+        machine.devices = devices;
+        machine.load(&program)?;
+        let mut profiler = Profiler::new(machine);
+        profiler.symbols = symbols;
+        profiler.debug_info = debug_info.clone().unwrap_or_default();
+        profiler.record_trace = args.trace.is_some();
+        let halt = profiler.run(args.steps);
+        write_profile(&profiler, args)?;
+        profiler.machine.finish(halt)
+    } else {
+        emulator::run(&program, &EmulatorOptions { step_limit: args.steps }, devices)?
+    };
+
+    if args.json {
+        let json = serde_json::to_string_pretty(&run).map_err(|e| format!("Failed to serialize run: {}", e))?;
+        println!("{}", json);
+    } else {
+        // devices given with --output already wrote their output
+        let shown: Vec<&DeviceOutput> = run.output.iter()
+            .filter(|output| !args.devices.outputs.iter().any(|spec| device_number(spec) == Some(output.device)))
+            .collect();
+        print_run(&run, &shown);
+    }
+    match run.halt {
+        Halt::Fault { address, message } => match debug_info.as_ref().and_then(|info| info.lookup(address)) {
+            Some(range) => Err(format!("Fault at {:06X} ({}): {}", address, range, message)),
+            None => Err(format!("Fault at {:06X}: {}", address, message)),
+        },
+        _ => Ok(()),
+    }
+}
+
+// writes the files asked for with --trace, --profile and --heatmap
+fn write_profile(profiler: &Profiler, args: &RunArgs) -> Result<(), String> {
+    let write = |path: &str, contents: String| fs::write(path, contents).map_err(|e| format!("Failed to write {}: {}", path, e));
+    if let Some(path) = &args.trace {
+        let contents = if Path::new(path).extension().is_some_and(|ext| ext.eq_ignore_ascii_case("csv")) {
+            profiler::trace_csv(&profiler.trace)
+        } else {
+            serde_json::to_string_pretty(&profiler.trace).map_err(|e| format!("Failed to serialize trace: {}", e))? + "\n"
+        };
+        write(path, contents)?;
+    }
+    let profile = profiler.profile();
+    if let Some(path) = &args.profile {
+        write(path, serde_json::to_string_pretty(&profile).map_err(|e| format!("Failed to serialize profile: {}", e))? + "\n")?;
+        if !args.json {
+            eprint!("{}", profile.render(PROFILE_SUMMARY_LEN));
+        }
+    }
+    if let Some(path) = &args.heatmap {
+        write(path, profile.heatmap_csv())?;
+    }
+    Ok(())
+}
+
+fn attach_devices(args: &DeviceArgs) -> Result<Devices, String> {
+    let mut devices = Devices::new();
+    let specs = args.inputs.iter().map(|spec| (spec, true)).chain(args.outputs.iter().map(|spec| (spec, false)));
+    for (spec, input) in specs {
+        let (number, path) = spec.split_once('=')
+            .ok_or_else(|| format!("Invalid device '{}': expected DEV=FILE", spec))?;
+        let number = device_number(spec)
+            .ok_or_else(|| format!("Invalid device number '{}': expected two hex digits", number))?;
+        let mut device = match (input, path) {
+            (true, "-") => Device::stdin(),
+            (false, "-") => Device::stdout(),
+            (true, path) => Device::input_file(path).map_err(|e| format!("{}: {}", path, e))?,
+            (false, path) => Device::output_file(path).map_err(|e| format!("{}: {}", path, e))?,
+        };
+        if let Some(busy) = args.busy {
+            device.readiness = Readiness::Every(busy);
+        }
+        device.end_of_file = args.eof.into();
+        devices.attach(number, device);
+    }
+    Ok(devices)
+}
+
+fn device_number(spec: &str) -> Option<u8> {
+    spec.split_once('=').and_then(|(number, _)| u8::from_str_radix(number, 16).ok())
+}
+
+fn debug_command(args: &DebugArgs) -> Result<(), String> {
+    let is_source = |file: &str| Path::new(file).extension().is_some_and(|ext| ext.eq_ignore_ascii_case("asm"));
+    let (programs, symbols, debug_info) = match args.files.as_slice() {
+        [file] if is_source(file) => {
+            let work = WorkDir::new("debug")?;
+            let assembly = assemble_and_report(&work, Path::new(file), &AssemblyOptions::default())?;
+            let program = ObjectProgram::parse(&assembly.object_program).map_err(|errors| {
+                errors.iter().map(ToString::to_string).collect::<Vec<_>>().join("\n")
+            })?;
+            let symbols: Vec<(String, usize)> = assembly.symbols.into_iter().map(|entry| (entry.name, entry.address)).collect();
+            (vec![program], symbols, Some(assembly.debug_info))
+        }
+        _ => (read_object_programs(&args.files)?, Vec::new(), args.debug_info.as_deref().map(read_debug_info).transpose()?),
+    };
+
+    let start = programs[0].header.start;
+    let linked = link_programs(&programs, args.progaddr.unwrap_or(start))?;
+    let mut machine = Machine::new();
+    machine.devices = attach_devices(&args.devices)?;
+    machine.load(&linked.program)?;
+
+    let mut debugger = Debugger::new(machine);
+    // assembler symbols move with the program; the load map already has absolute addresses
+    let shift = |address: usize| address + linked.map.progaddr - start;
+    debugger.symbols = linked.map.estab();
+    debugger.symbols.extend(symbols.into_iter().map(|(name, address)| (name, shift(address))));
+    if let Some(mut debug_info) = debug_info {
+        debug_info.relocate(linked.map.progaddr as isize - start as isize);
+        debugger.debug_info = debug_info;
+    }
+
+    println!("{} loaded at {:06X}; type 'help' for commands", linked.program.header.name, linked.map.progaddr);
+    print_location(&debugger);
+    let mut stdout = io::stdout();
+    let mut lines = io::stdin().lines();
+    loop {
+        print!("(sicdb) ");
+        stdout.flush().map_err(|e| format!("Failed to write output: {}", e))?;
+        let Some(line) = lines.next() else {
+            println!();
+            return Ok(());
+        };
+        let line = line.map_err(|e| format!("Failed to read standard input: {}", e))?;
+        match monitor_command(&mut debugger, &line) {
+            Ok(true) => return Ok(()),
+            Ok(false) => {}
+            Err(message) => eprintln!("error: {}", message),
+        }
+    }
+}
+
+const MONITOR_HELP: &str = "\
+break|b [LOC]          set a breakpoint at LOC, or list breakpoints
+delete|d LOC           clear the breakpoint at LOC
+watch|w LOC[-END]|REG  stop when memory (one word, or LOC up to END) or a register changes
+unwatch ID             remove a watchpoint
+step|s [N]             execute N instructions (default 1)
+next|n                 step, running a JSUB's whole subroutine
+continue|c             run to the next breakpoint, watchpoint or halt
+until|u LOC            run until PC reaches LOC
+regs|r                 show the registers
+x LOC [LEN]            dump LEN bytes (default 48) from LOC
+list|l [LOC] [N]       disassemble N instructions (default 8) from LOC (default PC)
+quit|q                 leave the monitor
+LOC is a hex address, a label or LABEL+OFFSET (hex).";
+
+// runs one monitor command; `Ok(true)` to quit
+fn monitor_command(debugger: &mut Debugger, line: &str) -> Result<bool, String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let Some((&command, args)) = words.split_first() else {
+        return Ok(false);
+    };
+    let count = |arg: Option<&&str>, default: usize| match arg {
+        Some(text) => text.parse::<usize>().map_err(|_| format!("'{}' is not a count", text)),
+        None => Ok(default),
+    };
+    let location = |arg: Option<&&str>| match arg {
+        Some(text) => debugger.resolve(text),
+        None => Err(format!("'{}' needs a location", command)),
+    };
+
+    match command {
+        "break" | "b" if args.is_empty() => {
+            let labels = debugger.labels();
+            for address in debugger.breakpoints() {
+                println!("{}", describe_address(address, &labels));
+            }
+        }
+        "break" | "b" => {
+            let address = location(args.first())?;
+            if !debugger.set_breakpoint(address) {
+                println!("breakpoint already set at {:06X}", address);
+            }
+        }
+        "delete" | "d" => {
+            let address = location(args.first())?;
+            if !debugger.clear_breakpoint(address) {
+                return Err(format!("No breakpoint at {:06X}", address));
+            }
+        }
+        "watch" | "w" if args.is_empty() => {
+            for watchpoint in debugger.watchpoints() {
+                println!("{}  {}", watchpoint.id, watchpoint.watch);
+            }
+        }
+        "watch" | "w" => {
+            let text = args[0];
+            let register = (0..10).find(|&number| get_register_name(number).is_some_and(|name| name.eq_ignore_ascii_case(text)));
+            let watch = match (register, text.split_once('-')) {
+                (Some(register), _) => Watch::Register { register },
+                (None, Some((start, end))) => Watch::Memory { range: debugger.resolve(start)?..debugger.resolve(end)? },
+                (None, None) => {
+                    let address = debugger.resolve(text)?;
+                    Watch::Memory { range: address..address + 3 }
+                }
+            };
+            let id = debugger.watch(watch)?;
+            println!("watchpoint {}", id);
+        }
+        "unwatch" => {
+            let id = count(args.first(), 0)?;
+            if !debugger.unwatch(id) {
+                return Err(format!("No watchpoint {}", id));
+            }
+        }
+        "step" | "s" => {
+            let mut stop = Stop::Step;
+            for _ in 0..count(args.first(), 1)? {
+                stop = debugger.step();
+                if stop != Stop::Step {
+                    break;
+                }
+            }
+            print_stop(debugger, &stop);
+        }
+        "next" | "n" => {
+            let stop = debugger.step_over();
+            print_stop(debugger, &stop);
+        }
+        "continue" | "c" => {
+            let stop = debugger.resume();
+            print_stop(debugger, &stop);
+        }
+        "until" | "u" => {
+            let address = location(args.first())?;
+            let stop = debugger.run_to(address);
+            print_stop(debugger, &stop);
+        }
+        "regs" | "r" => {
+            let r = &debugger.machine.registers;
+            println!("A={:06X} X={:06X} L={:06X} B={:06X} S={:06X} T={:06X}", r.a, r.x, r.l, r.b, r.s, r.t);
+            println!("F={} PC={:06X} SW={:06X} CC={:?}", r.f, r.pc, r.sw, r.condition_code());
+        }
+        "x" => {
+            let address = location(args.first())?;
+            print!("{}", debugger.dump(address, count(args.get(1), 48)?)?);
+        }
+        "list" | "l" => {
+            let address = match args.first() {
+                Some(_) => location(args.first())?,
+                None => debugger.machine.registers.pc as usize,
+            };
+            let labels = debugger.labels();
+            for (instruction, text) in debugger.disassemble(address, count(args.get(1), 8)?) {
+                let hex: String = instruction.bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+                let marker = if instruction.address == debugger.machine.registers.pc as usize { ">" } else { " " };
+                let label = labels.get(&instruction.address).map_or("", String::as_str);
+                println!("{} {:06X}  {:<8}  {:<8} {}", marker, instruction.address, hex, label, text);
+            }
+        }
+        "help" | "h" => println!("{}", MONITOR_HELP),
+        "quit" | "q" => return Ok(true),
+        _ => return Err(format!("Unknown command '{}'; type 'help' for commands", command)),
+    }
+    Ok(false)
+}
+
+fn describe_address(address: usize, labels: &BTreeMap<usize, String>) -> String {
+    match labels.get(&address) {
+        Some(label) => format!("{:06X} <{}>", address, label),
+        None => format!("{:06X}", address),
+    }
+}
+
+fn print_stop(debugger: &Debugger, stop: &Stop) {
+    if *stop != Stop::Step {
+        println!("{}", stop);
+    }
+    print_location(debugger);
+}
+
+// the instruction about to run
+fn print_location(debugger: &Debugger) {
+    let pc = debugger.machine.registers.pc as usize;
+    if let Some((_, text)) = debugger.disassemble(pc, 1).first() {
+        match debugger.debug_info.lookup(pc) {
+            Some(range) => println!("{}  {}  ({}:{})", describe_address(pc, &debugger.labels()), text, range.file, range.line),
+            None => println!("{}  {}", describe_address(pc, &debugger.labels()), text),
+        }
+    }
+}
+
+fn print_run(run: &Run, output: &[&DeviceOutput]) {
+    let r = &run.registers;
+    println!("{} after {} steps", run.halt, run.steps);
+    println!("A={:06X} X={:06X} L={:06X} B={:06X} S={:06X} T={:06X}", r.a, r.x, r.l, r.b, r.s, r.t);
+    println!("F={} PC={:06X} SW={:06X} CC={:?}", r.f, r.pc, r.sw, r.condition_code());
+    for output in output {
+        println!("device {:02X}: {:?}", output.device, output.text);
+    }
+}
+
+fn test_command(files: &[String], json: bool) -> Result<(), String> {
+    let mut reports = Vec::new();
+    for (k, file) in files.iter().enumerate() {
+        let test_file = TestFile::parse(&read_input(file)?).map_err(|e| format!("{}: {}", file, e))?;
+        // the source and include paths are relative to the test file
+        let root = Path::new(file).parent().map(Path::to_path_buf).unwrap_or_default();
+        let options = AssemblyOptions {
+            include_paths: test_file.include_paths.iter().map(|path| root.join(path).to_string_lossy().into_owned()).collect(),
+            ..AssemblyOptions::default()
+        };
+
+        let work = WorkDir::new(&format!("test-{}", k))?;
+        let assembly = assemble_and_report(&work, &root.join(&test_file.source), &options)?;
+        let program = ObjectProgram::parse(&assembly.object_program).map_err(|errors| {
+            errors.iter().map(ToString::to_string).collect::<Vec<_>>().join("\n")
+        })?;
+        let symbols: HashMap<String, usize> = assembly.symbols.into_iter().map(|entry| (entry.name, entry.address)).collect();
+        let report = testing::run_tests(&test_file, &program, &symbols);
+        if !json {
+            print!("{}", report.render());
+        }
+        reports.push(report);
+    }
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&reports).map_err(|e| format!("Failed to serialize results: {}", e))?);
+    }
+    let failed: usize = reports.iter().map(TestReport::failed).sum();
+    let total: usize = reports.iter().map(|report| report.cases.len()).sum();
+    if failed > 0 {
+        return Err(format!("{} of {} test cases failed", failed, total));
+    }
+    Ok(())
+}
+
+fn format_command(file: &str, write: bool) -> Result<(), String> {
+    if file == "-" && write {
+        return Err("--write needs a file, not standard input".to_string());
+    }
+    let source = read_input(file)?;
+    let formatted = formatter::format_source(&source);
+
+    if write {
+        fs::write(file, formatted).map_err(|e| format!("{}: {}", file, e))
+    } else {
+        print!("{}", formatted);
+        Ok(())
+    }
 }
@@ -1,9 +1,12 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use sqlx::types::Json;
 use sqlx::FromRow;
 use uuid::Uuid;
 use utoipa::ToSchema;
 
+use crate::debug_info::DebugInfo;
+
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
 pub struct User {
     pub id: Uuid,
@@ -28,6 +31,9 @@ pub struct AssemblyJob {
     pub symb_table: Option<String>,
     pub lit_table: Option<String>,
     pub object_program: Option<String>,
+    /// Source lines by address; missing for jobs saved before it was recorded
+    #[schema(value_type = Option<DebugInfo>)]
+    pub debug_info: Option<Json<DebugInfo>>,
     pub created_at: DateTime<Utc>,
 }
 
@@ -0,0 +1,499 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use utoipa::ToSchema;
+
+use crate::pass2::MAX_TEXT_RECORD_LEN;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct HeaderRecord {
+    pub name: String,
+    pub start: usize,
+    pub length: usize,
+}
+
+/// A `D` record entry: a symbol this program defines for others.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct ExternalSymbol {
+    pub name: String,
+    pub address: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct TextRecord {
+    pub address: usize,
+    pub bytes: Vec<u8>,
+}
+
+/// An `M` record: `half_bytes` half-bytes starting at `address` are adjusted
+/// by the value of `symbol` (added for `+`, subtracted for `-`), or by the
+/// program's load address when no symbol is given.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct ModificationRecord {
+    pub address: usize,
+    pub half_bytes: usize,
+    pub subtract: bool,
+    pub symbol: Option<String>,
+}
+
+/// A parsed object program: one `H` record, any `D`, `R`, `T` and `M`
+/// records, and one `E` record with an optional entry point.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct ObjectProgram {
+    pub header: HeaderRecord,
+    pub definitions: Vec<ExternalSymbol>,
+    pub references: Vec<String>,
+    pub text: Vec<TextRecord>,
+    pub modifications: Vec<ModificationRecord>,
+    pub entry: Option<usize>,
+}
+
+/// A problem found while reading an object program. `line` and `column` are
+/// 1-based and point at the offending field.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct ObjectError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl ObjectError {
+    fn new(line: usize, column: usize, message: String) -> Self {
+        Self { line, column, message }
+    }
+}
+
+impl fmt::Display for ObjectError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}, column {}: {}", self.line, self.column, self.message)
+    }
+}
+
+/// Most half-bytes an `M` record may modify: a word, or the 5 of a format 4
+/// address.
+pub const MAX_HALF_BYTES: usize = 6;
+
+// symbols per D and R record when writing
+const SYMBOLS_PER_RECORD: usize = 6;
+
+/// Writes the records in the layout Pass 2 uses, splitting text records
+/// longer than `MAX_TEXT_RECORD_LEN` bytes.
+impl fmt::Display for ObjectProgram {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = &self.header.name[..self.header.name.len().min(6)];
+        writeln!(f, "H{:<6}{:06X}{:06X}", name, self.header.start, self.header.length)?;
+        for chunk in self.definitions.chunks(SYMBOLS_PER_RECORD) {
+            write!(f, "D")?;
+            for symbol in chunk {
+                write!(f, "{:<6}{:06X}", symbol.name, symbol.address)?;
+            }
+            writeln!(f)?;
+        }
+        for chunk in self.references.chunks(SYMBOLS_PER_RECORD) {
+            writeln!(f, "R{}", chunk.iter().map(|name| format!("{:<6}", name)).collect::<String>().trim_end())?;
+        }
+        for record in &self.text {
+            for (k, bytes) in record.bytes.chunks(MAX_TEXT_RECORD_LEN).enumerate() {
+                let code: String = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+                writeln!(f, "T{:06X}{:02X}{}", record.address + k * MAX_TEXT_RECORD_LEN, bytes.len(), code)?;
+            }
+        }
+        for modification in &self.modifications {
+            write!(f, "M{:06X}{:02X}", modification.address, modification.half_bytes)?;
+            if let Some(symbol) = &modification.symbol {
+                write!(f, "{}{}", if modification.subtract { '-' } else { '+' }, symbol)?;
+            }
+            writeln!(f)?;
+        }
+        match self.entry {
+            Some(entry) => writeln!(f, "E{:06X}", entry),
+            None => writeln!(f, "E"),
+        }
+    }
+}
+
+impl ObjectProgram {
+    /// First address after the program.
+    pub fn end(&self) -> usize {
+        self.header.start + self.header.length
+    }
+
+    /// The bytes of all text records by address.
+    pub fn memory(&self) -> BTreeMap<usize, u8> {
+        self.text.iter()
+            .flat_map(|record| record.bytes.iter().enumerate().map(move |(k, &byte)| (record.address + k, byte)))
+            .collect()
+    }
+
+    /// Reads records in the layout Pass 2 writes (`H`, `T`, `E` with
+    /// fixed-width fields), plus `D`, `R` and `M` records. `^` separators as
+    /// printed in the textbook are ignored. Every problem is reported, not
+    /// just the first:
+    ///
+    /// - record and field lengths, including a text record whose length byte
+    ///   does not match its object code
+    /// - non-hex digits in addresses, lengths and object code
+    /// - missing, repeated or misplaced `H`/`E` records
+    /// - text records that overlap each other
+    /// - text, modification and entry addresses outside the header's range
+    /// - `M` records naming a symbol that is not defined or referred to
+    pub fn parse(text: &str) -> Result<ObjectProgram, Vec<ObjectError>> {
+        let mut errors = Vec::new();
+        let mut header: Option<(usize, HeaderRecord)> = None;
+        // set even when the H record is malformed, so it is reported once
+        let mut header_seen = false;
+        let mut end_line: Option<usize> = None;
+        let mut entry = None;
+        let mut definitions = Vec::new();
+        let mut references = Vec::new();
+        let mut text_records: Vec<(usize, TextRecord)> = Vec::new();
+        let mut modifications: Vec<(usize, ModificationRecord)> = Vec::new();
+
+        for (index, raw) in text.lines().enumerate() {
+            let record = Record::new(raw, index + 1);
+            if record.is_empty() {
+                continue;
+            }
+            if let Some((column, c)) = record.first_non_ascii() {
+                errors.push(ObjectError::new(record.line, column, format!("Unexpected character '{}'", c)));
+                continue;
+            }
+            if let Some(end_line) = end_line {
+                errors.push(record.error(0, format!("Record after the end record on line {}", end_line)));
+            }
+
+            let parsed = match record.kind() {
+                'H' => record.header().map(|parsed| {
+                    match &header {
+                        Some((line, _)) => errors.push(record.error(0, format!("Second header record; the first is on line {}", line))),
+                        None => header = Some((record.line, parsed)),
+                    }
+                }),
+                'D' => record.definitions().map(|parsed| definitions.extend(parsed)),
+                'R' => record.references().map(|parsed| references.extend(parsed)),
+                'T' => record.text().map(|parsed| text_records.push((record.line, parsed))),
+                'M' => record.modification().map(|parsed| modifications.push((record.line, parsed))),
+                'E' => {
+                    end_line = Some(record.line);
+                    record.end().map(|parsed| entry = parsed)
+                }
+                other => Err(record.error(0, format!("Unknown record type '{}'", other))),
+            };
+            if let Err(error) = parsed {
+                errors.push(error);
+            }
+            if record.kind() == 'H' {
+                header_seen = true;
+            } else if !header_seen {
+                errors.push(record.error(0, "Record before the header record".to_string()));
+            }
+        }
+
+        let Some((_, header)) = header else {
+            if !header_seen {
+                errors.push(ObjectError::new(1, 1, "Missing header record".to_string()));
+            }
+            errors.sort_by_key(|e| (e.line, e.column));
+            return Err(errors);
+        };
+        if end_line.is_none() {
+            errors.push(ObjectError::new(text.lines().count().max(1), 1, "Missing end record".to_string()));
+        }
+        let range = header.start..header.start + header.length;
+        let inside = |address: usize, len: usize| address >= range.start && address + len <= range.end;
+        let outside = |address: usize| format!("Address {:06X} is outside the program ({:06X}-{:06X})", address, range.start, range.end);
+
+        for (line, record) in &text_records {
+            if !record.bytes.is_empty() && !inside(record.address, record.bytes.len()) {
+                errors.push(ObjectError::new(*line, 2, format!(
+                    "Text record {:06X}-{:06X} is outside the program ({:06X}-{:06X})",
+                    record.address, record.address + record.bytes.len(), range.start, range.end,
+                )));
+            }
+        }
+
+        // in address order, a record overlaps an earlier one exactly when it starts
+        // before the furthest end seen so far
+        let mut by_address: Vec<&(usize, TextRecord)> = text_records.iter().filter(|(_, r)| !r.bytes.is_empty()).collect();
+        by_address.sort_by_key(|(line, record)| (record.address, *line));
+        let mut furthest: Option<&(usize, TextRecord)> = None;
+        for current in by_address {
+            if let Some(previous) = furthest {
+                let previous_end = previous.1.address + previous.1.bytes.len();
+                if current.1.address < previous_end {
+                    errors.push(ObjectError::new(current.0, 2, format!(
+                        "Text record at {:06X} overlaps the one on line {} ({:06X}-{:06X})",
+                        current.1.address, previous.0, previous.1.address, previous_end,
+                    )));
+                }
+            }
+            if furthest.is_none_or(|f| current.1.address + current.1.bytes.len() > f.1.address + f.1.bytes.len()) {
+                furthest = Some(current);
+            }
+        }
+
+        for (line, modification) in &modifications {
+            if !inside(modification.address, modification.half_bytes.div_ceil(2)) {
+                errors.push(ObjectError::new(*line, 2, outside(modification.address)));
+            }
+            if let Some(symbol) = &modification.symbol {
+                let known = *symbol == header.name
+                    || references.contains(symbol)
+                    || definitions.iter().any(|d: &ExternalSymbol| d.name == *symbol);
+                if !known {
+                    errors.push(ObjectError::new(*line, 11, format!(
+                        "Symbol '{}' is neither the program name nor in a D or R record", symbol
+                    )));
+                }
+            }
+        }
+
+        if let (Some(line), Some(entry)) = (end_line, entry) {
+            // an empty program may still name its start as the entry point
+            let allowed = inside(entry, 1) || (header.length == 0 && entry == header.start);
+            if !allowed {
+                errors.push(ObjectError::new(line, 2, outside(entry)));
+            }
+        }
+
+        if !errors.is_empty() {
+            errors.sort_by_key(|e| (e.line, e.column));
+            return Err(errors);
+        }
+        Ok(ObjectProgram {
+            header,
+            definitions,
+            references,
+            text: text_records.into_iter().map(|(_, record)| record).collect(),
+            modifications: modifications.into_iter().map(|(_, record)| record).collect(),
+            entry,
+        })
+    }
+}
+
+// one line with `^` separators and trailing whitespace removed; `columns`
+// maps each remaining character back to its column in the original line
+struct Record {
+    line: usize,
+    chars: Vec<char>,
+    columns: Vec<usize>,
+    original_len: usize,
+}
+
+impl Record {
+    fn new(raw: &str, line: usize) -> Self {
+        let raw = raw.trim_end();
+        let (columns, chars) = raw.chars()
+            .enumerate()
+            .filter(|&(_, c)| c != '^')
+            .map(|(index, c)| (index + 1, c))
+            .unzip();
+        Self { line, chars, columns, original_len: raw.chars().count() }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.chars.is_empty()
+    }
+
+    fn kind(&self) -> char {
+        self.chars[0]
+    }
+
+    fn len(&self) -> usize {
+        self.chars.len()
+    }
+
+    fn first_non_ascii(&self) -> Option<(usize, char)> {
+        self.chars.iter().position(|c| !c.is_ascii()).map(|i| (self.columns[i], self.chars[i]))
+    }
+
+    // error at character `index` (past the end points just after the line)
+    fn error(&self, index: usize, message: String) -> ObjectError {
+        let column = self.columns.get(index).copied().unwrap_or(self.original_len + 1);
+        ObjectError::new(self.line, column, message)
+    }
+
+    fn field(&self, start: usize, len: usize, what: &str) -> Result<String, ObjectError> {
+        if start + len > self.len() {
+            return Err(self.error(self.len(), format!(
+                "{} record is too short: {} needs columns {}-{}", self.kind(), what, start + 1, start + len,
+            )));
+        }
+        Ok(self.chars[start..start + len].iter().collect())
+    }
+
+    fn hex(&self, start: usize, len: usize, what: &str) -> Result<usize, ObjectError> {
+        let text = self.field(start, len, what)?;
+        if let Some(offset) = text.find(|c: char| !c.is_ascii_hexdigit()) {
+            return Err(self.error(start + offset, format!(
+                "Non-hex digit '{}' in {}", &text[offset..offset + 1], what,
+            )));
+        }
+        usize::from_str_radix(&text, 16).map_err(|e| self.error(start, format!("Invalid {}: {}", what, e)))
+    }
+
+    fn expect_len(&self, expected: usize) -> Result<(), ObjectError> {
+        if self.len() > expected {
+            return Err(self.error(expected, format!(
+                "{} record has {} extra characters", self.kind(), self.len() - expected,
+            )));
+        }
+        Ok(())
+    }
+
+    // H name(6) start(6) length(6)
+    fn header(&self) -> Result<HeaderRecord, ObjectError> {
+        let name = self.field(1, 6, "program name")?.trim().to_string();
+        let start = self.hex(7, 6, "start address")?;
+        let length = self.hex(13, 6, "program length")?;
+        self.expect_len(19)?;
+        Ok(HeaderRecord { name, start, length })
+    }
+
+    // D followed by name(6) address(6) pairs
+    fn definitions(&self) -> Result<Vec<ExternalSymbol>, ObjectError> {
+        if self.len() == 1 || !(self.len() - 1).is_multiple_of(12) {
+            return Err(self.error(self.len(), "D record must hold 6-character names each followed by a 6-digit address".to_string()));
+        }
+        (1..self.len())
+            .step_by(12)
+            .map(|start| {
+                Ok(ExternalSymbol {
+                    name: self.field(start, 6, "symbol name")?.trim().to_string(),
+                    address: self.hex(start + 6, 6, "symbol address")?,
+                })
+            })
+            .collect()
+    }
+
+    // R followed by 6-character names; the last may be shorter
+    fn references(&self) -> Result<Vec<String>, ObjectError> {
+        if self.len() == 1 {
+            return Err(self.error(1, "R record names no symbols".to_string()));
+        }
+        Ok(self.chars[1..]
+            .chunks(6)
+            .map(|name| name.iter().collect::<String>().trim().to_string())
+            .collect())
+    }
+
+    // T address(6) length(2) object code
+    fn text(&self) -> Result<TextRecord, ObjectError> {
+        let address = self.hex(1, 6, "text record address")?;
+        let length = self.hex(7, 2, "text record length")?;
+        let code_len = self.len().saturating_sub(9);
+        if !code_len.is_multiple_of(2) {
+            return Err(self.error(self.len() - 1, "Object code has an odd number of hex digits".to_string()));
+        }
+        if code_len != length * 2 {
+            return Err(self.error(7, format!(
+                "Text record length is {:02X} ({} bytes) but it holds {} bytes", length, length, code_len / 2,
+            )));
+        }
+        let bytes = (0..length)
+            .map(|k| self.hex(9 + k * 2, 2, "object code").map(|byte| byte as u8))
+            .collect::<Result<Vec<u8>, ObjectError>>()?;
+        Ok(TextRecord { address, bytes })
+    }
+
+    // M address(6) half-bytes(2), optionally followed by +/- and a symbol
+    fn modification(&self) -> Result<ModificationRecord, ObjectError> {
+        let address = self.hex(1, 6, "modification address")?;
+        let half_bytes = self.hex(7, 2, "modification length")?;
+        if half_bytes == 0 || half_bytes > MAX_HALF_BYTES {
+            return Err(self.error(7, format!("Modification length must be 01-{:02X} half-bytes, not {:02X}", MAX_HALF_BYTES, half_bytes)));
+        }
+
+        let (subtract, symbol) = if self.len() == 9 {
+            (false, None)
+        } else {
+            let subtract = match self.chars[9] {
+                '+' => false,
+                '-' => true,
+                other => return Err(self.error(9, format!("Expected '+' or '-' before the symbol, found '{}'", other))),
+            };
+            let symbol: String = self.chars[10..].iter().collect::<String>().trim().to_string();
+            if symbol.is_empty() || symbol.len() > 6 {
+                return Err(self.error(10, "Modification symbol must be 1-6 characters".to_string()));
+            }
+            (subtract, Some(symbol))
+        };
+        Ok(ModificationRecord { address, half_bytes, subtract, symbol })
+    }
+
+    // E, optionally followed by the entry address(6)
+    fn end(&self) -> Result<Option<usize>, ObjectError> {
+        if self.len() == 1 {
+            return Ok(None);
+        }
+        let entry = self.hex(1, 6, "entry address")?;
+        self.expect_len(7)?;
+        Ok(Some(entry))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // (line, column) of every error in `text`
+    fn error_positions(text: &str) -> Vec<(usize, usize)> {
+        ObjectProgram::parse(text).unwrap_err().iter().map(|e| (e.line, e.column)).collect()
+    }
+
+    #[test]
+    fn parses_and_writes_records() {
+        let text = "HCOPY  001000000009\nDBUFFER001006\nRRDREC WRREC\nT00100009141006482000000000\nM00100105+RDREC\nE001000\n";
+        let program = ObjectProgram::parse(text).unwrap();
+        assert_eq!(program.header, HeaderRecord { name: "COPY".to_string(), start: 0x1000, length: 9 });
+        assert_eq!(program.definitions, [ExternalSymbol { name: "BUFFER".to_string(), address: 0x1006 }]);
+        assert_eq!(program.references, ["RDREC", "WRREC"]);
+        assert_eq!(program.text[0].bytes.len(), 9);
+        assert_eq!(program.modifications[0].symbol.as_deref(), Some("RDREC"));
+        assert_eq!(program.entry, Some(0x1000));
+        assert_eq!(program.to_string(), text);
+    }
+
+    #[test]
+    fn ignores_textbook_separators() {
+        let program = ObjectProgram::parse("HCOPY  ^001000^000003\nT^001000^03^141006\nE^001000\n").unwrap();
+        assert_eq!(program.text[0].bytes, [0x14, 0x10, 0x06]);
+    }
+
+    #[test]
+    fn points_errors_at_the_offending_field() {
+        // non-hex digit in the text record's object code
+        assert_eq!(error_positions("HCOPY  001000000003\nT0010000314100G\nE001000\n"), [(2, 15)]);
+        // length byte that disagrees with the object code
+        assert_eq!(error_positions("HCOPY  001000000003\nT00100004141006\nE001000\n"), [(2, 8)]);
+        // text outside the header's range, and an entry point outside it
+        assert_eq!(error_positions("HCOPY  001000000003\nT00100303141006\nE002000\n"), [(2, 2), (3, 2)]);
+        // overlapping text records point at the later one
+        assert_eq!(error_positions("HCOPY  001000000006\nT00100003141006\nT00100203000000\nE001000\n"), [(3, 2)]);
+        // M symbol that is neither defined nor referred to
+        assert_eq!(error_positions("HCOPY  001000000003\nT00100003141006\nM00100005+OTHER\nE001000\n"), [(3, 11)]);
+        // columns count the separators that were removed
+        assert_eq!(error_positions("HCOPY  001000000003\nT^001000^03^14100G\nE001000\n"), [(2, 18)]);
+    }
+
+    #[test]
+    fn reports_missing_and_misplaced_records() {
+        // the missing header, and both records before it
+        assert_eq!(error_positions("T00100003141006\nE001000\n"), [(1, 1), (1, 1), (2, 1)]);
+        assert_eq!(error_positions("HCOPY  001000000003\nT00100003141006\n"), [(2, 1)]);
+        assert_eq!(error_positions("HCOPY  001000000003\nE001000\nT00100003141006\n"), [(3, 1)]);
+        assert_eq!(error_positions("HCOPY  001000000003\nX\nE001000\n"), [(2, 1)]);
+    }
+
+    #[test]
+    fn rejects_modifications_longer_than_a_word() {
+        let text = format!("HTEST  000000000010\nT00000010{}\nM00000010\nE000000\n", "00".repeat(16));
+        let errors = ObjectProgram::parse(&text).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!((errors[0].line, errors[0].column), (3, 8));
+
+        let text = "HTEST  000000000003\nT00000003000000\nM00000005\nM00000006\nE000000\n";
+        let program = ObjectProgram::parse(text).unwrap();
+        assert_eq!(program.modifications.iter().map(|m| m.half_bytes).collect::<Vec<_>>(), [5, 6]);
+    }
+}
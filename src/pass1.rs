@@ -1,11 +1,60 @@
-use std::fs::File;
-use std::io::{self, Write, BufRead};
-use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use crate::diagnostics::Diagnostic;
+use crate::expression;
+use crate::pass_trace::{self, Pass1Step, TracedSymbol};
+use crate::pass2::{takes_no_operand, FORMAT1, FORMAT2, FORMAT3, FORMAT4};
+use crate::source::{SourceFormat, SourceLocation};
+use crate::tables::{self, TableEntry, TableOrder};
+
+pub const DIRECTIVES: [&str; 12] = ["START", "END", "BYTE", "WORD", "RESB", "RESW", "BASE", "LTORG", "USE", "EQU", "INCLUDE", "COPY"];
+
+// register names and format 4F condition flags, which operands use without
+// defining them
+const OPERAND_NAMES: [&str; 11] = ["A", "X", "L", "B", "S", "T", "F", "Z", "N", "C", "V"];
+const REGISTERS: [&str; 9] = ["A", "X", "L", "B", "S", "T", "F", "PC", "SW"];
+const CONDITION_FLAGS: [&str; 4] = ["Z", "N", "C", "V"];
+/// Longest label accepted in strict mode (the original SIC limit).
+pub const MAX_STRICT_LABEL_LEN: usize = 6;
+/// 0-based column ranges of the label, opcode and operand fields in
+/// fixed-column source.
+const FIXED_LABEL: (usize, usize) = (0, 8);
+const FIXED_OPCODE: (usize, usize) = (9, 15);
+const FIXED_OPERAND: (usize, usize) = (16, 35);
+
 pub struct Pass1 {
     pub lines: Vec<String>,
     pub labels: Vec<String>,
     pub instr: Vec<String>,
     pub ref_data: Vec<String>,
+    pub sources: Vec<SourceLocation>,
+    /// Source location of each intermediate file line; literals placed by
+    /// `LTORG` or `END` take that statement's location
+    pub statement_sources: Vec<SourceLocation>,
+    /// 1-based column of each intermediate line's opcode in its source line
+    pub statement_columns: Vec<usize>,
+    pub include_paths: Vec<String>,
+    /// Canonical paths of every file read, the main source first
+    pub files: Vec<PathBuf>,
+    pub table_order: TableOrder,
+    pub symbol_entries: Vec<TableEntry>,
+    pub literal_entries: Vec<TableEntry>,
+    pub diagnostics: Vec<Diagnostic>,
+    pub strict: bool,
+    pub source_format: SourceFormat,
+    /// Whether to record `trace_steps`
+    pub trace: bool,
+    /// One per source statement, when `trace` is set
+    pub trace_steps: Vec<Pass1Step>,
+    root_dir: PathBuf,
+}
+
+impl Default for Pass1 {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Pass1 {
@@ -15,59 +64,221 @@ impl Pass1 {
             labels: Vec::new(),
             instr: Vec::new(),
             ref_data: Vec::new(),
+            sources: Vec::new(),
+            statement_sources: Vec::new(),
+            statement_columns: Vec::new(),
+            include_paths: Vec::new(),
+            files: Vec::new(),
+            table_order: TableOrder::default(),
+            symbol_entries: Vec::new(),
+            literal_entries: Vec::new(),
+            diagnostics: Vec::new(),
+            strict: false,
+            source_format: SourceFormat::default(),
+            trace: false,
+            trace_steps: Vec::new(),
+            root_dir: PathBuf::new(),
         }
     }
 
     pub fn process_file(&mut self, file_path: &str) -> io::Result<()> {
-        let input_file = File::open(file_path)?;
-        let reader = io::BufReader::new(input_file);
-        let raw_lines: Vec<String> = reader.lines().filter_map(Result::ok).collect();
-
-        for line in raw_lines {
-            let parts: Vec<String> = line.split(';')
-                .next()
-                .unwrap_or("")
-                .split_whitespace()
-                .map(|s| s.to_uppercase())
-                .collect();
-
-            if parts.is_empty() {
+        let path = Path::new(file_path);
+        self.root_dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+
+        let mut include_stack = Vec::new();
+        self.process_source(path, &mut include_stack)
+    }
+
+    // reads one source file, splicing INCLUDE/COPY targets in place; the stack
+    // holds the canonical paths of the files currently being read
+    fn process_source(&mut self, path: &Path, include_stack: &mut Vec<PathBuf>) -> io::Result<()> {
+        let file_name = self.display_name(path);
+        let canonical = fs::canonicalize(path)
+            .map_err(|e| io::Error::new(e.kind(), format!("Cannot open '{}': {}", file_name, e)))?;
+
+        if include_stack.contains(&canonical) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Include cycle detected: '{}' is already being included", file_name),
+            ));
+        }
+
+        if !self.files.contains(&canonical) {
+            self.files.push(canonical.clone());
+        }
+        let contents = fs::read_to_string(path)?;
+        include_stack.push(canonical);
+
+        for (index, line) in contents.lines().enumerate() {
+            let location = SourceLocation::new(&file_name, index + 1);
+
+            if let Some(target) = Self::include_target(line) {
+                let resolved = self.resolve_include(&target, path, &location)?;
+                self.process_source(&resolved, include_stack)
+                    .map_err(|e| io::Error::new(e.kind(), format!("{}\n  included from {}", e, location)))?;
                 continue;
             }
 
-            match parts.len() {
-                3 => {
+            self.push_statement(line, location);
+        }
+
+        include_stack.pop();
+        Ok(())
+    }
+
+    fn push_statement(&mut self, line: &str, location: SourceLocation) {
+        if self.source_format == SourceFormat::Fixed {
+            if let Some((label, instr, operand)) = Self::fixed_fields(line) {
+                self.labels.push(label);
+                self.instr.push(instr);
+                self.ref_data.push(operand);
+                self.lines.push(line.to_string());
+                self.sources.push(location);
+            }
+            return;
+        }
+
+        let parts: Vec<String> = line.split(';')
+            .next()
+            .unwrap_or("")
+            .split_whitespace()
+            .map(|s| s.to_uppercase())
+            .collect();
+
+        match parts.len() {
+            3 => {
+                self.labels.push(parts[0].trim_end_matches(',').to_string());
+                self.instr.push(parts[1].trim_end_matches(',').to_string());
+                self.ref_data.push(parts[2].trim_end_matches(',').to_string());
+            }
+            2 => {
+                if parts[0].trim_end_matches(',') == "*" || takes_no_operand(&parts[1])
+                {
                     self.labels.push(parts[0].trim_end_matches(',').to_string());
                     self.instr.push(parts[1].trim_end_matches(',').to_string());
-                    self.ref_data.push(parts[2].trim_end_matches(',').to_string());
-                    self.lines.push(line);
-                }
-                2 => {
-                    if parts[0].trim_end_matches(',').to_string() == "*"
-                    {
-                        self.labels.push(parts[0].trim_end_matches(',').to_string());
-                        self.instr.push(parts[1].trim_end_matches(',').to_string());
-                        self.ref_data.push("&".to_string());
-                    }
-                    else
-                    {
-                        self.labels.push("&".to_string());
-                        self.instr.push(parts[0].trim_end_matches(',').to_string());
-                        self.ref_data.push(parts[1].trim_end_matches(',').to_string());
-                    }
-                    self.lines.push(line);
+                    self.ref_data.push("&".to_string());
                 }
-                1 => {
+                else
+                {
                     self.labels.push("&".to_string());
                     self.instr.push(parts[0].trim_end_matches(',').to_string());
-                    self.ref_data.push("&".to_string());
-                    self.lines.push(line);
+                    self.ref_data.push(parts[1].trim_end_matches(',').to_string());
                 }
-                _ => {}
             }
+            1 => {
+                self.labels.push("&".to_string());
+                self.instr.push(parts[0].trim_end_matches(',').to_string());
+                self.ref_data.push("&".to_string());
+            }
+            _ => return,
         }
 
-        Ok(())
+        self.lines.push(line.to_string());
+        self.sources.push(location);
+    }
+
+    // label, opcode and operand by column position; `.` in column 1 or a
+    // leading `;` marks a comment line. Blank fields become `&`, spaces in
+    // operands are dropped outside quotes, and a quoted constant running past
+    // column 35 is read up to its closing quote.
+    fn fixed_fields(line: &str) -> Option<(String, String, String)> {
+        let chars: Vec<char> = line.chars().collect();
+        if chars.first() == Some(&'.') || line.trim_start().starts_with(';') {
+            return None;
+        }
+        let field = |(start, end): (usize, usize)| -> String {
+            chars.get(start..end.min(chars.len()))
+                .map(|c| c.iter().collect::<String>().trim().to_uppercase())
+                .unwrap_or_default()
+        };
+
+        let label = field(FIXED_LABEL);
+        let instr = field(FIXED_OPCODE);
+        if instr.is_empty() {
+            return None;
+        }
+
+        let mut operand = String::new();
+        let mut in_quote = false;
+        for (i, &c) in chars.iter().enumerate().skip(FIXED_OPERAND.0) {
+            if i >= FIXED_OPERAND.1 && !in_quote {
+                break;
+            }
+            if c == '\'' {
+                in_quote = !in_quote;
+            }
+            if in_quote || !c.is_whitespace() {
+                operand.push(c);
+            }
+        }
+        let operand = operand.to_uppercase();
+
+        let or_blank = |s: String| if s.is_empty() { "&".to_string() } else { s };
+        Some((or_blank(label), instr, or_blank(operand)))
+    }
+
+    // `INCLUDE file`, `INCLUDE 'file'` or `COPY 'file'`; COPY needs the quotes
+    // because it is also a common program name (`COPY START 0`)
+    fn include_target(line: &str) -> Option<String> {
+        let code = line.split(';').next().unwrap_or("").trim();
+        let (keyword, rest) = code.split_once(char::is_whitespace)?;
+        let keyword = keyword.to_uppercase();
+        if keyword != "INCLUDE" && keyword != "COPY" {
+            return None;
+        }
+
+        let rest = rest.trim();
+        if let Some(quoted) = rest.strip_prefix('\'') {
+            quoted.strip_suffix('\'').map(str::to_string)
+        } else if keyword == "INCLUDE" && rest.split_whitespace().count() == 1 {
+            Some(rest.to_string())
+        } else {
+            None
+        }
+    }
+
+    // relative to the including file first, then each configured include
+    // path; the file found must lie inside the main source's directory or an
+    // include path, so `..` and symbolic links cannot reach the rest of the
+    // file system
+    fn resolve_include(&self, target: &str, including_file: &Path, location: &SourceLocation) -> io::Result<PathBuf> {
+        let target_path = Path::new(target);
+        if target_path.is_absolute() {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("Included file '{}' must be a relative path at {}", target, location),
+            ));
+        }
+
+        let including_dir = including_file.parent().unwrap_or(Path::new(""));
+        let resolved = std::iter::once(including_dir.to_path_buf())
+            .chain(self.include_paths.iter().map(PathBuf::from))
+            .map(|dir| dir.join(target_path))
+            .find(|candidate| candidate.is_file())
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::NotFound, format!("Included file '{}' not found at {}", target, location))
+            })?;
+
+        let canonical = fs::canonicalize(&resolved)?;
+        let allowed = std::iter::once(self.root_dir.as_path())
+            .chain(self.include_paths.iter().map(Path::new))
+            .map(|dir| if dir.as_os_str().is_empty() { Path::new(".") } else { dir })
+            .filter_map(|dir| fs::canonicalize(dir).ok())
+            .any(|dir| canonical.starts_with(dir));
+        if !allowed {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("Included file '{}' is outside the source and include directories at {}", target, location),
+            ));
+        }
+        Ok(resolved)
+    }
+
+    fn display_name(&self, path: &Path) -> String {
+        path.strip_prefix(&self.root_dir)
+            .unwrap_or(path)
+            .to_string_lossy()
+            .to_string()
     }
 
     pub fn pass1_generator(&mut self, output_dir: &str) -> Result<(), String> {
@@ -88,6 +299,13 @@ impl Pass1 {
         let mut current_block = "DEFAULTB".to_string();
 
         let mut symbols: HashMap<String, (usize, String)> = HashMap::new();
+        let mut absolute_names: HashSet<String> = HashSet::new();
+        // statement index of each label's first definition
+        let mut definitions: HashMap<String, usize> = HashMap::new();
+        self.diagnostics.clear();
+        self.statement_sources.clear();
+        self.statement_columns.clear();
+        self.trace_steps.clear();
         let mut literals: HashMap<String, (usize, String)> = HashMap::new();
         let mut pending_literals: Vec<String> = Vec::new();
 
@@ -96,67 +314,143 @@ impl Pass1 {
             let ref_type = self.ref_data[i].clone();
             let labels_type = self.labels[i].clone();
 
+            self.finish_trace_step(&block_locctrs, &pending_literals);
+            if self.trace {
+                let locctr = *block_locctrs.get(&current_block).unwrap_or(&0);
+                self.trace_steps.push(Pass1Step {
+                    location: self.sources[i].clone(),
+                    statement: pass_trace::statement_text(&labels_type, &instr_type, &ref_type),
+                    block: current_block.clone(),
+                    locctr_before: locctr,
+                    locctr_after: locctr,
+                    symbols_added: Vec::new(),
+                    literals_added: Vec::new(),
+                    literals_placed: Vec::new(),
+                    literal_pool: Vec::new(),
+                });
+            }
+
             if instr_type == "START" {
-                writeln!(intermediate_file, "{:<10} {:<9} {}", labels_type, instr_type, ref_type).unwrap();
+                // the first block's LOCCTR begins at the load address; the
+                // others stay relative and are placed after it
+                let start_addr = if ref_type == "&" {
+                    0
+                } else {
+                    match usize::from_str_radix(&ref_type, 16) {
+                        Ok(addr) => addr,
+                        Err(_) => return Err(self.fail(Diagnostic::error(
+                            "invalid-start",
+                            format!("Invalid START address '{}'", ref_type),
+                            &self.sources[i],
+                        ))),
+                    }
+                };
+                block_locctrs.insert(current_block.clone(), start_addr);
+                self.write_statement(&mut intermediate_file, i, format!("{:<10} {:<9} {}", labels_type, instr_type, ref_type));
                 continue;
             }
 
             if instr_type == "USE" {
                 let valid_blocks = ["DEFAULT", "DEFAULTB", "CDATA", "CBLKS"];
                 if !valid_blocks.contains(&ref_type.as_str()) {
-                    return Err(format!("Unidentified block name '{}' at line {}", ref_type, i + 1));
+                    return Err(self.fail(Diagnostic::error(
+                        "unknown-block",
+                        format!("Unidentified block name '{}'", ref_type),
+                        &self.sources[i],
+                    )));
                 }
                 current_block = ref_type.clone();
                 let locctr = *block_locctrs.get(&current_block).unwrap_or(&0);
-                writeln!(intermediate_file, "{:<6} {:<10} {:<9} {}", format!("{:04X}", locctr), labels_type, instr_type, ref_type).unwrap();
+                if let Some(step) = self.trace_steps.last_mut() {
+                    step.block = current_block.clone();
+                    step.locctr_before = locctr;
+                }
+                self.write_statement(&mut intermediate_file, i, format!("{:<6} {:<10} {:<9} {}", format!("{:04X}", locctr), labels_type, instr_type, ref_type));
                 continue;
             }
 
             let locctr = *block_locctrs.get(&current_block).unwrap_or(&0);
             let display_label = if labels_type == "&" { String::new() } else { labels_type.clone() };
+
+            if instr_type == "EQU" {
+                if labels_type == "&" || labels_type == "*" {
+                    return Err(self.fail(Diagnostic::error("equ-without-label", "EQU without a label".to_string(), &self.sources[i])));
+                }
+                let (value, block) = if ref_type == "*" {
+                    (locctr, Some(current_block.clone()))
+                } else {
+                    match Self::evaluate_equ(&ref_type, &symbols, &absolute_names) {
+                        Ok(result) => result,
+                        Err(e) => return Err(self.fail(Diagnostic::error(
+                            "invalid-equ",
+                            format!("Invalid EQU operand '{}': {}", ref_type, e),
+                            &self.sources[i],
+                        ))),
+                    }
+                };
+                if !self.check_label(&labels_type, i, &mut definitions) {
+                    self.write_statement(&mut intermediate_file, i, format!("{:<6} {:<10} {:<9} {}", format!("{:04X}", locctr), display_label, instr_type, ref_type));
+                    continue;
+                }
+                self.trace_symbol(&labels_type, value, block.clone());
+                match block {
+                    Some(block) => {
+                        symbols.insert(labels_type.clone(), (value, block));
+                    }
+                    None => {
+                        absolute_names.insert(labels_type.clone());
+                        symbols.insert(labels_type.clone(), (value, String::new()));
+                    }
+                }
+                self.write_statement(&mut intermediate_file, i, format!("{:<6} {:<10} {:<9} {}", format!("{:04X}", locctr), display_label, instr_type, ref_type));
+                continue;
+            }
             
-            if labels_type != "&" && labels_type != "*" {
+            if labels_type != "&" && labels_type != "*" && self.check_label(&labels_type, i, &mut definitions) {
                 symbols.insert(labels_type.clone(), (locctr, current_block.clone()));
+                self.trace_symbol(&labels_type, locctr, Some(current_block.clone()));
             }
 
             if instr_type == "LTORG" {
-                writeln!(intermediate_file, "{:<6} {:<10} {:<9} {}", format!("{:04X}", locctr), display_label, instr_type, ref_type).unwrap();
+                self.write_statement(&mut intermediate_file, i, format!("{:<6} {:<10} {:<9} {}", format!("{:04X}", locctr), display_label, instr_type, ref_type));
                 for lit in &pending_literals {
                     let lit_locctr = *block_locctrs.get(&current_block).unwrap_or(&0);
                     if lit.starts_with("=C'") {
                         let _c = lit.chars().nth(3).unwrap_or('A');
-                        writeln!(intermediate_file, "{:<6} {:<10} {:<9} {}", format!("{:04X}", lit_locctr), "*", lit, "").unwrap();
+                        self.write_statement(&mut intermediate_file, i, format!("{:<6} {:<10} {:<9} ", format!("{:04X}", lit_locctr), "*", lit));
                         literals.insert(lit.clone(), (lit_locctr, current_block.clone()));
-                        *block_locctrs.get_mut(&current_block).unwrap() += 1;
+                        *block_locctrs.get_mut(&current_block).unwrap() += Self::literal_size(lit);
                     } else if lit.starts_with("=X'") {
-                        writeln!(intermediate_file, "{:<6} {:<10} {:<9} {}", format!("{:04X}", lit_locctr), "*", lit, "").unwrap();
+                        self.write_statement(&mut intermediate_file, i, format!("{:<6} {:<10} {:<9} ", format!("{:04X}", lit_locctr), "*", lit));
                         literals.insert(lit.clone(), (lit_locctr, current_block.clone()));
-                        *block_locctrs.get_mut(&current_block).unwrap() += 1;
+                        *block_locctrs.get_mut(&current_block).unwrap() += Self::literal_size(lit);
                     }
                 }
+                self.trace_literals_placed(&pending_literals, &literals);
                 pending_literals.clear();
                 continue;
             }
 
             if instr_type == "END" {
-                writeln!(intermediate_file, "{:<6} {:<10} {:<9} {}", format!("{:04X}", locctr), display_label, instr_type, ref_type).unwrap();
+                self.write_statement(&mut intermediate_file, i, format!("{:<6} {:<10} {:<9} {}", format!("{:04X}", locctr), display_label, instr_type, ref_type));
                 if !pending_literals.is_empty() {
                     current_block = "CDATA".to_string();
                     let mut lit_locctr = *block_locctrs.get(&current_block).unwrap_or(&0);
                     for lit in &pending_literals {
                         if lit.starts_with("=C'") {
                             let _c = lit.chars().nth(3).unwrap_or('A');
-                            writeln!(intermediate_file, "{:<6} {:<10} {:<9} {}", format!("{:04X}", lit_locctr), "*", lit, "").unwrap();
+                            self.write_statement(&mut intermediate_file, i, format!("{:<6} {:<10} {:<9} ", format!("{:04X}", lit_locctr), "*", lit));
                             literals.insert(lit.clone(), (lit_locctr, current_block.clone()));
-                            lit_locctr += 1;
-                            *block_locctrs.get_mut(&current_block).unwrap() += 1;
+                            lit_locctr += Self::literal_size(lit);
+                            *block_locctrs.get_mut(&current_block).unwrap() += Self::literal_size(lit);
                         } else if lit.starts_with("=X'") {
-                            writeln!(intermediate_file, "{:<6} {:<10} {:<9} {}", format!("{:04X}", lit_locctr), "*", lit, "").unwrap();
+                            self.write_statement(&mut intermediate_file, i, format!("{:<6} {:<10} {:<9} ", format!("{:04X}", lit_locctr), "*", lit));
                             literals.insert(lit.clone(), (lit_locctr, current_block.clone()));
-                            lit_locctr += 1;
-                            *block_locctrs.get_mut(&current_block).unwrap() += 1;
+                            lit_locctr += Self::literal_size(lit);
+                            *block_locctrs.get_mut(&current_block).unwrap() += Self::literal_size(lit);
                         }
                     }
+                    self.trace_literals_placed(&pending_literals, &literals);
                     pending_literals.clear();
                 }
                 continue;
@@ -165,23 +459,27 @@ impl Pass1 {
             if labels_type == "*" {
                 if ref_type.starts_with("=C'") {
                     let _obj = format!("{:02X}", ref_type.chars().nth(3).unwrap_or('A') as usize);
-                    writeln!(intermediate_file, "{:<6} {:<10} {:<9} {}", format!("{:04X}", locctr), "*", instr_type, ref_type).unwrap();
+                    self.write_statement(&mut intermediate_file, i, format!("{:<6} {:<10} {:<9} {}", format!("{:04X}", locctr), "*", instr_type, ref_type));
                     literals.insert(ref_type.clone(), (locctr, current_block.clone()));
-                    *block_locctrs.get_mut(&current_block).unwrap() += 1;
+                    *block_locctrs.get_mut(&current_block).unwrap() += Self::literal_size(&ref_type);
                 } else if ref_type.starts_with("=X'") {
                     let _obj = format!("{:02X}", usize::from_str_radix(&ref_type[3..ref_type.len()-1], 16).unwrap_or(0));
-                    writeln!(intermediate_file, "{:<6} {:<10} {:<9} {}", format!("{:04X}", locctr), "*", instr_type, ref_type).unwrap();
+                    self.write_statement(&mut intermediate_file, i, format!("{:<6} {:<10} {:<9} {}", format!("{:04X}", locctr), "*", instr_type, ref_type));
                     literals.insert(ref_type.clone(), (locctr, current_block.clone()));
-                    *block_locctrs.get_mut(&current_block).unwrap() += 1;
+                    *block_locctrs.get_mut(&current_block).unwrap() += Self::literal_size(&ref_type);
                 }
+                self.trace_literals_placed(std::slice::from_ref(&ref_type), &literals);
                 continue;
             }
 
-            if ref_type.starts_with("=") {
+            if ref_type.starts_with("=") && !pending_literals.contains(&ref_type) {
                 pending_literals.push(ref_type.clone());
+                if let Some(step) = self.trace_steps.last_mut() {
+                    step.literals_added.push(ref_type.clone());
+                }
             }
 
-            writeln!(intermediate_file, "{:<6} {:<10} {:<9} {}", format!("{:04X}", locctr), display_label, instr_type, ref_type).unwrap();
+            self.write_statement(&mut intermediate_file, i, format!("{:<6} {:<10} {:<9} {}", format!("{:04X}", locctr), display_label, instr_type, ref_type));
 
             let format1 = ["FIX", "FLOAT", "HIO", "SIO", "TIO", "NORM"];
             let format2 = ["ADDR", "CLEAR", "COMPR", "DIVR", "MULR", "RMO", "SHIFTR", "SHIFTL", "SUBR", "SVC", "TIXR"];
@@ -201,6 +499,15 @@ impl Pass1 {
 
             *block_locctrs.get_mut(&current_block).unwrap() += increment;
         }
+        self.finish_trace_step(&block_locctrs, &pending_literals);
+
+        let errors: Vec<String> = self.diagnostics.iter()
+            .filter(|d| d.is_error())
+            .map(|d| d.to_string())
+            .collect();
+        if !errors.is_empty() {
+            return Err(errors.join("\n"));
+        }
 
         let mut block_bases: HashMap<String, usize> = HashMap::new();
         let mut base = 0;
@@ -211,45 +518,236 @@ impl Pass1 {
         }
 
         let mut absolute_symbols: HashMap<String, usize> = HashMap::new();
+        self.symbol_entries.clear();
         for (label, (rel_addr, block)) in symbols {
             let abs_addr = rel_addr + *block_bases.get(&block).unwrap_or(&0);
-            absolute_symbols.insert(label, abs_addr);
+            absolute_symbols.insert(label.clone(), abs_addr);
+            let relative = !absolute_names.contains(&label);
+            self.symbol_entries.push(TableEntry {
+                name: label,
+                address: abs_addr,
+                block: relative.then_some(block),
+                relative,
+            });
         }
 
-        let mut absolute_literals: HashMap<String, usize> = HashMap::new();
+        self.literal_entries.clear();
         for (literal, (rel_addr, block)) in literals {
             let abs_addr = rel_addr + *block_bases.get(&block).unwrap_or(&0);
-            absolute_literals.insert(literal, abs_addr);
+            self.literal_entries.push(TableEntry {
+                name: literal,
+                address: abs_addr,
+                block: Some(block),
+                relative: true,
+            });
         }
 
+        // every symbol an instruction's operand names must be defined;
+        // register and condition names count as symbols only when defined
+        let mut undefined = Vec::new();
         for i in 0..self.lines.len() {
-            let ref_type = self.ref_data[i].clone();
-            let instr_type = self.instr[i].clone();
-            
-            if instr_type != "START" && instr_type != "USE" && instr_type != "END" && instr_type != "LTORG" && instr_type != "BASE" && instr_type != "WORD" && instr_type != "BYTE" && instr_type != "RESW" && instr_type != "RESB" {
-                if !ref_type.starts_with("=") && !ref_type.starts_with("#") && !ref_type.starts_with("@") && ref_type != "&" && !ref_type.starts_with("X'") && !ref_type.starts_with("C'") {
-                    let parts: Vec<&str> = ref_type.split(',').collect();
-                    for part in parts {
-                        let operand = part.trim();
-                        if !operand.is_empty() && !operand.chars().all(|c| c.is_digit(10) || c.is_uppercase()) && !operand.contains("'") {
-                            if !absolute_symbols.contains_key(operand) && !["A", "X", "L", "B", "S", "T", "F", "Z", "N", "C", "V"].contains(&operand) {
-                                return Err(format!("Unidentified symbol '{}' at line {}", operand, i + 1));
-                            }
-                        }
+            let operand = &self.ref_data[i];
+            if DIRECTIVES.contains(&self.instr[i].as_str()) || operand.starts_with('=') || operand.contains('\'') {
+                continue;
+            }
+            for part in operand.trim_start_matches(['#', '@']).split(',') {
+                for name in expression::symbols(part.trim()) {
+                    if !absolute_symbols.contains_key(&name) && !OPERAND_NAMES.contains(&name.as_str()) {
+                        undefined.push(Diagnostic::error("undefined-symbol", format!("Undefined symbol '{}'", name), &self.sources[i]));
                     }
                 }
             }
         }
+        if !undefined.is_empty() {
+            let messages: Vec<String> = undefined.into_iter().map(|diagnostic| self.fail(diagnostic)).collect();
+            return Err(messages.join("\n"));
+        }
+
+        tables::sort_entries(&mut self.symbol_entries, self.table_order);
+        tables::sort_entries(&mut self.literal_entries, self.table_order);
+        write!(symbol_table, "{}", tables::render_text(&self.symbol_entries)).unwrap();
+        write!(literals_table, "{}", tables::render_text(&self.literal_entries)).unwrap();
+
+        Ok(())
+    }
+
+    /// Whether warnings with `code` are allowed for statement `i`, i.e. its
+    /// comment does not carry a `lint: allow(code)` (or `allow(all)`) pragma.
+    pub fn allows(&self, i: usize, code: &str) -> bool {
+        let Some(comment) = self.comment(i) else {
+            return true;
+        };
+        let Some((_, pragma)) = comment.split_once("lint:") else {
+            return true;
+        };
+        let Some(codes) = pragma.trim_start().strip_prefix("allow(").and_then(|rest| rest.split(')').next()) else {
+            return true;
+        };
+        !codes.split(',').map(str::trim).any(|allowed| allowed == code || allowed == "all")
+    }
+
+    // the comment of statement `i`: after `;`, or in the fixed-column
+    // layout whatever follows the operand field
+    fn comment(&self, i: usize) -> Option<String> {
+        let line = &self.lines[i];
+        if let Some((_, comment)) = line.split_once(';') {
+            return Some(comment.to_string());
+        }
+        if self.source_format != SourceFormat::Fixed {
+            return None;
+        }
+        let mut in_quote = false;
+        for (k, c) in line.chars().enumerate().skip(FIXED_OPERAND.0) {
+            if k >= FIXED_OPERAND.1 && !in_quote {
+                return Some(line.chars().skip(k).collect());
+            }
+            if c == '\'' {
+                in_quote = !in_quote;
+            }
+        }
+        None
+    }
 
-        for (label, addr) in absolute_symbols {
-            writeln!(symbol_table, "{:<10} {:04X}", label, addr).unwrap();
+    fn warn(&mut self, i: usize, diagnostic: Diagnostic) {
+        if self.allows(i, &diagnostic.code) {
+            self.diagnostics.push(diagnostic);
         }
+    }
+
+    fn write_statement(&mut self, file: &mut File, i: usize, line: String) {
+        writeln!(file, "{}", line).unwrap();
+        self.statement_sources.push(self.sources[i].clone());
+        self.statement_columns.push(self.opcode_column(i));
+    }
 
-        for (literal, addr) in absolute_literals {
-            writeln!(literals_table, "{:<10} {:04X}", literal, addr).unwrap();
+    // where the opcode starts in source line `i`, looking past the label so
+    // a label such as LDA1 is not taken for LDA
+    fn opcode_column(&self, i: usize) -> usize {
+        let line = self.lines[i].to_uppercase();
+        let label = self.labels[i].to_uppercase();
+        let after_label = if label.is_empty() { 0 } else { line.find(&label).map_or(0, |at| at + label.len()) };
+        let opcode = self.instr[i].to_uppercase();
+        match line[after_label..].find(&opcode) {
+            Some(at) if !opcode.is_empty() => after_label + at + 1,
+            _ => line.find(|c: char| !c.is_whitespace()).map_or(1, |at| at + 1),
+        }
+    }
+
+    // completes the last trace step once its statement has been processed
+    fn finish_trace_step(&mut self, block_locctrs: &HashMap<String, usize>, pending_literals: &[String]) {
+        if let Some(step) = self.trace_steps.last_mut() {
+            step.locctr_after = *block_locctrs.get(&step.block).unwrap_or(&0);
+            step.literal_pool = pending_literals.to_vec();
+        }
+    }
+
+    fn trace_symbol(&mut self, name: &str, value: usize, block: Option<String>) {
+        if let Some(step) = self.trace_steps.last_mut() {
+            step.symbols_added.push(TracedSymbol { name: name.to_string(), value, block });
+        }
+    }
+
+    // records the addresses `pool`'s literals were just given
+    fn trace_literals_placed(&mut self, pool: &[String], literals: &HashMap<String, (usize, String)>) {
+        if let Some(step) = self.trace_steps.last_mut() {
+            for literal in pool {
+                if let Some((value, block)) = literals.get(literal) {
+                    step.literals_placed.push(TracedSymbol { name: literal.clone(), value: *value, block: Some(block.clone()) });
+                }
+            }
+        }
+    }
+
+    fn fail(&mut self, diagnostic: Diagnostic) -> String {
+        let message = diagnostic.to_string();
+        self.diagnostics.push(diagnostic);
+        message
+    }
+
+    // records diagnostics for a label definition; returns false when the label
+    // was already defined, in which case the first definition is kept
+    fn check_label(&mut self, label: &str, i: usize, definitions: &mut HashMap<String, usize>) -> bool {
+        let location = self.sources[i].clone();
+
+        if let Some(&first) = definitions.get(label) {
+            let first_location = self.sources[first].clone();
+            self.diagnostics.push(
+                Diagnostic::error("duplicate-label", format!("Duplicate definition of label '{}'", label), &location)
+                    .with_related(&first_location, "first defined"),
+            );
+            return false;
+        }
+        definitions.insert(label.to_string(), i);
+
+        if REGISTERS.contains(&label) {
+            self.warn(i, Diagnostic::warning(
+                "register-label",
+                format!("Label '{}' shadows a register name and will be read as the register in register operands", label),
+                &location,
+            ));
+        } else if CONDITION_FLAGS.contains(&label) {
+            self.warn(i, Diagnostic::warning(
+                "condition-label",
+                format!("Label '{}' shadows a condition flag and will be read as the flag in format 4F operands", label),
+                &location,
+            ));
+        } else if [&FORMAT1[..], &FORMAT2[..], &FORMAT3[..], &FORMAT4[..], &DIRECTIVES[..]].concat().contains(&label) {
+            self.warn(i, Diagnostic::warning(
+                "mnemonic-label",
+                format!("Label '{}' shadows an instruction or directive name", label),
+                &location,
+            ));
+        }
+
+        if self.strict && label.len() > MAX_STRICT_LABEL_LEN {
+            self.diagnostics.push(Diagnostic::error(
+                "label-too-long",
+                format!("Label '{}' is longer than {} characters", label, MAX_STRICT_LABEL_LEN),
+                &location,
+            ));
+        }
+
+        true
+    }
+
+    // value of an EQU expression over the symbols defined so far; the block is
+    // None when the result is absolute, otherwise that of its relative term
+    fn evaluate_equ(
+        expr: &str,
+        symbols: &HashMap<String, (usize, String)>,
+        absolute_names: &HashSet<String>,
+    ) -> Result<(usize, Option<String>), String> {
+        let lookup = |name: &str| {
+            symbols.get(name).map(|(value, _)| (*value as i64, !absolute_names.contains(name)))
+        };
+        let value = expression::evaluate(expr, &|name| lookup(name).map(|(value, _)| value))?;
+        let value = usize::try_from(value).map_err(|_| format!("negative value {}", value))?;
+
+        match expression::relative_terms(expr, &lookup)? {
+            0 => Ok((value, None)),
+            1 => {
+                let block = expression::symbols(expr)
+                    .iter()
+                    .find(|name| !absolute_names.contains(*name))
+                    .and_then(|name| symbols.get(name))
+                    .map(|(_, block)| block.clone());
+                Ok((value, block))
+            }
+            _ => Err("expression is neither absolute nor relative".to_string()),
+        }
+    }
+
+    // bytes taken by a literal in the pool: one per character of =C'..', one
+    // per two hex digits of =X'..'
+    fn literal_size(literal: &str) -> usize {
+        let content = literal.get(3..literal.len().saturating_sub(1)).unwrap_or("");
+        if literal.starts_with("=C'") {
+            content.len()
+        } else if literal.starts_with("=X'") {
+            content.len().div_ceil(2)
+        } else {
+            0
         }
-        
-        Ok(())
     }
 
     fn calculate_byte_size(&self, ref_type: &str) -> usize {
@@ -263,4 +761,101 @@ impl Pass1 {
             0
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use crate::assembler::{assemble_file, assemble_text, AssembleError, AssemblyOptions};
+    use crate::source::SourceFormat;
+    use std::fs;
+
+    // assembles `main.asm` from a scratch directory holding `files`, with
+    // `include` as the only include path
+    fn assemble_project(name: &str, files: &[(&str, &str)], include: Option<&str>) -> Result<Vec<String>, String> {
+        let dir = std::env::temp_dir().join(format!("sicxe-include-{}-{}", name, std::process::id()));
+        for (file, text) in files {
+            let path = dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, text).unwrap();
+        }
+        let options = AssemblyOptions {
+            include_paths: include.map(|path| dir.join(path).to_string_lossy().into_owned()).into_iter().collect(),
+            ..AssemblyOptions::default()
+        };
+        let main = dir.join("src/main.asm");
+        let result = assemble_file(&main.to_string_lossy(), &dir.to_string_lossy(), &options)
+            .map(|assembly| assembly.symbols.into_iter().map(|entry| entry.name).collect())
+            .map_err(|e| e.to_string());
+        fs::remove_dir_all(&dir).unwrap();
+        result
+    }
+
+    const LIB: &str = "VALUE   WORD   5\n";
+
+    #[test]
+    fn includes_files_next_to_the_source_and_on_include_paths() {
+        let main = "PROG    START  0\nFIRST   LDA    VALUE\n        RSUB\n        INCLUDE 'lib.asm'\n        END    FIRST\n";
+        let symbols = assemble_project("near", &[("src/main.asm", main), ("src/lib.asm", LIB)], None).unwrap();
+        assert!(symbols.contains(&"VALUE".to_string()));
+
+        let main = main.replace("'lib.asm'", "'io.asm'");
+        let files = [("src/main.asm", main.as_str()), ("shared/io.asm", LIB)];
+        assert!(assemble_project("path", &files, Some("shared")).is_ok());
+        assert!(assemble_project("nopath", &files, None).is_err());
+    }
+
+    #[test]
+    fn rejects_includes_outside_the_project() {
+        let main = "PROG    START  0\nFIRST   RSUB\n        INCLUDE '../other/lib.asm'\n        END    FIRST\n";
+        let error = assemble_project("escape", &[("src/main.asm", main), ("other/lib.asm", LIB)], None).unwrap_err();
+        assert!(error.contains("outside the source and include directories"), "{}", error);
+
+        let main = "PROG    START  0\nFIRST   RSUB\n        INCLUDE '/etc/hostname'\n        END    FIRST\n";
+        let error = assemble_project("absolute", &[("src/main.asm", main)], None).unwrap_err();
+        assert!(error.contains("must be a relative path"), "{}", error);
+    }
+
+    #[test]
+    fn reports_every_undefined_operand_symbol() {
+        let source = "\
+PROG    START  0
+FIRST   LDA    NOWHERE
+        LDA    #MISSING
+        J      @GONE
+        ADDR   A,X
+        CADD   A,TABLE,Z
+        LDA    TABLE,X
+        RSUB
+TABLE   RESW   2
+        END    FIRST
+";
+        let Err(AssembleError::Pass1 { diagnostics, .. }) = assemble_text(source, &AssemblyOptions::default()) else {
+            panic!("expected Pass 1 errors");
+        };
+        let found: Vec<(&str, &str, usize)> = diagnostics.iter()
+            .map(|d| (d.code.as_str(), d.message.as_str(), d.location.line))
+            .collect();
+        assert_eq!(found, [
+            ("undefined-symbol", "Undefined symbol 'NOWHERE'", 2),
+            ("undefined-symbol", "Undefined symbol 'MISSING'", 3),
+            ("undefined-symbol", "Undefined symbol 'GONE'", 4),
+        ]);
+    }
+
+    #[test]
+    fn reads_lint_pragmas_from_fixed_column_comments() {
+        let options = AssemblyOptions { source_format: SourceFormat::Fixed, lint: true, ..AssemblyOptions::default() };
+        let source = "\
+PROG     START  0
+FIRST    RSUB
+SCRATCH  WORD   0                  lint: allow(unused-label)
+SPARE    WORD   0                  not a pragma
+         END    FIRST
+";
+        let assembly = assemble_text(source, &options).unwrap();
+        let unused: Vec<usize> = assembly.diagnostics.iter()
+            .filter(|d| d.code == "unused-label")
+            .map(|d| d.location.line)
+            .collect();
+        assert_eq!(unused, [4]);
+    }
+}
@@ -1,12 +1,40 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
 use std::io::{self, BufRead, Write};
 use crate::conversions::{get_register_value, string_to_hex, hex_string_to_hex, integer_to_hex};
+use crate::debug_info::{DebugInfo, SourceRange};
+use crate::diagnostics::Diagnostic;
+use crate::encoding::{self, Encoding};
+use crate::expression;
+use crate::pass1::DIRECTIVES;
+use crate::pass_trace::{self, Pass2Step};
+use crate::image::{MemoryImage, Provenance};
+use crate::source::SourceLocation;
+use serde::Serialize;
+
+pub const FORMAT1: [&str; 6] = ["FIX", "FLOAT", "HIO", "SIO", "TIO", "NORM"];  
+pub const FORMAT2: [&str; 11] = ["ADDR", "CLEAR", "COMPR", "DIVR", "MULR", "RMO", "SHIFTR", "SHIFTL", "SUBR", "SVC", "TIXR"];  
+pub const FORMAT3: [&str; 42] = ["ADD", "ADDF", "AND", "COMP", "COMPF", "DIV", "DIVF", "J", "JEQ", "JGT", "JLT", "JSUB", "LDA", "LDB", "LDCH", "LDF", "LDL", "LDS", "LDT", "LDX", "LPS", "MUL", "MULF", "OR", "RD", "RSUB", "SSK", "STA", "STB", "STCH", "STF", "STI", "STL", "STS", "STSW", "STT", "STX", "SUB", "SUBF", "TD", "TIX", "WD"];  
+pub const FORMAT4: [&str; 5] = ["CADD", "CSUB", "CLOAD", "CSTORE", "CJUMP"];  
+const BLOCK_ORDER: [&str; 4] = ["DEFAULTB", "CDATA", "CBLKS", "DEFAULT"];
+
+/// Mnemonics written without an operand, so `LABEL RSUB` is a label and an
+/// opcode rather than an opcode and an operand.
+pub fn takes_no_operand(mnemonic: &str) -> bool {
+    let upper = mnemonic.to_uppercase();
+    upper == "RSUB" || upper == "LTORG" || FORMAT1.contains(&upper.as_str())
+}
+
+/// Default maximum number of object code bytes in one text record.
+pub const MAX_TEXT_RECORD_LEN: usize = 30;
 
-const FORMAT1: [&str; 6] = ["FIX", "FLOAT", "HIO", "SIO", "TIO", "NORM"];  
-const FORMAT2: [&str; 11] = ["ADDR", "CLEAR", "COMPR", "DIVR", "MULR", "RMO", "SHIFTR", "SHIFTL", "SUBR", "SVC", "TIXR"];  
-const FORMAT3: [&str; 41] = ["ADD", "ADDF", "AND", "COMP", "COMPF", "DIV", "J", "JEQ", "JGT", "JLT", "JSUB", "LDA", "LDB", "LDCH", "LDF", "LDL", "LDS", "LDT", "LDX", "LPS", "MUL", "MULF", "OR", "RD", "RSUB", "SSK", "STA", "STB", "STCH", "STF", "STI", "STL", "STS", "STSW", "STT", "STX", "SUB", "SUBF", "TD", "TIX", "WD"];  
-const FORMAT4: [&str; 5] = ["CADD", "CSUB", "CLOAD", "CSTORE", "CJUMP"];  
+#[derive(Debug, Clone, Serialize)]
+pub struct BlockInfo {
+    pub name: String,
+    pub number: usize,
+    pub address: usize,
+    pub length: usize,
+}
 
 pub struct Pass2 {
     pub labels: Vec<String>,
@@ -15,8 +43,10 @@ pub struct Pass2 {
     pub locctrs: Vec<usize>,
     pub blocks: Vec<String>,
     pub symbol_table: HashMap<String, String>,
+    pub absolute_symbols: HashSet<String>,
     pub literal_table: HashMap<String, String>,
-    pub object_code: HashMap<usize, String>,
+    /// The object code; the text records and listing are written from it
+    pub image: MemoryImage,
     pub opcode_table: HashMap<String, String>,
     pub base_addr: Option<usize>,
     pub current_block: String,
@@ -24,6 +54,30 @@ pub struct Pass2 {
     pub program_name: String,
     pub start_addr: usize,
     pub program_length: usize,
+    pub first_exec_addr: usize,
+    pub block_table: Vec<BlockInfo>,
+    pub record_breaks: HashSet<usize>,
+    pub max_text_record_len: usize,
+    /// Source location of each statement, from `Pass1::statement_sources`;
+    /// when empty, statements are located by intermediate file line
+    pub sources: Vec<SourceLocation>,
+    /// Opcode column of each statement, from `Pass1::statement_columns`
+    pub columns: Vec<usize>,
+    pub diagnostics: Vec<Diagnostic>,
+    /// Whether to explain how each instruction was encoded
+    pub explain: bool,
+    /// One per instruction, in statement order, when `explain` is set
+    pub encodings: Vec<Encoding>,
+    /// Whether to record `trace_steps`
+    pub trace: bool,
+    /// One per statement, when `trace` is set
+    pub trace_steps: Vec<Pass2Step>,
+}
+
+impl Default for Pass2 {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Pass2 {
@@ -35,8 +89,9 @@ impl Pass2 {
             locctrs: Vec::new(),
             blocks: Vec::new(),
             symbol_table: HashMap::new(),
+            absolute_symbols: HashSet::new(),
             literal_table: HashMap::new(),
-            object_code: HashMap::new(),
+            image: MemoryImage::new(),
             opcode_table: Self::create_opcode_table(),
             base_addr: None,
             current_block: "DEFAULTB".to_string(),
@@ -44,6 +99,17 @@ impl Pass2 {
             program_name: String::new(),
             start_addr: 0,
             program_length: 0,
+            first_exec_addr: 0,
+            block_table: Vec::new(),
+            record_breaks: HashSet::new(),
+            max_text_record_len: MAX_TEXT_RECORD_LEN,
+            sources: Vec::new(),
+            columns: Vec::new(),
+            diagnostics: Vec::new(),
+            explain: false,
+            encodings: Vec::new(),
+            trace: false,
+            trace_steps: Vec::new(),
         }
     }
 
@@ -54,7 +120,7 @@ impl Pass2 {
         table.insert("FLOAT".to_string(), "C0".to_string());
         table.insert("HIO".to_string(), "F4".to_string());
         table.insert("SIO".to_string(), "F0".to_string());
-        table.insert("TIO".to_string(), "E0".to_string());
+        table.insert("TIO".to_string(), "F8".to_string());
         table.insert("NORM".to_string(), "C8".to_string());
         
         table.insert("ADDR".to_string(), "90".to_string());
@@ -75,6 +141,7 @@ impl Pass2 {
         table.insert("COMP".to_string(), "28".to_string());
         table.insert("COMPF".to_string(), "88".to_string());
         table.insert("DIV".to_string(), "24".to_string());
+        table.insert("DIVF".to_string(), "64".to_string());
         table.insert("J".to_string(), "3C".to_string());
         table.insert("JEQ".to_string(), "30".to_string());
         table.insert("JGT".to_string(), "34".to_string());
@@ -123,8 +190,7 @@ impl Pass2 {
     pub fn detect_instruction_format(&self, instr: &str) -> u8 {
         let instr_upper = instr.to_uppercase();
         
-        if instr_upper.starts_with('+') {
-            let instr_without_plus = &instr_upper[1..];
+        if let Some(instr_without_plus) = instr_upper.strip_prefix('+') {
             if FORMAT4.contains(&instr_without_plus) {
                 return 4;
             }
@@ -160,19 +226,29 @@ impl Pass2 {
     pub fn get_opcode(&self, instr: &str) -> Option<String> {  
         let instr_upper = instr.to_uppercase();
         
-        let instr_key = if instr_upper.starts_with('+') {
-            &instr_upper[1..]
-        } else {
-            instr_upper.as_str()
-        };
+        let instr_key = instr_upper.strip_prefix('+').unwrap_or(instr_upper.as_str());
         
         self.opcode_table.get(instr_key).cloned()
     }
 
+    // the text after the first `n` whitespace-separated fields of `line`
+    fn fields_after(line: &str, n: usize) -> &str {
+        let mut rest = line.trim_start();
+        for _ in 0..n {
+            rest = rest.find(char::is_whitespace).map_or("", |end| rest[end..].trim_start());
+        }
+        rest.trim_end()
+    }
+
+    // Pass1 writes the label field at column 7, after the 4-digit location
+    fn has_label_column(line: &str) -> bool {
+        line.chars().nth(7).is_some_and(|c| !c.is_whitespace())
+    }
+
     pub fn read_intermediate_file(&mut self, file_path: &str) -> io::Result<()> {
         let input_file = File::open(file_path)?;
         let reader = io::BufReader::new(input_file);
-        let lines: Vec<String> = reader.lines().filter_map(Result::ok).collect();
+        let lines: Vec<String> = reader.lines().map_while(Result::ok).collect();
 
         for line in lines {
             let parts: Vec<String> = line.split_whitespace()
@@ -189,11 +265,21 @@ impl Pass2 {
                 self.operands.push(if parts.len() > 2 { parts[2].clone() } else { "0".to_string() });
                 self.locctrs.push(0);
                 self.blocks.push("DEFAULT".to_string());
-            } else if parts.len() >= 4 {
+                self.program_name = parts[0].clone();
+                self.start_addr = self.operands.last().and_then(|op| usize::from_str_radix(op, 16).ok()).unwrap_or(0);
+            } else if parts.len() >= 3 && parts[1] == "*" && parts[2].starts_with('=') {
+                let locctr = usize::from_str_radix(&parts[0], 16).unwrap_or(0);
+                self.labels.push("*".to_string());
+                self.instr.push(Self::fields_after(&line, 2).to_string());
+                self.operands.push("&".to_string());
+                self.locctrs.push(locctr);
+                self.blocks.push(self.current_block.clone());
+            } else if parts.len() >= 4 && Self::has_label_column(&line) {
                 let locctr = usize::from_str_radix(&parts[0], 16).unwrap_or(0);
                 let label = if parts[1] == "&" { "&".to_string() } else { parts[1].clone() };
                 let instr = parts[2].clone();
-                let operand = if parts.len() > 3 { parts[3].clone() } else { "&".to_string() };
+                // the rest of the line, so constants such as C'A B' keep their spaces
+                let operand = Self::fields_after(&line, 3).to_string();
                 
                 self.labels.push(label);
                 self.instr.push(instr.clone());
@@ -206,13 +292,29 @@ impl Pass2 {
                 } else {
                     self.blocks.push(self.current_block.clone());
                 }
-            } else if parts.len() == 3 {
+
+                // Pass1 places the literal pool flushed by END in CDATA
+                if instr == "END" {
+                    self.current_block = "CDATA".to_string();
+                }
+            } else if parts.len() == 3 && parts[1] == "*" {
+                let locctr = usize::from_str_radix(&parts[0], 16).unwrap_or(0);
+                self.labels.push("*".to_string());
+                self.instr.push(parts[2].clone());
+                self.operands.push("&".to_string());
+                self.locctrs.push(locctr);
+                self.blocks.push(self.current_block.clone());
+            } else if parts.len() >= 3 {
                 let locctr = usize::from_str_radix(&parts[0], 16).unwrap_or(0);
                 self.labels.push("&".to_string());
                 self.instr.push(parts[1].clone());
-                self.operands.push(parts[2].clone());
+                self.operands.push(Self::fields_after(&line, 2).to_string());
                 self.locctrs.push(locctr);
                 self.blocks.push(self.current_block.clone());
+
+                if parts[1] == "END" {
+                    self.current_block = "CDATA".to_string();
+                }
             } else if parts.len() == 2 {
                 self.labels.push("&".to_string());
                 self.instr.push(parts[0].clone());
@@ -234,7 +336,7 @@ impl Pass2 {
     pub fn read_symbol_table(&mut self, file_path: &str) -> io::Result<()> {
         let input_file = File::open(file_path)?;
         let reader = io::BufReader::new(input_file);
-        let lines: Vec<String> = reader.lines().filter_map(Result::ok).collect();
+        let lines: Vec<String> = reader.lines().map_while(Result::ok).collect();
 
         for line in lines {
             let parts: Vec<String> = line.split_whitespace()
@@ -244,6 +346,9 @@ impl Pass2 {
             if parts.len() >= 2 {
                 self.symbol_table.insert(parts[0].clone(), parts[1].clone());
             }
+            if parts.len() >= 4 && parts[3] == "A" {
+                self.absolute_symbols.insert(parts[0].clone());
+            }
         }
 
         Ok(())
@@ -252,14 +357,21 @@ impl Pass2 {
     pub fn read_literal_table(&mut self, file_path: &str) -> io::Result<()> {
         let input_file = File::open(file_path)?;
         let reader = io::BufReader::new(input_file);
-        let lines: Vec<String> = reader.lines().filter_map(Result::ok).collect();
+        let lines: Vec<String> = reader.lines().map_while(Result::ok).collect();
 
         for line in lines {
             let parts: Vec<String> = line.split_whitespace()
                 .map(|s| s.to_string())
                 .collect();
 
-            if parts.len() >= 2 {
+            // name, address, block and R/A; the name may be a constant with spaces
+            if parts.len() >= 4 {
+                let mut name = line.trim_end();
+                for _ in 0..3 {
+                    name = name.rfind(char::is_whitespace).map_or("", |end| name[..end].trim_end());
+                }
+                self.literal_table.insert(name.to_string(), parts[parts.len() - 3].clone());
+            } else if parts.len() >= 2 {
                 self.literal_table.insert(parts[0].clone(), parts[1].clone());
             }
         }
@@ -275,7 +387,7 @@ impl Pass2 {
         let opcode = self.get_opcode(instr)?;
         
         let parts: Vec<&str> = operand.split(',').collect();
-        let reg1 = if parts.len() >= 1 { parts[0].trim() } else { "" };
+        let reg1 = if !parts.is_empty() { parts[0].trim() } else { "" };
         let reg2 = if parts.len() >= 2 { parts[1].trim() } else { "" };
         
         let reg1_val = get_register_value(reg1);
@@ -298,26 +410,36 @@ impl Pass2 {
 
     pub fn calculate_displacement(&self, operand: &str, locctr: usize, base_addr: Option<usize>) -> Option<(i32, bool, bool)> {
         let operand_clean = operand.trim_start_matches('#').trim_start_matches('@').trim_end_matches(",X").trim();
-        
+
+        // no operand (RSUB): zero displacement
+        if operand_clean == "&" {
+            return Some((0, false, false));
+        }
+
         let target_addr_opt = if operand_clean.starts_with('=') {
             self.literal_table.get(operand_clean).cloned()
         } else {
             self.symbol_table.get(operand_clean).cloned()
         };
 
+        // absolute symbols (EQU constants) are used as-is, like a number
+        if self.absolute_symbols.contains(operand_clean) {
+            let value = i32::from_str_radix(target_addr_opt.as_deref()?, 16).ok()?;
+            return Some((value, false, false));
+        }
+
         if let Some(target_addr) = target_addr_opt {
             let target = usize::from_str_radix(&target_addr, 16).ok()?;
             let pc_next = locctr + 3;
             
+            // PC-relative when it reaches, otherwise base-relative
             let pc_disp = target as i32 - pc_next as i32;
-            let use_pc = pc_disp >= -2048 && pc_disp <= 2047;
-            
-            if let Some(base) = base_addr {
-                let base_disp = target as i32 - base as i32;
-                let use_base = base_disp >= 0 && base_disp <= 4095;
-                Some((pc_disp, use_pc, use_base))
-            } else {
-                Some((pc_disp, use_pc, false))
+            if (-2048..=2047).contains(&pc_disp) {
+                return Some((pc_disp, true, false));
+            }
+            match base_addr.map(|base| target as i32 - base as i32) {
+                Some(base_disp) if (0..=4095).contains(&base_disp) => Some((base_disp, false, true)),
+                _ => Some((pc_disp, false, false)),
             }
         } else if let Ok(val) = operand_clean.parse::<i32>() {
             Some((val, false, false))
@@ -332,8 +454,8 @@ impl Pass2 {
         
         let (disp, use_pc, use_base) = self.calculate_displacement(operand, locctr, base_addr)?;
         
-        let n = if is_immediate { 0 } else if is_indirect { 1 } else { 1 };
-        let i = if is_immediate { 1 } else if is_indirect { 0 } else { 1 };
+        let n = if is_immediate { 0 } else { 1 };
+        let i = if is_indirect { 0 } else { 1 };
         let x = if is_indexed { 1 } else { 0 };
         let b = if use_base { 1 } else { 0 };
         let p = if use_pc { 1 } else { 0 };
@@ -348,10 +470,9 @@ impl Pass2 {
         Some(format!("{:02X}{:02X}{:02X}", first_byte, second_byte, third_byte))
     }
 
-    pub fn generate_format4_object_code(&self, instr: &str, operand: &str) -> Option<String> {
-        let opcode = self.get_opcode(instr)?;
-        let (is_immediate, is_indirect, is_indexed) = self.detect_addressing_mode(operand);
-        
+    // the address a format 4 operand names: a literal, a symbol or a decimal
+    // constant, as in format 3
+    fn format4_target(&self, operand: &str) -> Option<usize> {
         let operand_clean = operand.trim_start_matches('#').trim_start_matches('@').trim_end_matches(",X").trim();
         
         let target_addr_opt = if operand_clean.starts_with('=') {
@@ -360,14 +481,20 @@ impl Pass2 {
             self.symbol_table.get(operand_clean).cloned()
         };
 
-        let target_addr = if let Some(addr) = target_addr_opt {
-            usize::from_str_radix(&addr, 16).ok()?
+        if let Some(addr) = target_addr_opt {
+            usize::from_str_radix(&addr, 16).ok()
         } else {
-            usize::from_str_radix(operand_clean, 16).ok()?
-        };
+            operand_clean.parse::<usize>().ok()
+        }
+    }
+
+    pub fn generate_format4_object_code(&self, instr: &str, operand: &str) -> Option<String> {
+        let opcode = self.get_opcode(instr)?;
+        let (is_immediate, is_indirect, is_indexed) = self.detect_addressing_mode(operand);
+        let target_addr = self.format4_target(operand)?;
         
-        let n = if is_immediate { 0 } else if is_indirect { 1 } else { 1 };
-        let i = if is_immediate { 1 } else if is_indirect { 0 } else { 1 };
+        let n = if is_immediate { 0 } else { 1 };
+        let i = if is_indirect { 0 } else { 1 };
         let x = if is_indexed { 1 } else { 0 };
         let b = 0;
         let p = 0;
@@ -390,12 +517,12 @@ impl Pass2 {
         
         let (reg_val, memory, condition) = if is_cjump {
             let parts: Vec<&str> = operand.split(',').collect();
-            let memory = if parts.len() >= 1 { parts[0].trim() } else { "" };
+            let memory = if !parts.is_empty() { parts[0].trim() } else { "" };
             let condition = if parts.len() >= 2 { parts[1].trim() } else { "" };
             (0u8, memory, condition)
         } else {
             let parts: Vec<&str> = operand.split(',').collect();
-            let register = if parts.len() >= 1 { parts[0].trim() } else { "" };
+            let register = if !parts.is_empty() { parts[0].trim() } else { "" };
             let memory = if parts.len() >= 2 { parts[1].trim() } else { "" };
             let condition = if parts.len() >= 3 { parts[2].trim() } else { "" };
             let reg_val = get_register_value(register);
@@ -413,12 +540,12 @@ impl Pass2 {
         let target_addr = if let Some(addr) = self.symbol_table.get(memory) {
             usize::from_str_radix(addr, 16).ok()?
         } else {
-            usize::from_str_radix(memory, 16).ok()?
+            memory.parse::<usize>().ok()?
         };
         
         let opcode_num = usize::from_str_radix(&opcode, 16).ok()?;
         
-        let first_byte = ((opcode_num & 0xFC) as u8) | ((reg_val & 0xF) >> 2) as u8;
+        let first_byte = ((opcode_num & 0xFC) as u8) | ((reg_val & 0xF) >> 2);
         let second_byte = ((reg_val & 0x3) << 6) | ((condition_flag & 0x3) << 4) | ((target_addr >> 16) & 0xF) as u8;
         let third_byte = ((target_addr >> 8) & 0xFF) as u8;
         let fourth_byte = (target_addr & 0xFF) as u8;
@@ -455,7 +582,7 @@ impl Pass2 {
                 } else {
                     operand.parse::<usize>().ok()?
                 };
-                Some(integer_to_hex(value, 3))
+                (value <= 0xFFFFFF).then(|| integer_to_hex(value, 3))
             }
             "BYTE" => {
                 if operand.starts_with("C'") {
@@ -484,61 +611,115 @@ impl Pass2 {
     }
 
     pub fn handle_memory_block(&mut self, instr: &str, operand: &str) {
-        match instr.to_uppercase().as_str() {
-            "USE" => {
-                self.current_block = operand.to_string();
-            }
-            _ => {}
+        if instr.to_uppercase() == "USE" {
+            self.current_block = operand.to_string();
         }
     }
 
     pub fn generate_header_record(&self) -> String {
-        let name_padded = format!("{:<6}", &self.program_name[..self.program_name.len().min(6)]);
+        let name = if self.program_name == "&" { "" } else { self.program_name.as_str() };
+        let name_padded = format!("{:<6}", &name[..name.len().min(6)]);
         let start_hex = format!("{:06X}", self.start_addr);
         let length_hex = format!("{:06X}", self.program_length);
         format!("H{}{}{}", name_padded, start_hex, length_hex)
     }
 
+    // text records follow address order but never span a block boundary, the
+    // storage skipped by RESW/RESB, or more than `max_text_record_len` bytes;
+    // a statement's code is only split when it is longer than a whole record
     pub fn generate_text_records(&self) -> Vec<String> {
+        let max_len = self.max_text_record_len.clamp(1, 0xFF);
         let mut records = Vec::new();
-        let mut sorted_addrs: Vec<_> = self.object_code.keys().cloned().collect();
-        sorted_addrs.sort();
-
-        let mut i = 0;
-        while i < sorted_addrs.len() {
-            let start_addr = sorted_addrs[i];
-            let mut current_addr = start_addr;
-            let mut obj_code = String::new();
-            let mut bytes_count = 0;
-
-            while i < sorted_addrs.len() && bytes_count < 30 {
-                let addr = sorted_addrs[i];
-                if addr == current_addr {
-                    if let Some(code) = self.object_code.get(&addr) {
-                        obj_code.push_str(code);
-                        bytes_count += code.len() / 2;
-                        current_addr += code.len() / 2;
-                    }
-                    i += 1;
-                } else if addr < current_addr {
-                    i += 1;
-                } else {
-                    break;
-                }
+        let mut record_start = 0;
+        let mut record_code: Vec<u8> = Vec::new();
+
+        let mut bytes = self.image.iter();
+        while let Some((item_addr, _)) = bytes.next() {
+            let mut item = self.image.item(item_addr);
+            if item.is_empty() {
+                // bytes without a statement form items of one
+                item = self.image.get(item_addr).into_iter().collect();
+            }
+            let block = self.image.origin(item_addr).map(|origin| &origin.block);
+
+            let continues_record = !record_code.is_empty()
+                && item_addr == record_start + record_code.len()
+                && !self.record_breaks.contains(&item_addr)
+                && block == self.image.origin(record_start).map(|origin| &origin.block)
+                && record_code.len() + item.len() <= max_len;
+
+            if !continues_record {
+                Self::push_text_record(&mut records, record_start, &record_code);
+                record_code.clear();
+                record_start = item_addr;
             }
 
-            if !obj_code.is_empty() {
-                let start_hex = format!("{:06X}", start_addr);
-                let length_hex = format!("{:02X}", bytes_count);
-                records.push(format!("T{}{}{}", start_hex, length_hex, obj_code));
+            // a single item longer than a whole record is split across records
+            for (k, &byte) in item.iter().enumerate() {
+                if record_code.len() == max_len {
+                    Self::push_text_record(&mut records, record_start, &record_code);
+                    record_code.clear();
+                    record_start = item_addr + k;
+                }
+                record_code.push(byte);
+            }
+            if item.len() > 1 {
+                bytes.nth(item.len() - 2);
             }
         }
 
+        Self::push_text_record(&mut records, record_start, &record_code);
         records
     }
 
+    fn push_text_record(records: &mut Vec<String>, start_addr: usize, obj_code: &[u8]) {
+        if !obj_code.is_empty() {
+            records.push(format!("T{:06X}{:02X}{}", start_addr, obj_code.len(), hex(obj_code)));
+        }
+    }
+
     pub fn generate_end_record(&self) -> String {
-        format!("E{:06X}", self.start_addr)
+        format!("E{:06X}", self.first_exec_addr)
+    }
+
+    /// Assembly listing: address, label, opcode and operand of every statement
+    /// followed by the object code generated for it.
+    pub fn generate_listing(&self) -> String {
+        let mut listing = String::new();
+        for i in 0..self.instr.len() {
+            let instr = self.instr[i].as_str();
+            let address = if instr == "START" {
+                self.start_addr
+            } else {
+                let base = self.blocks.get(i).and_then(|block| self.block_bases.get(block)).copied().unwrap_or(0);
+                self.locctrs.get(i).copied().unwrap_or(0) + base
+            };
+            // reserved storage and zero-size statements share an address with
+            // the next piece of code
+            let emits_code = !matches!(instr, "START" | "RESW" | "RESB") && self.statement_size(i) > 0;
+            // code a later statement overwrote is not shown
+            let code = if emits_code && self.image.origin(address).is_some_and(|origin| origin.statement == i) {
+                hex(&self.image.item(address))
+            } else {
+                String::new()
+            };
+
+            let field = |i: usize, fields: &[String]| match fields.get(i).map(String::as_str) {
+                Some("&") | None => String::new(),
+                Some(value) => value.to_string(),
+            };
+            let row = format!(
+                "{:04X}   {:<10} {:<9} {:<18} {}",
+                address,
+                field(i, &self.labels),
+                instr,
+                field(i, &self.operands),
+                code,
+            );
+            listing.push_str(row.trim_end());
+            listing.push('\n');
+        }
+        listing
     }
 
     pub fn write_object_program(&self, output_path: &str) -> io::Result<()> {
@@ -561,6 +742,7 @@ impl Pass2 {
         self.read_literal_table(literal_path)?;
 
         self.calculate_block_bases();
+        let mut break_pending = false;
 
         for i in 0..self.instr.len() {
             let instr = self.instr[i].clone();
@@ -568,16 +750,29 @@ impl Pass2 {
             let label = if i < self.labels.len() { self.labels[i].clone() } else { "&".to_string() };
 
             self.handle_memory_block(&instr, &operand);
+            if self.trace {
+                self.trace_steps.push(Pass2Step {
+                    location: self.sources.get(i).cloned(),
+                    statement: pass_trace::statement_text(&label, &instr, &operand),
+                    block: self.blocks.get(i).cloned().unwrap_or_else(|| self.current_block.clone()),
+                    address: None,
+                    object_code: None,
+                    base_before: self.base_addr,
+                    base_after: self.base_addr,
+                });
+            }
 
             if instr.to_uppercase() == "START" {
                 self.program_name = label.clone();
                 self.start_addr = usize::from_str_radix(&operand, 16).unwrap_or(0);
+                self.first_exec_addr = self.start_addr;
                 continue;
             }
 
             if instr.to_uppercase() == "END" {
-                if let Some(addr) = self.symbol_table.get(&operand) {
-                    self.start_addr = usize::from_str_radix(addr, 16).unwrap_or(self.start_addr);
+                if operand != "&" {
+                    self.first_exec_addr = self.evaluate_expression(&operand)
+                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("Invalid END operand '{}': {}", operand, e)))?;
                 }
                 continue;
             }
@@ -601,21 +796,21 @@ impl Pass2 {
             let block_base = *self.block_bases.get(&block).unwrap_or(&0);
             let absolute_locctr = locctr + block_base;
 
-            if label == "*" && instr.starts_with('=') {
-                if let Some(obj_code) = self.get_literal_value(&instr) {
-                    self.object_code.insert(absolute_locctr, obj_code);
-                }
-                continue;
+            // reserved storage ends the current text record
+            if instr == "RESW" || instr == "RESB" {
+                break_pending = true;
             }
 
-            let format = self.detect_instruction_format(&instr);
-
-            if let Some(obj_code) = self.handle_directive(&instr, &operand, absolute_locctr) {
-                if !obj_code.is_empty() {
-                    self.object_code.insert(absolute_locctr, obj_code);
-                }
+            let mut is_instruction = false;
+            let mut format = 0;
+            let obj_code = if label == "*" && instr.starts_with('=') {
+                self.get_literal_value(&instr)
+            } else if DIRECTIVES.contains(&instr.to_uppercase().as_str()) {
+                self.handle_directive(&instr, &operand, absolute_locctr)
             } else {
-                let obj_code = if self.is_format4f(&instr) {
+                is_instruction = true;
+                format = self.detect_instruction_format(&instr);
+                if self.is_format4f(&instr) {
                     self.generate_format4f_object_code(&instr, &operand)
                 } else {
                     match format {
@@ -625,70 +820,260 @@ impl Pass2 {
                         4 => self.generate_format4_object_code(&instr, &operand),
                         _ => None,
                     }
-                };
+                }
+            };
 
-                if let Some(code) = obj_code {
-                    self.object_code.insert(absolute_locctr, code);
+            let problem = match &obj_code {
+                None if is_instruction && self.get_opcode(&instr).is_none() => {
+                    Some(("unknown-instruction", format!("Unknown instruction '{}'", instr)))
                 }
+                None => Some(("invalid-operand", format!("Invalid operand '{}' for {}", operand, instr))),
+                Some(_) if is_instruction && !self.is_format4f(&instr) => match format {
+                    3 => self.check_displacement(&operand, absolute_locctr).err().map(|message| ("displacement-out-of-range", message)),
+                    4 => self.format4_target(&operand)
+                        .filter(|target| *target > 0xFFFFF)
+                        .map(|target| ("address-out-of-range", format!("Address {} ({:X}) does not fit in format 4's 20 bits", target, target))),
+                    _ => None,
+                },
+                Some(_) => None,
+            };
+            if let Some((code, message)) = problem {
+                let location = self.statement_location(i);
+                self.diagnostics.push(Diagnostic::error(code, message, &location));
+            }
+
+            if let Some(step) = self.trace_steps.last_mut() {
+                step.address = Some(absolute_locctr);
+                step.object_code = obj_code.clone().filter(|code| !code.is_empty());
+                step.base_after = self.base_addr;
             }
-        }
 
-        if let Some(&max_addr) = self.object_code.keys().max() {
-            self.program_length = max_addr - self.start_addr + 1;
+            if let Some(code) = obj_code.filter(|code| !code.is_empty()) {
+                self.place(i, absolute_locctr, &code, &block);
+                if self.explain && is_instruction {
+                    if let Some(mut explained) = encoding::explain(self, &instr, &operand, absolute_locctr, &code) {
+                        explained.location = self.sources.get(i).cloned();
+                        self.encodings.push(explained);
+                    }
+                }
+                if break_pending {
+                    self.record_breaks.insert(absolute_locctr);
+                    break_pending = false;
+                }
+            }
         }
 
+        self.program_length = self.block_table.iter().map(|block| block.length).sum();
+        self.image.name = if self.program_name == "&" { String::new() } else { self.program_name.clone() };
+        self.image.entry = Some(self.first_exec_addr);
+
         self.write_object_program(output_path)?;
 
         Ok(())
     }
 
+    // writes statement `i`'s object code (hex) into the image at `address`,
+    // warning about code of earlier statements it overwrites
+    fn place(&mut self, i: usize, address: usize, code: &str, block: &str) {
+        let bytes: Vec<u8> = (0..code.len() / 2)
+            .filter_map(|k| u8::from_str_radix(&code[2 * k..2 * k + 2], 16).ok())
+            .collect();
+        let origin = Provenance { statement: i, location: self.sources.get(i).cloned(), block: block.to_string() };
+        let location = self.location(&origin);
+
+        for overlap in self.image.write(address, &bytes, origin) {
+            let mut diagnostic = Diagnostic::warning(
+                "overlapping-code",
+                format!(
+                    "'{}' overwrites {} byte{} of code at {:06X}",
+                    self.instr[i],
+                    overlap.length,
+                    if overlap.length == 1 { "" } else { "s" },
+                    overlap.address,
+                ),
+                &location,
+            );
+            if let Some(previous) = &overlap.previous {
+                diagnostic = diagnostic.with_related(&self.location(previous), "code first assembled here");
+            }
+            self.diagnostics.push(diagnostic);
+        }
+    }
+
+    // a format 3 displacement must reach its target PC- or base-relative, or
+    // be a constant that fits in 12 bits
+    fn check_displacement(&self, operand: &str, locctr: usize) -> Result<(), String> {
+        let Some((disp, use_pc, use_base)) = self.calculate_displacement(operand, locctr, self.base_addr) else {
+            return Ok(());
+        };
+        let name = operand.trim_start_matches('#').trim_start_matches('@').trim_end_matches(",X").trim();
+        if use_pc || use_base || name == "&" {
+            return Ok(());
+        }
+        if self.absolute_symbols.contains(name) || name.parse::<i32>().is_ok() {
+            if (0..=0xFFF).contains(&disp) {
+                return Ok(());
+            }
+            return Err(format!("Value {} does not fit in a 12-bit displacement; use + for format 4", name));
+        }
+        Err(format!("'{}' is out of reach of PC-relative and base-relative addressing; use + for format 4 or set BASE", name))
+    }
+
+    fn statement_location(&self, i: usize) -> SourceLocation {
+        self.sources.get(i).cloned().unwrap_or_else(|| SourceLocation::new("intermediate.txt", i + 1))
+    }
+
+    fn location(&self, origin: &Provenance) -> SourceLocation {
+        origin.location.clone().unwrap_or_else(|| self.statement_location(origin.statement))
+    }
+
+    /// Source ranges of the assembled image, one per run of bytes from the
+    /// same statement.
+    pub fn debug_info(&self) -> DebugInfo {
+        let mut ranges: Vec<SourceRange> = Vec::new();
+        let mut previous: Option<&Provenance> = None;
+        for (address, _) in self.image.iter() {
+            let Some(origin) = self.image.origin(address) else {
+                previous = None;
+                continue;
+            };
+            if let (Some(range), Some(last)) = (ranges.last_mut(), previous) {
+                if range.end == address && last == origin {
+                    range.end += 1;
+                    continue;
+                }
+            }
+            previous = Some(origin);
+
+            let i = origin.statement;
+            let location = self.location(origin);
+            let symbol = match self.labels[i].as_str() {
+                "&" => None,
+                "*" => Some(self.instr[i].clone()),
+                label => Some(label.to_string()),
+            };
+            ranges.push(SourceRange {
+                start: address,
+                end: address + 1,
+                file: location.file,
+                line: location.line,
+                column: self.columns.get(i).copied().unwrap_or(1),
+                block: origin.block.clone(),
+                symbol,
+            });
+        }
+        DebugInfo { program: self.image.name.clone(), entry: self.image.entry, ranges }
+    }
+
+    pub fn evaluate_expression(&self, expr: &str) -> Result<usize, String> {
+        let lookup = |name: &str| {
+            self.symbol_table.get(name)
+                .and_then(|addr| i64::from_str_radix(addr, 16).ok())
+        };
+        let value = expression::evaluate(expr, &lookup)?;
+        usize::try_from(value).map_err(|_| format!("Expression evaluates to negative value {}", value))
+    }
+
+    // places the blocks one after another and fills the block table; the
+    // DEFAULTB counter already starts at the START address, so its base is 0
     fn calculate_block_bases(&mut self) {
         let mut block_sizes: HashMap<String, usize> = HashMap::new();
         block_sizes.insert("DEFAULT".to_string(), 0);
-        block_sizes.insert("DEFAULTB".to_string(), 0);
+        block_sizes.insert("DEFAULTB".to_string(), self.start_addr);
         block_sizes.insert("CDATA".to_string(), 0);
         block_sizes.insert("CBLKS".to_string(), 0);
 
         for i in 0..self.locctrs.len() {
             if i < self.blocks.len() {
                 let block = self.blocks[i].clone();
-                let locctr = self.locctrs[i];
-                let instr = self.instr[i].clone();
-                let operand = if i < self.operands.len() { self.operands[i].clone() } else { "&".to_string() };
-                let format1 = ["FIX", "FLOAT", "HIO", "SIO", "TIO", "NORM"];
-                let format2 = ["ADDR", "CLEAR", "COMPR", "DIVR", "MULR", "RMO", "SHIFTR", "SHIFTL", "SUBR", "SVC", "TIXR"];
-                let format4f = ["CADD", "CSUB", "CLOAD", "CSTORE", "CJUMP"];
-
-                let increment = match instr.as_str() {
-                    _ if format1.contains(&instr.as_str()) => 1,
-                    _ if format2.contains(&instr.as_str()) => 2,
-                    _ if format4f.contains(&instr.as_str()) || instr.starts_with('+') => 4,
-                    "WORD" => 3,
-                    "RESW" => operand.parse::<usize>().unwrap_or(0) * 3,
-                    "RESB" => operand.parse::<usize>().unwrap_or(0),
-                    "BYTE" => {
-                        if operand.starts_with("X'") {
-                            (operand.len() - 3) / 2
-                        } else if operand.starts_with("C'") {
-                            operand.len() - 3
-                        } else {
-                            1
-                        }
-                    }
-                    "BASE" | "LTORG" | "END" | "START" | "USE" => 0,
-                    _ => 3,
-                };
-                
-                let final_locctr = locctr + increment;
-                *block_sizes.get_mut(&block).unwrap_or(&mut 0) = (*block_sizes.get(&block).unwrap_or(&0)).max(final_locctr);
+                let final_locctr = self.locctrs[i] + self.statement_size(i);
+                let size = block_sizes.entry(block).or_insert(0);
+                *size = (*size).max(final_locctr);
             }
         }
 
+        self.block_table.clear();
         let mut base = 0;
-        let blocks = ["DEFAULTB", "CDATA", "CBLKS", "DEFAULT"];
-        for block in blocks {
+        for (number, block) in BLOCK_ORDER.iter().enumerate() {
+            let size = *block_sizes.get(*block).unwrap_or(&0);
+            let address = if number == 0 { self.start_addr } else { base };
             self.block_bases.insert(block.to_string(), base);
-            base += *block_sizes.get(block).unwrap_or(&0);
+            self.block_table.push(BlockInfo {
+                name: block.to_string(),
+                number,
+                address,
+                length: base + size - address,
+            });
+            base += size;
+        }
+    }
+
+    // bytes occupied by the statement at index i of the intermediate file
+    fn statement_size(&self, i: usize) -> usize {
+        let instr = self.instr[i].as_str();
+        let operand = if i < self.operands.len() { self.operands[i].as_str() } else { "&" };
+
+        if self.labels[i] == "*" && instr.starts_with('=') {
+            return self.get_literal_value(instr).map_or(0, |hex| hex.len() / 2);
+        }
+
+        match instr {
+            _ if FORMAT1.contains(&instr) => 1,
+            _ if FORMAT2.contains(&instr) => 2,
+            _ if FORMAT4.contains(&instr) || instr.starts_with('+') => 4,
+            "WORD" => 3,
+            "RESW" => operand.parse::<usize>().unwrap_or(0) * 3,
+            "RESB" => operand.parse::<usize>().unwrap_or(0),
+            "BYTE" => {
+                if operand.starts_with("X'") {
+                    (operand.len() - 3) / 2
+                } else if operand.starts_with("C'") {
+                    operand.len() - 3
+                } else {
+                    1
+                }
+            }
+            "BASE" | "LTORG" | "END" | "START" | "USE" | "EQU" => 0,
+            _ => 3,
         }
     }
-}
\ No newline at end of file
+
+    pub fn block_of(&self, addr: usize) -> Option<&BlockInfo> {
+        self.block_table.iter()
+            .find(|block| addr >= block.address && addr < block.address + block.length)
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02X}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::{assemble_text, AssembleError, AssemblyOptions};
+
+    #[test]
+    fn format4_constants_are_decimal() {
+        let pass2 = Pass2::new();
+        assert_eq!(pass2.generate_format4_object_code("+LDT", "#4096").as_deref(), Some("75101000"));
+        assert_eq!(pass2.generate_format4_object_code("+LDA", "#1000000").as_deref(), Some("011F4240"));
+        assert_eq!(pass2.generate_format3_object_code("LDT", "#4095", 0, None).as_deref(), Some("750FFF"));
+    }
+
+    #[test]
+    fn assembles_format4_constants() {
+        let assembly = assemble_text("PROG    START  0\nFIRST   +LDT   #4096\n        +LDA   #1000000\n        RSUB\n        END    FIRST\n", &AssemblyOptions::default()).unwrap();
+        assert!(assembly.object_program.lines().any(|line| line == "T0000000B75101000011F42404F0000"));
+    }
+
+    #[test]
+    fn reports_operands_that_do_not_fit() {
+        let source = "PROG    START  0\nFIRST   LDA    #5000\n        +LDA   #1048576\n        FOO    1\n        RSUB\n        END    FIRST\n";
+        let Err(AssembleError::Pass2 { diagnostics, .. }) = assemble_text(source, &AssemblyOptions::default()) else {
+            panic!("expected Pass 2 errors");
+        };
+        let found: Vec<(&str, usize)> = diagnostics.iter().map(|d| (d.code.as_str(), d.location.line)).collect();
+        assert_eq!(found, [("displacement-out-of-range", 2), ("address-out-of-range", 3), ("unknown-instruction", 4)]);
+    }
+}
@@ -0,0 +1,67 @@
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::source::SourceLocation;
+
+/// A name given a value while tracing: a label, an `EQU` or a literal.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct TracedSymbol {
+    pub name: String,
+    /// Address relative to the start of `block`, or the value of an
+    /// absolute `EQU`
+    pub value: usize,
+    /// `None` for absolute symbols
+    pub block: Option<String>,
+}
+
+/// What Pass 1 did with one source statement.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct Pass1Step {
+    pub location: SourceLocation,
+    pub statement: String,
+    /// Block whose LOCCTR the statement uses; for `USE`, the block it
+    /// switches to
+    pub block: String,
+    pub locctr_before: usize,
+    pub locctr_after: usize,
+    pub symbols_added: Vec<TracedSymbol>,
+    /// Literals first referenced here, now waiting in the pool
+    pub literals_added: Vec<String>,
+    /// Literals given addresses here, by `LTORG` or `END`
+    pub literals_placed: Vec<TracedSymbol>,
+    /// Literals still waiting for an address after this statement
+    pub literal_pool: Vec<String>,
+}
+
+/// What Pass 2 did with one intermediate file statement, including the
+/// literal pool entries Pass 1 added.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct Pass2Step {
+    pub location: Option<SourceLocation>,
+    pub statement: String,
+    pub block: String,
+    /// Absolute address; `None` for `START`, `END` and `USE`
+    pub address: Option<usize>,
+    pub object_code: Option<String>,
+    pub base_before: Option<usize>,
+    pub base_after: Option<usize>,
+}
+
+/// A step-by-step record of both passes, for showing how the assembler
+/// builds its state.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, ToSchema)]
+pub struct PassTrace {
+    pub pass1: Vec<Pass1Step>,
+    pub pass2: Vec<Pass2Step>,
+}
+
+/// A statement's fields as one line, leaving out the ones the passes mark
+/// empty with `&`.
+pub fn statement_text(label: &str, instr: &str, operand: &str) -> String {
+    [label, instr, operand]
+        .iter()
+        .filter(|field| !field.is_empty() && **field != "&")
+        .copied()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
@@ -0,0 +1,346 @@
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+use crate::conversions::get_register_name;
+use crate::debug_info::DebugInfo;
+use crate::emulator::{Halt, Machine, Registers};
+use crate::tables::csv_field;
+
+/// A register an instruction changed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RegisterChange {
+    pub register: String,
+    /// Hex for the integer registers, a decimal number for `F`
+    pub before: String,
+    pub after: String,
+}
+
+/// Bytes an instruction stored.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MemoryWrite {
+    pub address: usize,
+    /// The bytes written, in hex
+    pub bytes: String,
+}
+
+/// One executed instruction.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TraceStep {
+    /// 1-based count of instructions executed
+    pub step: u64,
+    pub address: usize,
+    pub instruction: String,
+    /// `file:line:column` of the statement, when debug info is given
+    pub source: Option<String>,
+    /// Registers that changed, other than `PC`; the next step's `address`
+    /// is where `PC` went
+    pub registers: Vec<RegisterChange>,
+    pub writes: Vec<MemoryWrite>,
+}
+
+/// Instructions executed from one source line.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LineProfile {
+    pub file: String,
+    pub line: usize,
+    pub instructions: u64,
+}
+
+/// Instructions executed in one subroutine, i.e. between a `JSUB` to
+/// `address` and the return past it. The program's entry point counts as a
+/// subroutine called once.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SubroutineProfile {
+    /// Label of `address`, or the address in hex
+    pub name: String,
+    pub address: usize,
+    pub calls: u64,
+    /// Instructions executed in the subroutine itself
+    pub instructions: u64,
+    /// Including the subroutines it called
+    pub inclusive: u64,
+}
+
+/// How often one byte of memory was used.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct MemoryHeat {
+    pub address: usize,
+    /// Times the byte was fetched as part of an instruction
+    pub executes: u64,
+    pub reads: u64,
+    pub writes: u64,
+}
+
+/// Counts gathered over a run.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Profile {
+    pub instructions: u64,
+    /// Source lines, most executed first; empty without debug info
+    pub lines: Vec<LineProfile>,
+    /// Most instructions (inclusive) first
+    pub subroutines: Vec<SubroutineProfile>,
+    /// Every byte used, in address order
+    pub heatmap: Vec<MemoryHeat>,
+}
+
+impl Profile {
+    /// The heatmap as CSV, one row per byte.
+    pub fn heatmap_csv(&self) -> String {
+        let mut out = String::from("address,executes,reads,writes\n");
+        for heat in &self.heatmap {
+            out.push_str(&format!("{:06X},{},{},{}\n", heat.address, heat.executes, heat.reads, heat.writes));
+        }
+        out
+    }
+
+    /// A readable summary: the busiest lines and subroutines.
+    pub fn render(&self, limit: usize) -> String {
+        let mut out = format!("{} instructions executed\n", self.instructions);
+        if !self.lines.is_empty() {
+            out.push_str("\nlines:\n");
+            for line in self.lines.iter().take(limit) {
+                out.push_str(&format!("  {:>10}  {}:{}\n", line.instructions, line.file, line.line));
+            }
+        }
+        out.push_str("\nsubroutines:          calls       self  inclusive\n");
+        for subroutine in self.subroutines.iter().take(limit) {
+            out.push_str(&format!(
+                "  {:<14} {:>10} {:>10} {:>10}\n",
+                subroutine.name, subroutine.calls, subroutine.instructions, subroutine.inclusive,
+            ));
+        }
+        out
+    }
+}
+
+/// Renders `steps` as CSV. Changed registers are written `A=000000>000005`
+/// and writes `001030:000005`, several separated by spaces.
+pub fn trace_csv(steps: &[TraceStep]) -> String {
+    let mut out = String::from("step,address,instruction,source,registers,writes\n");
+    for step in steps {
+        let registers: Vec<String> = step.registers.iter()
+            .map(|change| format!("{}={}>{}", change.register, change.before, change.after))
+            .collect();
+        let writes: Vec<String> = step.writes.iter().map(|write| format!("{:06X}:{}", write.address, write.bytes)).collect();
+        out.push_str(&format!(
+            "{},{:06X},{},{},{},{}\n",
+            step.step,
+            step.address,
+            csv_field(&step.instruction),
+            step.source.as_deref().unwrap_or(""),
+            registers.join(" "),
+            writes.join(" "),
+        ));
+    }
+    out
+}
+
+// a subroutine being executed: where it starts and where it returns to
+struct Frame {
+    address: usize,
+    return_address: usize,
+}
+
+/// Runs a [`Machine`] while recording an execution trace and counting what
+/// it executes. Labels in `symbols` name subroutines and trace operands.
+pub struct Profiler {
+    pub machine: Machine,
+    /// Labels and their addresses, e.g. from `Assembly::symbols` or
+    /// `LoadMap::estab()`
+    pub symbols: HashMap<String, usize>,
+    /// Source lines by address, for `TraceStep::source` and per-line counts
+    pub debug_info: DebugInfo,
+    /// Whether to keep `trace`; the profile is always gathered
+    pub record_trace: bool,
+    pub trace: Vec<TraceStep>,
+    /// Instructions executed per debug info range, by its start address
+    ranges: HashMap<usize, u64>,
+    subroutines: BTreeMap<usize, SubroutineProfile>,
+    heatmap: BTreeMap<usize, MemoryHeat>,
+    stack: Vec<Frame>,
+}
+
+impl Profiler {
+    pub fn new(mut machine: Machine) -> Self {
+        machine.record_accesses = true;
+        Self {
+            machine,
+            symbols: HashMap::new(),
+            debug_info: DebugInfo::new(),
+            record_trace: false,
+            trace: Vec::new(),
+            ranges: HashMap::new(),
+            subroutines: BTreeMap::new(),
+            heatmap: BTreeMap::new(),
+            stack: Vec::new(),
+        }
+    }
+
+    /// Runs at most `max_steps` instructions, as [`Machine::run`] does.
+    pub fn run(&mut self, max_steps: u64) -> Halt {
+        let labels = self.labels();
+        if self.stack.is_empty() {
+            let entry = self.machine.registers.pc as usize;
+            self.enter(entry, usize::MAX, &labels);
+        }
+        for _ in 0..max_steps {
+            if let Some(halt) = self.step(&labels) {
+                return halt;
+            }
+        }
+        Halt::StepLimit
+    }
+
+    /// The counts so far.
+    pub fn profile(&self) -> Profile {
+        let mut counts: HashMap<(&str, usize), u64> = HashMap::new();
+        for (&start, &instructions) in &self.ranges {
+            if let Some(range) = self.debug_info.lookup(start) {
+                *counts.entry((range.file.as_str(), range.line)).or_default() += instructions;
+            }
+        }
+        let mut lines: Vec<LineProfile> = counts.into_iter()
+            .map(|((file, line), instructions)| LineProfile { file: file.to_string(), line, instructions })
+            .collect();
+        lines.sort_by(|a, b| b.instructions.cmp(&a.instructions).then_with(|| (&a.file, a.line).cmp(&(&b.file, b.line))));
+        let mut subroutines: Vec<SubroutineProfile> = self.subroutines.values().cloned().collect();
+        subroutines.sort_by(|a, b| b.inclusive.cmp(&a.inclusive).then_with(|| a.address.cmp(&b.address)));
+        Profile {
+            instructions: self.machine.steps,
+            lines,
+            subroutines,
+            heatmap: self.heatmap.values().cloned().collect(),
+        }
+    }
+
+    // one instruction; `Some` when the machine stopped
+    fn step(&mut self, labels: &BTreeMap<usize, String>) -> Option<Halt> {
+        let address = self.machine.registers.pc as usize;
+        let Ok(instruction) = self.machine.fetch(address) else {
+            return self.machine.step();
+        };
+        let before = self.machine.registers.clone();
+        let steps = self.machine.steps;
+        let halt = self.machine.step();
+        // a fault, or returning before the instruction ran
+        if self.machine.steps == steps {
+            return halt;
+        }
+
+        if let Some(range) = self.debug_info.lookup(address) {
+            *self.ranges.entry(range.start).or_default() += 1;
+        }
+        self.count_instruction();
+        for offset in 0..instruction.size() {
+            self.heat(address + offset).executes += 1;
+        }
+        let accesses = std::mem::take(&mut self.machine.accesses);
+        for access in &accesses {
+            for offset in 0..access.bytes.len() {
+                let heat = self.heat(access.address + offset);
+                if access.write {
+                    heat.writes += 1;
+                } else {
+                    heat.reads += 1;
+                }
+            }
+        }
+
+        if self.record_trace {
+            let base = Some(before.b as usize);
+            self.trace.push(TraceStep {
+                step: self.machine.steps,
+                address,
+                instruction: instruction.text(base, labels),
+                source: self.debug_info.lookup(address).map(ToString::to_string),
+                registers: register_changes(&before, &self.machine.registers),
+                writes: accesses.iter()
+                    .filter(|access| access.write)
+                    .map(|access| MemoryWrite { address: access.address, bytes: hex(&access.bytes) })
+                    .collect(),
+            });
+        }
+
+        let pc = self.machine.registers.pc as usize;
+        if instruction.mnemonic == "JSUB" && halt.is_none() {
+            self.enter(pc, address + instruction.size(), labels);
+        }
+        // RSUB, or J @RETADR after STL RETADR
+        while self.stack.len() > 1 && self.stack.last().is_some_and(|frame| frame.return_address == pc) {
+            self.stack.pop();
+        }
+        halt
+    }
+
+    fn enter(&mut self, address: usize, return_address: usize, labels: &BTreeMap<usize, String>) {
+        let subroutine = self.subroutines.entry(address).or_insert_with(|| SubroutineProfile {
+            name: labels.get(&address).cloned().unwrap_or_else(|| format!("{:06X}", address)),
+            address,
+            calls: 0,
+            instructions: 0,
+            inclusive: 0,
+        });
+        subroutine.calls += 1;
+        self.stack.push(Frame { address, return_address });
+    }
+
+    // charges the instruction just executed to the innermost subroutine, and
+    // once to every subroutine on the stack for the inclusive count
+    fn count_instruction(&mut self) {
+        for (k, frame) in self.stack.iter().enumerate() {
+            if self.stack[..k].iter().any(|outer| outer.address == frame.address) {
+                continue;
+            }
+            if let Some(subroutine) = self.subroutines.get_mut(&frame.address) {
+                subroutine.inclusive += 1;
+            }
+        }
+        if let Some(subroutine) = self.stack.last().and_then(|frame| self.subroutines.get_mut(&frame.address)) {
+            subroutine.instructions += 1;
+        }
+    }
+
+    fn heat(&mut self, address: usize) -> &mut MemoryHeat {
+        self.heatmap.entry(address).or_insert_with(|| MemoryHeat { address, ..MemoryHeat::default() })
+    }
+
+    // labels by address; the first alphabetically when several share one
+    fn labels(&self) -> BTreeMap<usize, String> {
+        let mut labels: BTreeMap<usize, String> = BTreeMap::new();
+        for (name, &address) in &self.symbols {
+            let entry = labels.entry(address).or_insert_with(|| name.clone());
+            if name < entry {
+                *entry = name.clone();
+            }
+        }
+        for range in &self.debug_info.ranges {
+            if let Some(symbol) = range.symbol.as_ref().filter(|symbol| !symbol.starts_with('=')) {
+                labels.entry(range.start).or_insert_with(|| symbol.clone());
+            }
+        }
+        labels
+    }
+}
+
+fn register_changes(before: &Registers, after: &Registers) -> Vec<RegisterChange> {
+    let mut changes = Vec::new();
+    for register in [0, 1, 2, 3, 4, 5, 9] {
+        let (old, new) = (before.get(register).unwrap_or(0), after.get(register).unwrap_or(0));
+        if old != new {
+            changes.push(RegisterChange {
+                register: get_register_name(register).unwrap_or("?").to_string(),
+                before: format!("{:06X}", old),
+                after: format!("{:06X}", new),
+            });
+        }
+        // F sits between T and SW in register order
+        if register == 5 && before.f.to_bits() != after.f.to_bits() {
+            changes.push(RegisterChange { register: "F".to_string(), before: before.f.to_string(), after: after.f.to_string() });
+        }
+    }
+    changes
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02X}", byte)).collect()
+}
@@ -0,0 +1,39 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use utoipa::ToSchema;
+
+/// Where a statement came from: the file it was read from (relative to the
+/// directory of the main source file) and its 1-based line number.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, ToSchema)]
+pub struct SourceLocation {
+    pub file: String,
+    pub line: usize,
+}
+
+impl SourceLocation {
+    pub fn new(file: &str, line: usize) -> Self {
+        Self {
+            file: file.to_string(),
+            line,
+        }
+    }
+}
+
+impl fmt::Display for SourceLocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.file, self.line)
+    }
+}
+
+/// How source lines are split into label, opcode and operand.
+///
+/// - `Free`: whitespace-separated tokens; the token count decides their roles
+/// - `Fixed`: textbook columns (label 1-8, opcode 10-15, operand 17-35,
+///   comment after), so operands may contain spaces
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SourceFormat {
+    #[default]
+    Free,
+    Fixed,
+}
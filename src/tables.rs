@@ -0,0 +1,71 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// Order in which symbol and literal tables are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum TableOrder {
+    #[default]
+    Name,
+    Address,
+}
+
+/// One row of the symbol or literal table. `block` is `None` for absolute
+/// symbols (`EQU` of a constant expression), which belong to no block.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct TableEntry {
+    pub name: String,
+    pub address: usize,
+    pub block: Option<String>,
+    pub relative: bool,
+}
+
+pub fn sort_entries(entries: &mut [TableEntry], order: TableOrder) {
+    match order {
+        TableOrder::Name => entries.sort_by(|a, b| a.name.cmp(&b.name)),
+        TableOrder::Address => entries.sort_by(|a, b| a.address.cmp(&b.address).then_with(|| a.name.cmp(&b.name))),
+    }
+}
+
+/// Text form written to `symbTable.txt` / `litTable.txt`. The first two
+/// columns (name, hex address) are what Pass2 reads back.
+pub fn render_text(entries: &[TableEntry]) -> String {
+    let mut out = String::new();
+    for entry in entries {
+        out.push_str(&format!(
+            "{:<10} {:04X}   {:<8} {}\n",
+            entry.name,
+            entry.address,
+            entry.block.as_deref().unwrap_or("-"),
+            if entry.relative { "R" } else { "A" }
+        ));
+    }
+    out
+}
+
+pub fn render_json(entries: &[TableEntry]) -> String {
+    serde_json::to_string_pretty(entries).unwrap_or_else(|_| "[]".to_string())
+}
+
+pub fn render_csv(entries: &[TableEntry]) -> String {
+    let mut out = String::from("name,address,block,type\n");
+    for entry in entries {
+        out.push_str(&format!(
+            "{},{:04X},{},{}\n",
+            csv_field(&entry.name),
+            entry.address,
+            entry.block.as_deref().unwrap_or(""),
+            if entry.relative { "R" } else { "A" }
+        ));
+    }
+    out
+}
+
+// literal names such as =C'A,B' need quoting
+pub fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
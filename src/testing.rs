@@ -0,0 +1,412 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+use crate::conversions::{get_register_name, get_register_value};
+use crate::debugger::Debugger;
+use crate::devices::Device;
+use crate::emulator::{Halt, Machine, DEFAULT_STEP_LIMIT};
+use crate::object_program::ObjectProgram;
+
+/// A test file: a program and the cases to run it against.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TestFile {
+    /// Source file, relative to the test file
+    pub source: String,
+    /// Directories searched for `INCLUDE`/`COPY` files, relative to the test
+    /// file
+    #[serde(default)]
+    pub include_paths: Vec<String>,
+    /// Most instructions each case may run, unless the case sets its own
+    pub step_limit: Option<u64>,
+    pub cases: Vec<TestCase>,
+}
+
+impl TestFile {
+    pub fn parse(json: &str) -> Result<Self, String> {
+        serde_json::from_str(json).map_err(|e| format!("Invalid test file: {}", e))
+    }
+}
+
+/// A register's value: a number, or a string of hex digits. Integers are
+/// kept to 24 bits, so -1 is `FFFFFF`; `F` takes any number.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum RegisterValue {
+    Number(f64),
+    Hex(String),
+}
+
+/// One run of the program: the state it starts from and what it should end
+/// with. Memory locations are hex addresses, labels, or a label plus a hex
+/// offset (`TABLE+3`); memory contents are hex bytes. Devices are numbered
+/// in hex.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TestCase {
+    pub name: String,
+    #[serde(default)]
+    pub registers: BTreeMap<String, RegisterValue>,
+    #[serde(default)]
+    pub memory: BTreeMap<String, String>,
+    /// Text each input device reads
+    #[serde(default)]
+    pub inputs: BTreeMap<String, String>,
+    pub step_limit: Option<u64>,
+    #[serde(default)]
+    pub expect: Expectation,
+}
+
+/// What a case checks after the run. Only the registers, memory and devices
+/// named are compared.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Expectation {
+    #[serde(default)]
+    pub registers: BTreeMap<String, RegisterValue>,
+    #[serde(default)]
+    pub memory: BTreeMap<String, String>,
+    /// Text each output device should have been written, in full
+    #[serde(default)]
+    pub output: BTreeMap<String, String>,
+    /// How the run should stop: `returned`, `loop`, `svc`, `step_limit` or
+    /// `fault`. When left out, any stop other than the step limit or a fault
+    /// passes.
+    pub halt: Option<String>,
+}
+
+/// A value that did not match.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Mismatch {
+    /// `A`, `memory TABLE+3`, `device 05 (differs from character 2)` or
+    /// `halt`
+    pub what: String,
+    pub expected: String,
+    pub actual: String,
+}
+
+/// The outcome of one case.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CaseResult {
+    pub name: String,
+    pub passed: bool,
+    /// How the run stopped, e.g. `returned` or `fault at 001006: ...`
+    pub halt: String,
+    pub steps: u64,
+    pub mismatches: Vec<Mismatch>,
+    /// Set when the case could not be run, e.g. for an unknown label; no
+    /// comparisons are made
+    pub error: Option<String>,
+}
+
+/// The outcome of every case in a test file.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct TestReport {
+    pub source: String,
+    pub cases: Vec<CaseResult>,
+}
+
+impl TestReport {
+    pub fn passed(&self) -> usize {
+        self.cases.iter().filter(|case| case.passed).count()
+    }
+
+    pub fn failed(&self) -> usize {
+        self.cases.len() - self.passed()
+    }
+
+    /// One line per case, with the mismatches of each failed case under it.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for case in &self.cases {
+            out.push_str(&format!("{} {}\n", if case.passed { "PASS" } else { "FAIL" }, case.name));
+            if let Some(error) = &case.error {
+                out.push_str(&format!("    error: {}\n", error));
+            }
+            for mismatch in &case.mismatches {
+                out.push_str(&format!("    {}:\n      expected {}\n      actual   {}\n", mismatch.what, mismatch.expected, mismatch.actual));
+            }
+        }
+        out.push_str(&format!("{}: {} passed, {} failed\n", self.source, self.passed(), self.failed()));
+        out
+    }
+}
+
+/// Runs every case of `file` against `program`, each on a fresh machine.
+/// `symbols` gives the labels memory locations may use, e.g. from
+/// `Assembly::symbols`.
+pub fn run_tests(file: &TestFile, program: &ObjectProgram, symbols: &HashMap<String, usize>) -> TestReport {
+    TestReport {
+        source: file.source.clone(),
+        cases: file.cases.iter()
+            .map(|case| run_case(case, program, symbols, case.step_limit.or(file.step_limit).unwrap_or(DEFAULT_STEP_LIMIT)))
+            .collect(),
+    }
+}
+
+fn run_case(case: &TestCase, program: &ObjectProgram, symbols: &HashMap<String, usize>, step_limit: u64) -> CaseResult {
+    let mut result = CaseResult {
+        name: case.name.clone(),
+        passed: false,
+        halt: String::new(),
+        steps: 0,
+        mismatches: Vec::new(),
+        error: None,
+    };
+    let mut debugger = match prepare(case, program, symbols) {
+        Ok(debugger) => debugger,
+        Err(error) => {
+            result.error = Some(error);
+            return result;
+        }
+    };
+
+    let halt = debugger.machine.run(step_limit);
+    result.steps = debugger.machine.steps;
+    result.halt = match &halt {
+        Halt::Fault { message, .. } => format!("{}: {}", halt, message),
+        _ => halt.to_string(),
+    };
+    if let Err(error) = check(&case.expect, &debugger, &halt, &mut result.mismatches) {
+        result.error = Some(error);
+        return result;
+    }
+    result.passed = result.mismatches.is_empty();
+    result
+}
+
+// a machine with the program loaded and the case's starting state
+fn prepare(case: &TestCase, program: &ObjectProgram, symbols: &HashMap<String, usize>) -> Result<Debugger, String> {
+    let mut machine = Machine::new();
+    for (device, text) in &case.inputs {
+        machine.devices.attach(device_number(device)?, Device::buffer(text.as_bytes()));
+    }
+    machine.load(program)?;
+    let mut debugger = Debugger::new(machine);
+    debugger.symbols = symbols.clone();
+
+    for (name, value) in &case.registers {
+        let register = register_number(name)?;
+        if register == 6 {
+            debugger.machine.registers.f = float_value(name, value)?;
+        } else {
+            debugger.machine.registers.set(register, integer_value(name, value)?)?;
+        }
+    }
+    for (location, bytes) in &case.memory {
+        let address = debugger.resolve(location)?;
+        debugger.machine.write(address, &hex_bytes(location, bytes)?)?;
+    }
+    Ok(debugger)
+}
+
+fn check(expect: &Expectation, debugger: &Debugger, halt: &Halt, mismatches: &mut Vec<Mismatch>) -> Result<(), String> {
+    let machine = &debugger.machine;
+    let actual_halt = halt_kind(halt);
+    match &expect.halt {
+        Some(expected) => {
+            if !HALT_KINDS.contains(&expected.as_str()) {
+                return Err(format!("Unknown halt '{}': expected one of {}", expected, HALT_KINDS.join(", ")));
+            }
+            if expected != actual_halt {
+                mismatches.push(Mismatch { what: "halt".to_string(), expected: expected.clone(), actual: actual_halt.to_string() });
+            }
+        }
+        None if matches!(halt, Halt::StepLimit | Halt::Fault { .. }) => {
+            mismatches.push(Mismatch {
+                what: "halt".to_string(),
+                expected: "returned, loop or svc".to_string(),
+                actual: actual_halt.to_string(),
+            });
+        }
+        None => {}
+    }
+
+    for (name, value) in &expect.registers {
+        let register = register_number(name)?;
+        let (expected, actual) = if register == 6 {
+            let expected = float_value(name, value)?;
+            if expected.to_bits() == machine.registers.f.to_bits() {
+                continue;
+            }
+            (expected.to_string(), machine.registers.f.to_string())
+        } else {
+            let (expected, actual) = (integer_value(name, value)?, machine.registers.get(register)?);
+            if expected == actual {
+                continue;
+            }
+            (word_text(expected), word_text(actual))
+        };
+        mismatches.push(Mismatch { what: name.to_uppercase(), expected, actual });
+    }
+
+    for (location, bytes) in &expect.memory {
+        let address = debugger.resolve(location)?;
+        let expected = hex_bytes(location, bytes)?;
+        let actual = machine.read(address, expected.len())?;
+        if expected != actual {
+            mismatches.push(Mismatch { what: format!("memory {}", location), expected: hex(&expected), actual: hex(&actual) });
+        }
+    }
+
+    for (device, text) in &expect.output {
+        let number = device_number(device)?;
+        let actual = machine.devices.get(number).map(|device| device.output()).unwrap_or_default();
+        if text.as_bytes() != actual {
+            let actual = String::from_utf8_lossy(actual);
+            let differs = text.chars().zip(actual.chars()).take_while(|(a, b)| a == b).count();
+            mismatches.push(Mismatch {
+                what: format!("device {:02X} (differs from character {})", number, differs),
+                expected: format!("{:?}", text),
+                actual: format!("{:?}", actual),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Names `Expectation::halt` accepts.
+const HALT_KINDS: [&str; 5] = ["returned", "loop", "svc", "step_limit", "fault"];
+
+fn halt_kind(halt: &Halt) -> &'static str {
+    match halt {
+        Halt::Returned => "returned",
+        Halt::Loop { .. } => "loop",
+        Halt::SupervisorCall { .. } => "svc",
+        Halt::StepLimit => "step_limit",
+        Halt::Fault { .. } => "fault",
+    }
+}
+
+fn register_number(name: &str) -> Result<u8, String> {
+    let number = get_register_value(name);
+    match get_register_name(number) {
+        Some(register) if register.eq_ignore_ascii_case(name) => Ok(number),
+        _ => Err(format!("Unknown register '{}'", name)),
+    }
+}
+
+fn integer_value(name: &str, value: &RegisterValue) -> Result<u32, String> {
+    match value {
+        RegisterValue::Number(number) if number.fract() == 0.0 && number.abs() <= 0xFFFFFF as f64 => {
+            Ok((*number as i64 & 0xFFFFFF) as u32)
+        }
+        RegisterValue::Number(number) => Err(format!("{}: {} is not a 24-bit integer", name, number)),
+        RegisterValue::Hex(text) => u32::from_str_radix(text, 16)
+            .ok()
+            .filter(|value| *value <= 0xFFFFFF)
+            .ok_or_else(|| format!("{}: '{}' is not a 24-bit hex value", name, text)),
+    }
+}
+
+fn float_value(name: &str, value: &RegisterValue) -> Result<f64, String> {
+    match value {
+        RegisterValue::Number(number) => Ok(*number),
+        RegisterValue::Hex(text) => Err(format!("{}: expected a number, not '{}'", name, text)),
+    }
+}
+
+fn hex_bytes(location: &str, text: &str) -> Result<Vec<u8>, String> {
+    let digits: String = text.chars().filter(|c| !c.is_whitespace()).collect();
+    if digits.is_empty() || !digits.len().is_multiple_of(2) || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("{}: '{}' is not a whole number of hex bytes", location, text));
+    }
+    Ok((0..digits.len()).step_by(2).map(|i| u8::from_str_radix(&digits[i..i + 2], 16).unwrap_or(0)).collect())
+}
+
+fn device_number(device: &str) -> Result<u8, String> {
+    u8::from_str_radix(device, 16).map_err(|_| format!("Invalid device number '{}': expected two hex digits", device))
+}
+
+// a word in hex and as a signed number
+fn word_text(value: u32) -> String {
+    let signed = ((value << 8) as i32) >> 8;
+    format!("{:06X} ({})", value, signed)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02X}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::{assemble_file, AssemblyOptions};
+    use std::fs;
+
+    #[test]
+    fn parse_rejects_unknown_fields() {
+        assert!(TestFile::parse(r#"{ "source": "a.asm", "cases": [] }"#).is_ok());
+        assert!(TestFile::parse(r#"{ "source": "a.asm", "cases": [], "extra": 1 }"#).is_err());
+        assert!(TestFile::parse(r#"{ "source": "a.asm", "cases": [{ "name": "x", "expect": { "halted": "loop" } }] }"#).is_err());
+    }
+
+    #[test]
+    fn integer_values_are_24_bits() {
+        assert_eq!(integer_value("A", &RegisterValue::Number(-1.0)), Ok(0xFFFFFF));
+        assert_eq!(integer_value("A", &RegisterValue::Number(42.0)), Ok(42));
+        assert_eq!(integer_value("A", &RegisterValue::Hex("ffffff".to_string())), Ok(0xFFFFFF));
+        assert!(integer_value("A", &RegisterValue::Number(16777216.0)).is_err());
+        assert!(integer_value("A", &RegisterValue::Number(-16777216.0)).is_err());
+        assert!(integer_value("A", &RegisterValue::Number(1.5)).is_err());
+        assert!(integer_value("A", &RegisterValue::Hex("1000000".to_string())).is_err());
+        assert!(integer_value("A", &RegisterValue::Hex("XYZ".to_string())).is_err());
+    }
+
+    #[test]
+    fn hex_bytes_need_whole_bytes() {
+        assert_eq!(hex_bytes("M", "00 0A ff"), Ok(vec![0x00, 0x0A, 0xFF]));
+        assert!(hex_bytes("M", "").is_err());
+        assert!(hex_bytes("M", "ABC").is_err());
+        assert!(hex_bytes("M", "0G").is_err());
+    }
+
+    #[test]
+    fn halt_defaults_to_any_normal_stop() {
+        let debugger = Debugger::new(Machine::new());
+        let halts = |halt: Halt| {
+            let mut mismatches = Vec::new();
+            check(&Expectation::default(), &debugger, &halt, &mut mismatches).unwrap();
+            mismatches.len()
+        };
+        assert_eq!(halts(Halt::Returned), 0);
+        assert_eq!(halts(Halt::Loop { address: 0 }), 0);
+        assert_eq!(halts(Halt::StepLimit), 1);
+        assert_eq!(halts(Halt::Fault { address: 0, message: String::new() }), 1);
+
+        let expect = Expectation { halt: Some("stopped".to_string()), ..Expectation::default() };
+        assert!(check(&expect, &debugger, &Halt::Returned, &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn runs_cases_against_an_assembled_program() {
+        let dir = std::env::temp_dir().join(format!("sicxe-testing-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("double.asm");
+        fs::write(&source, "DOUBLE  START  0\nFIRST   LDA    VALUE\n        ADD    VALUE\n        STA    RESULT\n        RSUB\nVALUE   WORD   5\nRESULT  RESW   1\n        END    FIRST\n").unwrap();
+        let assembly = assemble_file(&source.to_string_lossy(), &dir.to_string_lossy(), &AssemblyOptions::default()).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let program = ObjectProgram::parse(&assembly.object_program).unwrap();
+        let symbols: HashMap<String, usize> = assembly.symbols.into_iter().map(|entry| (entry.name, entry.address)).collect();
+        let file = TestFile::parse(r#"{
+            "source": "double.asm",
+            "cases": [
+                { "name": "default", "expect": { "registers": { "A": 10 }, "memory": { "RESULT": "00000A" } } },
+                { "name": "patched", "memory": { "VALUE": "FFFFFF" }, "expect": { "registers": { "A": -2 }, "halt": "returned" } },
+                { "name": "wrong", "expect": { "registers": { "A": 11 }, "memory": { "VALUE+1": "FF" } } },
+                { "name": "unknown label", "memory": { "NOWHERE": "00" } }
+            ]
+        }"#).unwrap();
+        let report = run_tests(&file, &program, &symbols);
+
+        assert_eq!(report.passed(), 2);
+        assert_eq!(report.failed(), 2);
+        assert!(report.cases[0].passed && report.cases[1].passed);
+        assert_eq!(report.cases[0].halt, "returned");
+        let whats: Vec<&str> = report.cases[2].mismatches.iter().map(|mismatch| mismatch.what.as_str()).collect();
+        assert_eq!(whats, ["A", "memory VALUE+1"]);
+        assert_eq!(report.cases[2].mismatches[0].actual, "00000A (10)");
+        assert!(report.cases[3].error.is_some());
+    }
+}